@@ -0,0 +1,26 @@
+use std::fs::File;
+use std::io::Read;
+
+use crate::error::*;
+use crate::types::*;
+
+pub fn load_bin_file(file_path: &str) -> Result<Vec<InstructionValue>, SimulatorError> {
+    let error = |reason: String| SimulatorError::InvalidBinFile {
+        path: file_path.to_string(),
+        reason,
+    };
+    let mut file = File::open(file_path).map_err(|e| error(e.to_string()))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)
+        .map_err(|e| error(e.to_string()))?;
+    if buf.len() % 4 != 0 {
+        return Err(error(format!(
+            "the size of sum of instructions is not a multiple of 4: {}",
+            buf.len()
+        )));
+    }
+    Ok(buf
+        .chunks(4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect())
+}
@@ -0,0 +1,486 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::snapshot::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BranchPredictorKind {
+    /// Predicts every conditional branch not taken.
+    #[default]
+    NotTaken,
+    /// Predicts backward branches taken and forward ones not taken.
+    Btfn,
+    /// Repeats the last outcome of each branch, kept in a pc-indexed table.
+    OneBit,
+    /// A pc-indexed table of 2-bit saturating counters.
+    TwoBit,
+    /// 2-bit saturating counters indexed by the pc xor the global branch history.
+    Gshare,
+}
+
+impl fmt::Display for BranchPredictorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            BranchPredictorKind::NotTaken => "not-taken",
+            BranchPredictorKind::Btfn => "btfn",
+            BranchPredictorKind::OneBit => "one-bit",
+            BranchPredictorKind::TwoBit => "two-bit",
+            BranchPredictorKind::Gshare => "gshare",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BranchPredictorConfig {
+    /// Predictor of the direction of conditional branches.
+    pub kind: BranchPredictorKind,
+    /// Entries of the table of the one-bit, two-bit and gshare predictors; a power of two.
+    pub table_size: usize,
+    /// Number of latest branch outcomes gshare mixes into the index.
+    pub history_len: usize,
+    /// Entries of the branch target buffer predicting `jal`; 0 for none.
+    pub btb_size: usize,
+    /// Entries of the return address stack predicting returns through `ra`; 0 for none.
+    pub ras_size: usize,
+}
+
+impl Default for BranchPredictorConfig {
+    fn default() -> Self {
+        BranchPredictorConfig {
+            kind: BranchPredictorKind::NotTaken,
+            table_size: 1024,
+            history_len: 10,
+            btb_size: 64,
+            ras_size: 8,
+        }
+    }
+}
+
+impl BranchPredictorConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.table_size.is_power_of_two() {
+            return Err(format!(
+                "branch table size must be a power of two (got {})",
+                self.table_size
+            ));
+        }
+        if self.history_len > 32 {
+            return Err(format!(
+                "branch history length must be at most 32 (got {})",
+                self.history_len
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A control transfer instruction as executed, without its prediction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlTransfer {
+    /// A conditional branch, whose target is known once it is decoded.
+    Branch { target: Address, is_taken: bool },
+    /// `jal`, which is a call when it links `ra`.
+    Jump { target: Address, is_call: bool },
+    /// `jalr`, which is a return when it jumps through `ra` without linking.
+    IndirectJump {
+        target: Address,
+        is_call: bool,
+        is_return: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BranchStats {
+    pub branch_count: usize,
+    pub branch_miss_count: usize,
+    pub jump_count: usize,
+    /// `jal`s whose target was not in the branch target buffer.
+    pub btb_miss_count: usize,
+    pub return_count: usize,
+    /// Returns to another address than the top of the return address stack.
+    pub ras_miss_count: usize,
+    /// `jalr`s other than returns, which are never predicted.
+    pub indirect_jump_count: usize,
+}
+
+impl BranchStats {
+    pub fn get_miss_count(&self) -> usize {
+        self.branch_miss_count
+            + self.btb_miss_count
+            + self.ras_miss_count
+            + self.indirect_jump_count
+    }
+
+    /// Share of conditional branches predicted right in percent, 0 when none has run.
+    pub fn get_accuracy(&self) -> f64 {
+        if self.branch_count == 0 {
+            return 0.0;
+        }
+        (self.branch_count - self.branch_miss_count) as f64 / self.branch_count as f64 * 100.0
+    }
+}
+
+trait DirectionPredictor {
+    fn predict(&self, pc: Address, target: Address) -> bool;
+
+    fn update(&mut self, pc: Address, is_taken: bool);
+
+    fn write_snapshot(&self, _writer: &mut SnapshotWriter) {}
+
+    fn read_snapshot(&mut self, _reader: &mut SnapshotReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+struct StaticPredictor {
+    is_btfn: bool,
+}
+
+impl DirectionPredictor for StaticPredictor {
+    fn predict(&self, pc: Address, target: Address) -> bool {
+        self.is_btfn && target <= pc
+    }
+
+    fn update(&mut self, _pc: Address, _is_taken: bool) {}
+}
+
+/// Saturating counters indexed by the pc, xored with the global history when it is kept.
+/// The branch is predicted taken when its counter is in the upper half.
+struct CounterPredictor {
+    counters: Vec<u8>,
+    max_count: u8,
+    history: u32,
+    history_len: usize,
+}
+
+impl CounterPredictor {
+    fn new(table_size: usize, max_count: u8, history_len: usize) -> Self {
+        CounterPredictor {
+            counters: vec![max_count / 2; table_size],
+            max_count,
+            history: 0,
+            history_len,
+        }
+    }
+
+    fn get_index(&self, pc: Address) -> usize {
+        ((pc >> 2) ^ self.history) as usize & (self.counters.len() - 1)
+    }
+}
+
+impl DirectionPredictor for CounterPredictor {
+    fn predict(&self, pc: Address, _target: Address) -> bool {
+        self.counters[self.get_index(pc)] > self.max_count / 2
+    }
+
+    fn update(&mut self, pc: Address, is_taken: bool) {
+        let index = self.get_index(pc);
+        let counter = &mut self.counters[index];
+        if is_taken {
+            *counter = (*counter + 1).min(self.max_count);
+        } else {
+            *counter = counter.saturating_sub(1);
+        }
+        if self.history_len != 0 {
+            let mask = (1u64 << self.history_len) - 1;
+            self.history = ((((self.history as u64) << 1) | is_taken as u64) & mask) as u32;
+        }
+    }
+
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.put_bytes(&self.counters);
+        writer.put_u32(self.history);
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        let counters = reader.get_bytes()?;
+        if counters.len() != self.counters.len() {
+            return Err(format!(
+                "branch table size mismatch (snapshot: {}, current: {})",
+                counters.len(),
+                self.counters.len()
+            ));
+        }
+        self.counters = counters;
+        self.history = reader.get_u32()?;
+        Ok(())
+    }
+}
+
+/// Predicts the control transfers of the core; a misprediction flushes the pipeline.
+pub struct BranchPredictor {
+    config: BranchPredictorConfig,
+    direction: Box<dyn DirectionPredictor>,
+    /// Pcs and targets of `jal`s, direct-mapped by pc.
+    btb: Vec<Option<(Address, Address)>>,
+    /// Return addresses of the open calls, the latest last; the oldest one is lost when it overflows.
+    ras: VecDeque<Address>,
+    stats: BranchStats,
+}
+
+impl BranchPredictor {
+    /// The config is assumed to have passed `BranchPredictorConfig::validate`.
+    pub fn new(config: BranchPredictorConfig) -> Self {
+        let direction: Box<dyn DirectionPredictor> = match config.kind {
+            BranchPredictorKind::NotTaken => Box::new(StaticPredictor { is_btfn: false }),
+            BranchPredictorKind::Btfn => Box::new(StaticPredictor { is_btfn: true }),
+            BranchPredictorKind::OneBit => Box::new(CounterPredictor::new(config.table_size, 1, 0)),
+            BranchPredictorKind::TwoBit => Box::new(CounterPredictor::new(config.table_size, 3, 0)),
+            BranchPredictorKind::Gshare => Box::new(CounterPredictor::new(
+                config.table_size,
+                3,
+                config.history_len,
+            )),
+        };
+        BranchPredictor {
+            config,
+            direction,
+            btb: vec![None; config.btb_size],
+            ras: VecDeque::with_capacity(config.ras_size),
+            stats: BranchStats::default(),
+        }
+    }
+
+    pub fn get_config(&self) -> BranchPredictorConfig {
+        self.config
+    }
+
+    /// Predicts the control transfer at `pc`, learns its outcome and returns whether it was mispredicted.
+    pub fn resolve(&mut self, pc: Address, transfer: &ControlTransfer) -> bool {
+        match *transfer {
+            ControlTransfer::Branch { target, is_taken } => {
+                self.stats.branch_count += 1;
+                let is_miss = self.direction.predict(pc, target) != is_taken;
+                self.direction.update(pc, is_taken);
+                if is_miss {
+                    self.stats.branch_miss_count += 1;
+                }
+                is_miss
+            }
+            ControlTransfer::Jump { target, is_call } => {
+                self.stats.jump_count += 1;
+                let is_miss = if self.btb.is_empty() {
+                    true
+                } else {
+                    let index = (pc >> 2) as usize % self.btb.len();
+                    let is_hit = self.btb[index] == Some((pc, target));
+                    self.btb[index] = Some((pc, target));
+                    !is_hit
+                };
+                if is_miss {
+                    self.stats.btb_miss_count += 1;
+                }
+                if is_call {
+                    self.push_return_address(pc.wrapping_add(4));
+                }
+                is_miss
+            }
+            ControlTransfer::IndirectJump {
+                target,
+                is_call,
+                is_return,
+            } => {
+                let is_miss = if is_return {
+                    self.stats.return_count += 1;
+                    let is_miss = self.ras.pop_back() != Some(target);
+                    if is_miss {
+                        self.stats.ras_miss_count += 1;
+                    }
+                    is_miss
+                } else {
+                    self.stats.indirect_jump_count += 1;
+                    true
+                };
+                if is_call {
+                    self.push_return_address(pc.wrapping_add(4));
+                }
+                is_miss
+            }
+        }
+    }
+
+    fn push_return_address(&mut self, addr: Address) {
+        if self.config.ras_size == 0 {
+            return;
+        }
+        if self.ras.len() == self.config.ras_size {
+            self.ras.pop_front();
+        }
+        self.ras.push_back(addr);
+    }
+
+    pub fn show(&self) {
+        let config = &self.config;
+        match config.kind {
+            BranchPredictorKind::NotTaken | BranchPredictorKind::Btfn => {
+                print!("branch predictor: {}", config.kind)
+            }
+            BranchPredictorKind::OneBit | BranchPredictorKind::TwoBit => {
+                print!(
+                    "branch predictor: {} ({} entries)",
+                    config.kind, config.table_size
+                )
+            }
+            BranchPredictorKind::Gshare => print!(
+                "branch predictor: {} ({} entries, {}-bit history)",
+                config.kind, config.table_size, config.history_len
+            ),
+        }
+        println!(
+            ", {}-entry btb, {}-entry ras",
+            config.btb_size, config.ras_size
+        );
+        let stats = &self.stats;
+        println!("branch count: {}", stats.branch_count);
+        println!("branch mispredict count: {}", stats.branch_miss_count);
+        println!("branch prediction accuracy: {:.5}%", stats.get_accuracy());
+        println!("jump count: {}", stats.jump_count);
+        println!("btb miss count: {}", stats.btb_miss_count);
+        println!("return count: {}", stats.return_count);
+        println!("ras miss count: {}", stats.ras_miss_count);
+        println!("other indirect jump count: {}", stats.indirect_jump_count);
+        println!("total mispredict count: {}", stats.get_miss_count());
+    }
+
+    pub fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.direction.write_snapshot(writer);
+        writer.put_usize(self.btb.len());
+        for entry in self.btb.iter() {
+            writer.put_bool(entry.is_some());
+            if let Some((pc, target)) = entry {
+                writer.put_u32(*pc);
+                writer.put_u32(*target);
+            }
+        }
+        writer.put_usize(self.ras.len());
+        for addr in self.ras.iter() {
+            writer.put_u32(*addr);
+        }
+        let stats = &self.stats;
+        for counter in [
+            stats.branch_count,
+            stats.branch_miss_count,
+            stats.jump_count,
+            stats.btb_miss_count,
+            stats.return_count,
+            stats.ras_miss_count,
+            stats.indirect_jump_count,
+        ] {
+            writer.put_usize(counter);
+        }
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        self.direction.read_snapshot(reader)?;
+        reader.expect_usize("btb size", self.btb.len())?;
+        for entry in self.btb.iter_mut() {
+            *entry = if reader.get_bool()? {
+                Some((reader.get_u32()?, reader.get_u32()?))
+            } else {
+                None
+            };
+        }
+        let ras_len = reader.get_usize()?;
+        if ras_len > self.config.ras_size {
+            return Err(format!(
+                "ras size mismatch (snapshot: {}, current: {})",
+                ras_len, self.config.ras_size
+            ));
+        }
+        self.ras.clear();
+        for _ in 0..ras_len {
+            self.ras.push_back(reader.get_u32()?);
+        }
+        let stats = &mut self.stats;
+        for counter in [
+            &mut stats.branch_count,
+            &mut stats.branch_miss_count,
+            &mut stats.jump_count,
+            &mut stats.btb_miss_count,
+            &mut stats.return_count,
+            &mut stats.ras_miss_count,
+            &mut stats.indirect_jump_count,
+        ] {
+            *counter = reader.get_usize()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a loop branch at 0x20 back to 0x10 taken 3 times out of 4, 32 times over, and returns the mispredictions.
+    fn count_loop_misses(kind: BranchPredictorKind) -> usize {
+        let mut predictor = BranchPredictor::new(BranchPredictorConfig {
+            kind,
+            ..BranchPredictorConfig::default()
+        });
+        for _ in 0..32 {
+            for i in 0..4 {
+                let transfer = ControlTransfer::Branch {
+                    target: 0x10,
+                    is_taken: i != 3,
+                };
+                predictor.resolve(0x20, &transfer);
+            }
+        }
+        predictor.stats.branch_miss_count
+    }
+
+    #[test]
+    fn test_direction_predictors() {
+        assert_eq!(count_loop_misses(BranchPredictorKind::NotTaken), 32 * 3);
+        assert_eq!(count_loop_misses(BranchPredictorKind::Btfn), 32);
+        // misses the exit and the next entry of every loop
+        assert_eq!(count_loop_misses(BranchPredictorKind::OneBit), 32 * 2);
+        // stays taken across the exits once warmed up
+        assert_eq!(count_loop_misses(BranchPredictorKind::TwoBit), 2 + 31);
+        // learns the exits from the history, missing only while it warms up
+        assert_eq!(count_loop_misses(BranchPredictorKind::Gshare), 10);
+    }
+
+    #[test]
+    fn test_btb_and_ras() {
+        let mut predictor = BranchPredictor::new(BranchPredictorConfig {
+            ras_size: 1,
+            ..BranchPredictorConfig::default()
+        });
+        let call = ControlTransfer::Jump {
+            target: 0x100,
+            is_call: true,
+        };
+        let ret = ControlTransfer::IndirectJump {
+            target: 0x14,
+            is_call: false,
+            is_return: true,
+        };
+        assert!(predictor.resolve(0x10, &call));
+        assert!(!predictor.resolve(0x104, &ret));
+        assert!(!predictor.resolve(0x10, &call));
+        // the second call overflows the stack
+        assert!(predictor.resolve(
+            0x200,
+            &ControlTransfer::Jump {
+                target: 0x300,
+                is_call: true,
+            }
+        ));
+        assert!(!predictor.resolve(
+            0x304,
+            &ControlTransfer::IndirectJump {
+                target: 0x204,
+                is_call: false,
+                is_return: true,
+            }
+        ));
+        assert!(predictor.resolve(0x104, &ret));
+        assert_eq!(predictor.stats.get_miss_count(), 3);
+    }
+}
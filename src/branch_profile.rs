@@ -0,0 +1,100 @@
+use fxhash::FxHashMap;
+
+use crate::types::*;
+use crate::utils::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct BranchCounts {
+    execution_count: usize,
+    taken_count: usize,
+    /// Executions going the other way than the previous one.
+    direction_change_count: usize,
+    flush_count: usize,
+    last_is_taken: bool,
+}
+
+/// Outcomes of every conditional branch, to find the ones wasting the most cycles on flushes.
+pub struct BranchProfile {
+    counts: FxHashMap<Address, BranchCounts>,
+    flush_stall: usize,
+}
+
+impl BranchProfile {
+    /// `flush_stall` is the cycles a flush costs.
+    pub fn new(flush_stall: usize) -> Self {
+        BranchProfile {
+            counts: FxHashMap::default(),
+            flush_stall,
+        }
+    }
+
+    pub fn record(&mut self, pc: Address, is_taken: bool, is_flushed: bool) {
+        let counts = self.counts.entry(pc).or_default();
+        if counts.execution_count != 0 && counts.last_is_taken != is_taken {
+            counts.direction_change_count += 1;
+        }
+        counts.execution_count += 1;
+        counts.taken_count += is_taken as usize;
+        counts.flush_count += is_flushed as usize;
+        counts.last_is_taken = is_taken;
+    }
+
+    /// Returns the branches by pc, most flushed first.
+    fn get_sorted_counts(&self) -> Vec<(Address, BranchCounts)> {
+        let mut counts = self
+            .counts
+            .iter()
+            .map(|(pc, counts)| (*pc, *counts))
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| {
+            b.1.flush_count
+                .cmp(&a.1.flush_count)
+                .then(b.1.execution_count.cmp(&a.1.execution_count))
+                .then(a.0.cmp(&b.0))
+        });
+        counts
+    }
+
+    /// `describe_pc` gives the text shown for the instruction at a pc.
+    pub fn show(&self, describe_pc: impl Fn(Address) -> String) {
+        println!("---------- branch stats ----------");
+        print_filled_with_space(&"pc".to_string(), 40);
+        println!(
+            " {:>10} {:>10} {:>10} {:>10} {:>10} {:>12}",
+            "executed", "taken", "taken rate", "changes", "flushes", "wasted"
+        );
+        for (pc, counts) in self.get_sorted_counts() {
+            print_filled_with_space(&describe_pc(pc), 40);
+            println!(
+                " {:>10} {:>10} {:>9.5}% {:>10} {:>10} {:>12}",
+                counts.execution_count,
+                counts.taken_count,
+                counts.taken_count as f64 / counts.execution_count as f64 * 100.0,
+                counts.direction_change_count,
+                counts.flush_count,
+                counts.flush_count as u128 * self.flush_stall as u128
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_profile() {
+        let mut profile = BranchProfile::new(3);
+        for is_taken in [true, true, false, true] {
+            profile.record(0x10, is_taken, !is_taken);
+        }
+        profile.record(0x20, false, false);
+        let counts = profile.get_sorted_counts();
+        assert_eq!(counts[0].0, 0x10);
+        assert_eq!(counts[0].1.execution_count, 4);
+        assert_eq!(counts[0].1.taken_count, 3);
+        assert_eq!(counts[0].1.direction_change_count, 2);
+        assert_eq!(counts[0].1.flush_count, 1);
+        assert_eq!(counts[1].0, 0x20);
+    }
+}
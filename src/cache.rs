@@ -1,25 +1,145 @@
-use crate::{memory::WORD_SIZE, types::*, utils::*};
-use linked_hash_map::LinkedHashMap;
+use crate::{memory::WORD_SIZE, replacement_policy::*, snapshot::*, types::*, utils::*};
+use clap::ValueEnum;
+use serde::Deserialize;
 
-type CacheValue = [MemoryValue; LINE_SIZE / WORD_SIZE];
+type CacheValue = Vec<MemoryValue>;
 
-const CACHE_SIZE: usize = 16 * 1024;
-const WAY_NUM: usize = 1;
-pub const LINE_SIZE: usize = 16;
-const TOTAL_LINE_NUM: usize = CACHE_SIZE / LINE_SIZE;
-const LINE_NUM: usize = TOTAL_LINE_NUM / WAY_NUM;
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum WritePolicy {
+    /// Stores only mark the line dirty; it is written to memory when evicted.
+    #[default]
+    WriteBack,
+    /// Every store is also written to memory, so lines are never dirty.
+    WriteThrough,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum WriteMissPolicy {
+    /// A store miss fetches the line into the cache.
+    #[default]
+    Allocate,
+    /// A store miss is written to memory without touching the cache.
+    NoAllocate,
+}
+
+/// Geometry of the data cache; the defaults are those of the current hardware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Total capacity in bytes.
+    pub size: usize,
+    pub way_num: usize,
+    /// Line size in bytes.
+    pub line_size: usize,
+    pub policy: ReplacementPolicyKind,
+    /// Seed of the random replacement policy.
+    pub seed: u64,
+    pub write_policy: WritePolicy,
+    pub write_miss_policy: WriteMissPolicy,
+    /// Number of entries of the write buffer between the cache and memory (0 for none).
+    pub write_buffer_size: usize,
+    /// Cycles added to every access that reaches this cache.
+    pub latency: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            size: 16 * 1024,
+            way_num: 1,
+            line_size: 16,
+            policy: ReplacementPolicyKind::Lru,
+            seed: 0,
+            write_policy: WritePolicy::WriteBack,
+            write_miss_policy: WriteMissPolicy::Allocate,
+            write_buffer_size: 0,
+            latency: 0,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.size.is_power_of_two() {
+            return Err(format!("cache size {} is not a power of two", self.size));
+        }
+        if self.way_num == 0 {
+            return Err("cache way num must be positive".to_string());
+        }
+        if !self.line_size.is_power_of_two() || self.line_size < WORD_SIZE {
+            return Err(format!(
+                "cache line size {} is not a power of two of at least {}",
+                self.line_size, WORD_SIZE
+            ));
+        }
+        let total_line_num = self.size / self.line_size;
+        if total_line_num == 0
+            || !total_line_num.is_multiple_of(self.way_num)
+            || !(total_line_num / self.way_num).is_power_of_two()
+        {
+            return Err(format!(
+                "{} bytes of cache cannot be split into {} ways of {}-byte lines",
+                self.size, self.way_num, self.line_size
+            ));
+        }
+        if self.policy == ReplacementPolicyKind::Plru && !self.way_num.is_power_of_two() {
+            return Err(format!(
+                "plru replacement needs a power-of-two way num, not {}",
+                self.way_num
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn get_line_num(&self) -> usize {
+        self.size / self.line_size / self.way_num
+    }
+
+    pub fn get_words_per_line(&self) -> usize {
+        self.line_size / WORD_SIZE
+    }
+
+    /// Whether a store goes to memory right away, either written through or around the cache on a miss.
+    pub fn writes_to_memory(&self, is_hit: bool) -> bool {
+        self.write_policy == WritePolicy::WriteThrough
+            || (!is_hit && self.write_miss_policy == WriteMissPolicy::NoAllocate)
+    }
+
+    pub fn allocates_on_store_miss(&self) -> bool {
+        self.write_miss_policy == WriteMissPolicy::Allocate
+    }
+}
+
+/// Line replaced by `Cache::set_line`, with its address and words.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Eviction {
+    None,
+    Clean {
+        addr: Address,
+        line: Vec<MemoryValue>,
+    },
+    Dirty {
+        addr: Address,
+        line: Vec<MemoryValue>,
+    },
+}
 
 #[derive(Debug, Clone)]
 pub struct CacheLine {
     valid: bool,
     dirty: bool,
-    accessed: bool,
+    pub(crate) accessed: bool,
     tag: Tag,
     value: CacheValue,
 }
 
 pub struct Cache {
-    values: [LinkedHashMap<Tag, CacheLine>; LINE_NUM],
+    /// `way_num` lines per set.
+    values: Vec<Vec<CacheLine>>,
+    policy: Box<dyn ReplacementPolicy>,
+    config: CacheConfig,
     way_num: usize,
     tag_bit_num: usize,
     index_bit_num: usize,
@@ -35,33 +155,27 @@ pub enum CacheAccess {
 }
 
 impl Cache {
-    pub fn new() -> Self {
-        let mut values = vec![];
-        for _ in 0..LINE_NUM {
-            let mut map = LinkedHashMap::with_capacity(WAY_NUM);
-            for _ in 0..WAY_NUM {
-                map.insert(
-                    std::u32::MAX,
-                    CacheLine {
-                        valid: false,
-                        dirty: false,
-                        accessed: false,
-                        tag: std::u32::MAX,
-                        value: [0; LINE_SIZE / WORD_SIZE],
-                    },
-                );
-            }
-            values.push(map);
-        }
-        let values: [LinkedHashMap<Tag, CacheLine>; LINE_NUM] = values.try_into().unwrap();
-        let way_num = WAY_NUM;
-        let line_size = LINE_SIZE;
-        let line_num = LINE_NUM;
+    /// `config` is assumed to have passed `CacheConfig::validate`.
+    pub fn new(config: CacheConfig) -> Self {
+        let way_num = config.way_num;
+        let line_size = config.line_size;
+        let line_num = config.get_line_num();
+        let empty_line = CacheLine {
+            valid: false,
+            dirty: false,
+            accessed: false,
+            tag: u32::MAX,
+            value: vec![0; config.get_words_per_line()],
+        };
+        let values = vec![vec![empty_line; way_num]; line_num];
+        let policy = create_replacement_policy(config.policy, line_num, way_num, config.seed);
         let index_bit_num = (line_num as u32).trailing_zeros() as usize;
         let offset_bit_num = (line_size as u32).trailing_zeros() as usize;
         let tag_bit_num = 32 - index_bit_num - offset_bit_num;
         Cache {
             values,
+            policy,
+            config,
             way_num,
             tag_bit_num,
             index_bit_num,
@@ -69,8 +183,17 @@ impl Cache {
         }
     }
 
-    pub fn get_offset_bit_num(&self) -> usize {
-        self.offset_bit_num
+    pub fn get_config(&self) -> CacheConfig {
+        self.config
+    }
+
+    pub fn get_line_addr(&self, addr: Address) -> Address {
+        addr & !((1 << self.offset_bit_num) - 1)
+    }
+
+    fn get_line_addr_of(&self, tag: Tag, index: CacheIndex) -> Address {
+        (tag << (self.index_bit_num + self.offset_bit_num)) as Address
+            + (index << self.offset_bit_num) as Address
     }
 
     fn get_tag(&self, addr: Address) -> Tag {
@@ -78,6 +201,9 @@ impl Cache {
     }
 
     fn get_index(&self, addr: Address) -> CacheIndex {
+        if self.index_bit_num == 0 {
+            return 0;
+        }
         ((addr << self.tag_bit_num) >> (32 - self.index_bit_num)) as CacheIndex
     }
 
@@ -92,13 +218,19 @@ impl Cache {
         (tag, index, offset)
     }
 
+    fn find_way(&self, index: CacheIndex, tag: Tag) -> Option<usize> {
+        self.values[index]
+            .iter()
+            .position(|cache_line| cache_line.valid && cache_line.tag == tag)
+    }
+
     fn update_on_get(cache_line: &mut CacheLine) {
         cache_line.accessed = true;
         cache_line.valid = true;
     }
 
-    fn update_on_set(cache_line: &mut CacheLine) {
-        cache_line.dirty = true;
+    fn update_on_set(cache_line: &mut CacheLine, is_write_back: bool) {
+        cache_line.dirty |= is_write_back;
         cache_line.accessed = true;
         cache_line.valid = true;
     }
@@ -142,12 +274,10 @@ impl Cache {
 
     pub fn get_word(&mut self, addr: Address) -> CacheAccess {
         let (tag, index, offset) = self.get_status(addr);
-        let cache_line = self.values[index].get_refresh(&tag);
-        match cache_line {
-            Some(cache_line) => {
-                if !cache_line.valid {
-                    return CacheAccess::Miss;
-                }
+        match self.find_way(index, tag) {
+            Some(way) => {
+                self.policy.on_hit(index, way);
+                let cache_line = &mut self.values[index][way];
                 // let mut value: u32 = 0;
                 // for i in 0..4 {
                 //     value += (cache_line.value[offset + i] as u32) << (8 * i);
@@ -160,47 +290,151 @@ impl Cache {
         }
     }
 
-    pub fn set_line(
-        &mut self,
-        addr: Address,
-        line: [MemoryValue; LINE_SIZE / WORD_SIZE],
-    ) -> Option<[(Address, MemoryValue); LINE_SIZE / WORD_SIZE]> {
-        let tag = self.get_tag(addr);
-        let index = self.get_index(addr);
-        let cache_line_candidates = &self.values[index];
-        let cache_line = cache_line_candidates.get(&tag);
-        assert!(cache_line.is_none());
-
-        let mut dirty_line_evicted = false;
-        let mut evicted_values = [(0, 0); LINE_SIZE / WORD_SIZE];
-        if self.values[index].len() >= self.way_num {
-            let candidate_for_eviction = self.values[index].pop_front();
-            if let Some((_, cache_line)) = candidate_for_eviction {
-                if cache_line.dirty {
-                    dirty_line_evicted = true;
-                    let addr = (cache_line.tag << (self.index_bit_num + self.offset_bit_num))
-                        as Address
-                        + (index << self.offset_bit_num) as Address;
-                    for (i, value) in evicted_values.iter_mut().enumerate() {
-                        *value = (addr + i as Address * 4, cache_line.value[i]);
-                    }
+    pub fn peek_word(&self, addr: Address) -> Option<Word> {
+        let (tag, index, offset) = self.get_status(addr);
+        self.find_way(index, tag)
+            .map(|way| u32_to_i32(self.values[index][way].value[offset >> 2]))
+    }
+
+    /// Saves every line followed by the state of the replacement policy.
+    pub fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.put_usize(self.config.get_line_num());
+        writer.put_usize(self.way_num);
+        writer.put_usize(self.config.line_size);
+        writer.put_bytes(self.config.policy.to_string().as_bytes());
+        writer.put_bool(self.config.write_policy == WritePolicy::WriteBack);
+        for set in self.values.iter() {
+            for cache_line in set.iter() {
+                writer.put_bool(cache_line.valid);
+                writer.put_bool(cache_line.dirty);
+                writer.put_bool(cache_line.accessed);
+                writer.put_u32(cache_line.tag);
+                for value in cache_line.value.iter() {
+                    writer.put_u32(*value);
+                }
+            }
+        }
+        self.policy.write_snapshot(writer);
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        reader.expect_usize("cache line num", self.config.get_line_num())?;
+        reader.expect_usize("cache way num", self.way_num)?;
+        reader.expect_usize("cache line size", self.config.line_size)?;
+        let policy = String::from_utf8_lossy(&reader.get_bytes()?).to_string();
+        if policy != self.config.policy.to_string() {
+            return Err(format!(
+                "replacement policy mismatch (snapshot: {}, current: {})",
+                policy, self.config.policy
+            ));
+        }
+        if reader.get_bool()? != (self.config.write_policy == WritePolicy::WriteBack) {
+            return Err("the snapshot was taken with another write policy".to_string());
+        }
+        for set in self.values.iter_mut() {
+            for cache_line in set.iter_mut() {
+                cache_line.valid = reader.get_bool()?;
+                cache_line.dirty = reader.get_bool()?;
+                cache_line.accessed = reader.get_bool()?;
+                cache_line.tag = reader.get_u32()?;
+                for value in cache_line.value.iter_mut() {
+                    *value = reader.get_u32()?;
                 }
             }
         }
-        let mut cache_line = CacheLine {
+        self.policy.read_snapshot(reader)
+    }
+
+    /// Overwrites a word if its line is cached, without refreshing the line or marking it dirty.
+    pub fn poke_word(&mut self, addr: Address, value: Word) {
+        let (tag, index, offset) = self.get_status(addr);
+        if let Some(way) = self.find_way(index, tag) {
+            self.values[index][way].value[offset >> 2] = i32_to_u32(value);
+        }
+    }
+
+    /// Reads a whole line on a hit, refreshing it as `get_word` does.
+    pub fn get_line(&mut self, addr: Address) -> Option<Vec<MemoryValue>> {
+        let (tag, index, _) = self.get_status(addr);
+        let way = self.find_way(index, tag)?;
+        self.policy.on_hit(index, way);
+        let cache_line = &mut self.values[index][way];
+        Self::update_on_get(cache_line);
+        Some(cache_line.value.clone())
+    }
+
+    /// Invalidates a line and returns its words and whether it was dirty.
+    pub fn take_line(&mut self, addr: Address) -> Option<(Vec<MemoryValue>, bool)> {
+        let (tag, index, _) = self.get_status(addr);
+        let way = self.find_way(index, tag)?;
+        let cache_line = &mut self.values[index][way];
+        cache_line.valid = false;
+        Some((cache_line.value.clone(), cache_line.dirty))
+    }
+
+    /// Writes a word from the level above if its line is cached, marking the line dirty.
+    pub fn update_word(&mut self, addr: Address, value: Word) -> bool {
+        let (tag, index, offset) = self.get_status(addr);
+        match self.find_way(index, tag) {
+            Some(way) => {
+                let cache_line = &mut self.values[index][way];
+                cache_line.value[offset >> 2] = i32_to_u32(value);
+                cache_line.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Writes a line from the level above if it is cached, marking it dirty.
+    pub fn update_line(&mut self, addr: Address, line: &[MemoryValue]) -> bool {
+        let (tag, index, _) = self.get_status(addr);
+        match self.find_way(index, tag) {
+            Some(way) => {
+                let cache_line = &mut self.values[index][way];
+                cache_line.value.copy_from_slice(line);
+                cache_line.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_line(&mut self, addr: Address, line: Vec<MemoryValue>, dirty: bool) -> Eviction {
+        let tag = self.get_tag(addr);
+        let index = self.get_index(addr);
+        assert!(self.find_way(index, tag).is_none());
+
+        let way = match self.values[index]
+            .iter()
+            .position(|cache_line| !cache_line.valid)
+        {
+            Some(way) => way,
+            None => self.policy.choose_victim(index, &mut self.values[index]),
+        };
+        let cache_line = CacheLine {
             valid: true,
-            dirty: false,
+            dirty,
             accessed: true,
             tag,
-            value: [0; LINE_SIZE / WORD_SIZE],
+            value: line,
         };
-        cache_line.value[..LINE_SIZE / WORD_SIZE].copy_from_slice(&line[..LINE_SIZE / WORD_SIZE]);
-        self.values[index].insert(tag, cache_line);
+        let evicted_line = std::mem::replace(&mut self.values[index][way], cache_line);
+        self.policy.on_fill(index, way);
 
-        if dirty_line_evicted {
-            Some(evicted_values)
+        let addr = self.get_line_addr_of(evicted_line.tag, index);
+        if !evicted_line.valid {
+            Eviction::None
+        } else if evicted_line.dirty {
+            Eviction::Dirty {
+                addr,
+                line: evicted_line.value,
+            }
         } else {
-            None
+            Eviction::Clean {
+                addr,
+                line: evicted_line.value,
+            }
         }
     }
 
@@ -242,22 +476,80 @@ impl Cache {
 
     pub fn set_word(&mut self, addr: Address, value: Word) -> CacheAccess {
         let (tag, index, offset) = self.get_status(addr);
-        let cache_line = self.values[index].get_refresh(&tag);
-        match cache_line {
-            Some(cache_line) => {
+        let is_write_back = self.config.write_policy == WritePolicy::WriteBack;
+        match self.find_way(index, tag) {
+            Some(way) => {
+                self.policy.on_hit(index, way);
+                let cache_line = &mut self.values[index][way];
                 let value = i32_to_u32(value);
-                if !cache_line.valid {
-                    return CacheAccess::Miss;
-                }
                 // for i in 0..4 {
                 //     cache_line.value[offset + i] = ((value >> (i * 8)) & 0xff) as UByte;
                 // }
                 cache_line.value[offset >> 2] = value;
 
-                Self::update_on_set(cache_line);
+                Self::update_on_set(cache_line, is_write_back);
                 CacheAccess::HitSet
             }
             None => CacheAccess::Miss,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_config_validate() {
+        assert!(CacheConfig::default().validate().is_ok());
+        assert!(CacheConfig {
+            line_size: 24,
+            ..CacheConfig::default()
+        }
+        .validate()
+        .is_err());
+        assert!(CacheConfig {
+            way_num: 3,
+            ..CacheConfig::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_nru_replacement() {
+        let mut cache = Cache::new(CacheConfig {
+            size: 32,
+            way_num: 2,
+            line_size: 16,
+            policy: ReplacementPolicyKind::Nru,
+            ..CacheConfig::default()
+        });
+        assert_eq!(cache.set_line(0x00, vec![1; 4], false), Eviction::None);
+        assert_eq!(cache.set_line(0x10, vec![2; 4], false), Eviction::None);
+        // every line has been accessed, so the bits are cleared and way 0 is evicted
+        assert_eq!(
+            cache.set_line(0x20, vec![3; 4], false),
+            Eviction::Clean {
+                addr: 0x00,
+                line: vec![1; 4]
+            }
+        );
+        assert_eq!(cache.peek_word(0x00), None);
+        // 0x10 is now the only line not accessed since the bits were cleared
+        assert!(matches!(cache.set_word(0x24, 4), CacheAccess::HitSet));
+        assert!(matches!(
+            cache.set_line(0x30, vec![5; 4], false),
+            Eviction::Clean { addr: 0x10, .. }
+        ));
+        assert_eq!(cache.peek_word(0x10), None);
+        assert_eq!(cache.peek_word(0x24), Some(4));
+        assert_eq!(
+            cache.set_line(0x40, vec![6; 4], false),
+            Eviction::Dirty {
+                addr: 0x20,
+                line: vec![3, 4, 3, 3]
+            }
+        );
+    }
+}
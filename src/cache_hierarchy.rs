@@ -0,0 +1,884 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::cache::*;
+use crate::memory::*;
+use crate::replacement_policy::*;
+use crate::snapshot::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::write_buffer::*;
+
+/// Cycles the core stalls for each memory event in the predicted cycle count.
+/// With the defaults only load misses are charged, as in the model calibrated against the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StallConfig {
+    /// Charged for loads that read their line from memory.
+    pub load_miss: usize,
+    /// Charged for store misses that fetch the line from memory (see `WriteMissPolicy`).
+    pub store_miss: usize,
+    /// Charged for writing a dirty line back when there is no write buffer.
+    pub write_back: usize,
+    /// Charged for clean lines evicted from the last cache level.
+    pub clean_eviction: usize,
+    /// Time taken by memory for a single write, which the core waits for when there is no write buffer.
+    pub memory_write: usize,
+}
+
+impl Default for StallConfig {
+    fn default() -> Self {
+        StallConfig {
+            load_miss: 108 * 120,
+            store_miss: 0,
+            write_back: 0,
+            clean_eviction: 0,
+            memory_write: 108 * 120,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Inclusion {
+    /// Lines are filled into both levels and evicted from each independently.
+    #[default]
+    NonInclusive,
+    /// Every L1 line is also in L2; lines evicted from L2 are invalidated in L1.
+    Inclusive,
+    /// A line is in at most one level; L2 only holds lines evicted from L1.
+    Exclusive,
+}
+
+impl fmt::Display for Inclusion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Inclusion::NonInclusive => "non-inclusive",
+            Inclusion::Inclusive => "inclusive",
+            Inclusion::Exclusive => "exclusive",
+        })
+    }
+}
+
+/// Geometry of the second-level cache, which shares the line size of L1 and is always write-back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct L2Config {
+    /// Total capacity in bytes.
+    pub size: usize,
+    pub way_num: usize,
+    pub policy: ReplacementPolicyKind,
+    /// Seed of the random replacement policy.
+    pub seed: u64,
+    /// Cycles added to every access that reaches L2.
+    pub latency: usize,
+    pub inclusion: Inclusion,
+}
+
+impl Default for L2Config {
+    fn default() -> Self {
+        L2Config {
+            size: 128 * 1024,
+            way_num: 4,
+            policy: ReplacementPolicyKind::Lru,
+            seed: 0,
+            latency: 10,
+            inclusion: Inclusion::NonInclusive,
+        }
+    }
+}
+
+impl L2Config {
+    pub fn get_cache_config(&self, l1_config: &CacheConfig) -> CacheConfig {
+        CacheConfig {
+            size: self.size,
+            way_num: self.way_num,
+            line_size: l1_config.line_size,
+            policy: self.policy,
+            seed: self.seed,
+            latency: self.latency,
+            ..CacheConfig::default()
+        }
+    }
+
+    pub fn validate(&self, l1_config: &CacheConfig) -> Result<(), String> {
+        self.get_cache_config(l1_config)
+            .validate()
+            .map_err(|reason| format!("l2 {}", reason))?;
+        if self.inclusion == Inclusion::Inclusive && self.size < l1_config.size {
+            return Err(format!(
+                "inclusive l2 of {} bytes is smaller than l1 of {} bytes",
+                self.size, l1_config.size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Hit and miss counts of a single cache level; misses are split by the kind of access from the core.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub memory_access_count: usize,
+    pub cache_hit_count: usize,
+    pub load_cache_miss_count: usize,
+    pub store_cache_miss_count: usize,
+    pub write_back_count: usize,
+    pub clean_eviction_count: usize,
+}
+
+impl CacheStats {
+    /// Hit rate in percent, 0 for a cache that has not been accessed.
+    pub fn get_hit_rate(&self) -> f64 {
+        if self.memory_access_count == 0 {
+            return 0.0;
+        }
+        self.cache_hit_count as f64 / self.memory_access_count as f64 * 100.0
+    }
+
+    fn count_miss(&mut self, is_store: bool) {
+        if is_store {
+            self.store_cache_miss_count += 1;
+        } else {
+            self.load_cache_miss_count += 1;
+        }
+    }
+
+    /// Records the eviction returned by `Cache::set_line`.
+    fn count_eviction(&mut self, eviction: &Eviction) {
+        match eviction {
+            Eviction::None => {}
+            Eviction::Clean { .. } => self.clean_eviction_count += 1,
+            Eviction::Dirty { .. } => self.write_back_count += 1,
+        }
+    }
+
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        for counter in [
+            self.memory_access_count,
+            self.cache_hit_count,
+            self.load_cache_miss_count,
+            self.store_cache_miss_count,
+            self.write_back_count,
+            self.clean_eviction_count,
+        ] {
+            writer.put_usize(counter);
+        }
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        for counter in [
+            &mut self.memory_access_count,
+            &mut self.cache_hit_count,
+            &mut self.load_cache_miss_count,
+            &mut self.store_cache_miss_count,
+            &mut self.write_back_count,
+            &mut self.clean_eviction_count,
+        ] {
+            *counter = reader.get_usize()?;
+        }
+        Ok(())
+    }
+}
+
+/// Stall cycles charged so far, by cause.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StallCycles {
+    /// Latencies of the cache levels.
+    pub latency: u128,
+    pub load_miss: u128,
+    pub store_miss: u128,
+    pub clean_eviction: u128,
+    /// Cycles spent waiting for memory writes, either for memory itself or for a full write buffer.
+    pub write: u128,
+}
+
+impl StallCycles {
+    pub fn get_total(&self) -> u128 {
+        self.latency + self.load_miss + self.store_miss + self.clean_eviction + self.write
+    }
+
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        for cycle_num in [
+            self.latency,
+            self.load_miss,
+            self.store_miss,
+            self.clean_eviction,
+            self.write,
+        ] {
+            writer.put_u128(cycle_num);
+        }
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        for cycle_num in [
+            &mut self.latency,
+            &mut self.load_miss,
+            &mut self.store_miss,
+            &mut self.clean_eviction,
+            &mut self.write,
+        ] {
+            *cycle_num = reader.get_u128()?;
+        }
+        Ok(())
+    }
+}
+
+/// What brings a line into L1; prefetches are neither charged nor counted as accesses of L2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FillKind {
+    Load,
+    Store,
+    Prefetch,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrefetchStats {
+    pub issue_count: usize,
+    /// Prefetched lines used by the core before being evicted.
+    pub useful_count: usize,
+    /// Useful prefetches used before their line arrived; the rest of the wait is charged as a load miss.
+    pub late_count: usize,
+    /// Prefetched lines evicted without being used.
+    pub useless_count: usize,
+    /// Misses on lines that a prefetch had evicted.
+    pub pollution_miss_count: usize,
+}
+
+impl PrefetchStats {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        for counter in [
+            self.issue_count,
+            self.useful_count,
+            self.late_count,
+            self.useless_count,
+            self.pollution_miss_count,
+        ] {
+            writer.put_usize(counter);
+        }
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        for counter in [
+            &mut self.issue_count,
+            &mut self.useful_count,
+            &mut self.late_count,
+            &mut self.useless_count,
+            &mut self.pollution_miss_count,
+        ] {
+            *counter = reader.get_usize()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_line_set(writer: &mut SnapshotWriter, lines: &HashSet<Address>) {
+    let mut lines = lines.iter().copied().collect::<Vec<_>>();
+    lines.sort();
+    writer.put_usize(lines.len());
+    for line_addr in lines {
+        writer.put_u32(line_addr);
+    }
+}
+
+fn read_line_set(reader: &mut SnapshotReader) -> Result<HashSet<Address>, String> {
+    let mut lines = HashSet::new();
+    for _ in 0..reader.get_usize()? {
+        lines.insert(reader.get_u32()?);
+    }
+    Ok(lines)
+}
+
+/// L1, an optional L2 and the write buffer in front of memory.
+/// Moving lines from L1 into L2 is not charged, as it overlaps with the fill that caused it.
+pub struct CacheHierarchy {
+    l1: Cache,
+    l2: Option<(Cache, L2Config)>,
+    l1_stats: CacheStats,
+    l2_stats: CacheStats,
+    back_invalidation_count: usize,
+    memory_write_count: usize,
+    stall_cycles: StallCycles,
+    stall_config: StallConfig,
+    write_buffer: WriteBuffer,
+    prefetch_stats: PrefetchStats,
+    /// Lines in L1 brought by a prefetch and not used yet, with the cycle they arrive at.
+    prefetched_lines: HashMap<Address, u128>,
+    /// Lines evicted from L1 by a prefetch and not missed yet.
+    prefetch_victims: HashSet<Address>,
+    last_prefetch_hit: bool,
+}
+
+impl CacheHierarchy {
+    /// The configs are assumed to have passed `CacheConfig::validate` and `L2Config::validate`.
+    pub fn new(
+        config: CacheConfig,
+        l2_config: Option<L2Config>,
+        stall_config: StallConfig,
+    ) -> Self {
+        CacheHierarchy {
+            l1: Cache::new(config),
+            l2: l2_config
+                .map(|l2_config| (Cache::new(l2_config.get_cache_config(&config)), l2_config)),
+            l1_stats: CacheStats::default(),
+            l2_stats: CacheStats::default(),
+            back_invalidation_count: 0,
+            memory_write_count: 0,
+            stall_cycles: StallCycles::default(),
+            stall_config,
+            write_buffer: WriteBuffer::new(
+                config.write_buffer_size,
+                stall_config.memory_write as u128,
+            ),
+            prefetch_stats: PrefetchStats::default(),
+            prefetched_lines: HashMap::new(),
+            prefetch_victims: HashSet::new(),
+            last_prefetch_hit: false,
+        }
+    }
+
+    pub fn get_config(&self) -> CacheConfig {
+        self.l1.get_config()
+    }
+
+    pub fn get_l2_config(&self) -> Option<L2Config> {
+        self.l2.as_ref().map(|(_, l2_config)| *l2_config)
+    }
+
+    pub fn get_l1_stats(&self) -> &CacheStats {
+        &self.l1_stats
+    }
+
+    /// Whether the last load or store was the first use of a prefetched line.
+    pub fn was_prefetch_hit(&self) -> bool {
+        self.last_prefetch_hit
+    }
+
+    pub fn get_stall_cycle_num(&self) -> u128 {
+        self.stall_cycles.get_total()
+    }
+
+    /// Counts an access of the core, including those made while the cache is disabled.
+    pub fn count_access(&mut self) {
+        self.l1_stats.memory_access_count += 1;
+    }
+
+    /// Returns the word and whether it hit in L1.
+    /// `base_cycle_num` is the predicted cycle count without memory stalls, used to time the write buffer.
+    pub fn load_word(
+        &mut self,
+        memory: &mut Memory,
+        addr: Address,
+        base_cycle_num: u128,
+    ) -> (Word, bool) {
+        self.stall_cycles.latency += self.l1.get_config().latency as u128;
+        match self.l1.get_word(addr) {
+            CacheAccess::HitWord(value) => {
+                self.l1_stats.cache_hit_count += 1;
+                self.count_prefetch_use(addr, base_cycle_num);
+                (value, true)
+            }
+            CacheAccess::Miss => {
+                self.l1_stats.load_cache_miss_count += 1;
+                self.count_pollution_miss(addr);
+                let line = self.fill_l1(memory, addr, FillKind::Load, base_cycle_num);
+                let offset = (addr - self.l1.get_line_addr(addr)) as usize / WORD_SIZE;
+                (u32_to_i32(line[offset]), false)
+            }
+            _ => unreachable!("get_word only returns HitWord or Miss"),
+        }
+    }
+
+    /// Returns whether the store hit in L1.
+    pub fn store_word(
+        &mut self,
+        memory: &mut Memory,
+        addr: Address,
+        value: Word,
+        base_cycle_num: u128,
+    ) -> bool {
+        let config = self.l1.get_config();
+        self.stall_cycles.latency += config.latency as u128;
+        match self.l1.set_word(addr, value) {
+            CacheAccess::HitSet => {
+                self.l1_stats.cache_hit_count += 1;
+                self.count_prefetch_use(addr, base_cycle_num);
+                if config.writes_to_memory(true) {
+                    self.write_through(memory, addr, value, base_cycle_num);
+                }
+                true
+            }
+            CacheAccess::Miss => {
+                self.l1_stats.store_cache_miss_count += 1;
+                self.count_pollution_miss(addr);
+                if config.writes_to_memory(false) {
+                    self.write_through(memory, addr, value, base_cycle_num);
+                } else {
+                    self.write_below_l1(memory, addr, value);
+                }
+                if config.allocates_on_store_miss() {
+                    self.fill_l1(memory, addr, FillKind::Store, base_cycle_num);
+                }
+                false
+            }
+            _ => unreachable!("set_word only returns HitSet or Miss"),
+        }
+    }
+
+    /// Brings the line of `addr` into L1 ahead of demand, unless it is already there or outside memory.
+    pub fn prefetch_line(&mut self, memory: &mut Memory, addr: Address, base_cycle_num: u128) {
+        let line_addr = self.l1.get_line_addr(addr);
+        if line_addr as usize + self.l1.get_config().line_size > MEMORY_SIZE
+            || self.l1.peek_word(line_addr).is_some()
+        {
+            return;
+        }
+        self.prefetch_stats.issue_count += 1;
+        let delay = match self.l2.as_ref() {
+            Some((l2, l2_config)) if l2.peek_word(line_addr).is_some() => l2_config.latency,
+            _ => self.stall_config.load_miss,
+        };
+        let arrival_cycle_num = base_cycle_num + self.get_stall_cycle_num() + delay as u128;
+        self.fill_l1(memory, line_addr, FillKind::Prefetch, base_cycle_num);
+        self.prefetched_lines.insert(line_addr, arrival_cycle_num);
+        self.prefetch_victims.remove(&line_addr);
+    }
+
+    fn count_prefetch_use(&mut self, addr: Address, base_cycle_num: u128) {
+        let arrival_cycle_num = self.prefetched_lines.remove(&self.l1.get_line_addr(addr));
+        self.last_prefetch_hit = arrival_cycle_num.is_some();
+        if let Some(arrival_cycle_num) = arrival_cycle_num {
+            self.prefetch_stats.useful_count += 1;
+            let now = base_cycle_num + self.get_stall_cycle_num();
+            if arrival_cycle_num > now {
+                self.prefetch_stats.late_count += 1;
+                self.stall_cycles.load_miss += arrival_cycle_num - now;
+            }
+        }
+    }
+
+    fn count_pollution_miss(&mut self, addr: Address) {
+        self.last_prefetch_hit = false;
+        if self.prefetch_victims.remove(&self.l1.get_line_addr(addr)) {
+            self.prefetch_stats.pollution_miss_count += 1;
+        }
+    }
+
+    /// Tracks a line leaving L1 for the prefetch statistics.
+    fn count_l1_removal(&mut self, line_addr: Address, kind: FillKind) {
+        if self.prefetched_lines.remove(&line_addr).is_some() {
+            self.prefetch_stats.useless_count += 1;
+        } else if kind == FillKind::Prefetch {
+            self.prefetch_victims.insert(line_addr);
+        }
+    }
+
+    /// Reads a word without touching cache state or statistics.
+    pub fn peek_word(&self, memory: &Memory, addr: Address) -> Word {
+        if let Some(value) = self.l1.peek_word(addr) {
+            return value;
+        }
+        if let Some(value) = self.l2.as_ref().and_then(|(l2, _)| l2.peek_word(addr)) {
+            return value;
+        }
+        memory.load_word(addr)
+    }
+
+    /// Writes a word to every level holding it and to memory without touching cache state or statistics.
+    pub fn poke_word(&mut self, memory: &mut Memory, addr: Address, value: Word) {
+        self.l1.poke_word(addr, value);
+        if let Some((l2, _)) = self.l2.as_mut() {
+            l2.poke_word(addr, value);
+        }
+        memory.store_word(addr, value);
+    }
+
+    /// Writes a store that missed in L1 into the next level without charging for it.
+    /// Returns whether L2 took the word.
+    fn write_below_l1(&mut self, memory: &mut Memory, addr: Address, value: Word) -> bool {
+        if let Some((l2, _)) = self.l2.as_mut() {
+            if l2.update_word(addr, value) {
+                return true;
+            }
+        }
+        memory.store_word(addr, value);
+        false
+    }
+
+    /// Writes a store through L1, into L2 when it holds the line and otherwise into memory.
+    fn write_through(
+        &mut self,
+        memory: &mut Memory,
+        addr: Address,
+        value: Word,
+        base_cycle_num: u128,
+    ) {
+        if self.write_below_l1(memory, addr, value) {
+            let l2_config = self.get_l2_config().unwrap();
+            self.l2_stats.memory_access_count += 1;
+            self.l2_stats.cache_hit_count += 1;
+            self.stall_cycles.latency += l2_config.latency as u128;
+        } else {
+            self.memory_write_count += 1;
+            self.issue_memory_write(self.stall_config.memory_write, base_cycle_num);
+        }
+    }
+
+    /// Accounts for a write of a store or a dirty line to memory; the data itself is already there.
+    fn issue_memory_write(&mut self, unbuffered_stall: usize, base_cycle_num: u128) {
+        let now = base_cycle_num + self.get_stall_cycle_num();
+        self.stall_cycles.write += self.write_buffer.push(now, unbuffered_stall as u128);
+    }
+
+    fn read_from_memory(
+        &mut self,
+        memory: &Memory,
+        line_addr: Address,
+        kind: FillKind,
+    ) -> Vec<MemoryValue> {
+        match kind {
+            FillKind::Load => self.stall_cycles.load_miss += self.stall_config.load_miss as u128,
+            FillKind::Store => self.stall_cycles.store_miss += self.stall_config.store_miss as u128,
+            FillKind::Prefetch => {}
+        }
+        memory.get_cache_line(line_addr, self.l1.get_config().line_size)
+    }
+
+    /// Brings the line of `addr` into L1 and returns its words.
+    fn fill_l1(
+        &mut self,
+        memory: &mut Memory,
+        addr: Address,
+        kind: FillKind,
+        base_cycle_num: u128,
+    ) -> Vec<MemoryValue> {
+        let line_addr = self.l1.get_line_addr(addr);
+        let (line, dirty) = self.read_below_l1(memory, line_addr, kind, base_cycle_num);
+        let eviction = self.l1.set_line(line_addr, line.clone(), dirty);
+        self.l1_stats.count_eviction(&eviction);
+        if let Eviction::Clean { addr, .. } | Eviction::Dirty { addr, .. } = eviction {
+            self.count_l1_removal(addr, kind);
+        }
+        self.evict_from_l1(memory, eviction, base_cycle_num);
+        line
+    }
+
+    /// Reads a line missing in L1 from L2 or memory, together with whether it has to stay dirty.
+    fn read_below_l1(
+        &mut self,
+        memory: &mut Memory,
+        line_addr: Address,
+        kind: FillKind,
+        base_cycle_num: u128,
+    ) -> (Vec<MemoryValue>, bool) {
+        let Some((l2, l2_config)) = self.l2.as_mut() else {
+            return (self.read_from_memory(memory, line_addr, kind), false);
+        };
+        let l2_config = *l2_config;
+        let is_demand = kind != FillKind::Prefetch;
+        if is_demand {
+            self.l2_stats.memory_access_count += 1;
+            self.stall_cycles.latency += l2_config.latency as u128;
+        }
+        let hit = if l2_config.inclusion == Inclusion::Exclusive {
+            l2.take_line(line_addr)
+        } else {
+            l2.get_line(line_addr).map(|line| (line, false))
+        };
+        if let Some(hit) = hit {
+            if is_demand {
+                self.l2_stats.cache_hit_count += 1;
+            }
+            return hit;
+        }
+        if is_demand {
+            self.l2_stats.count_miss(kind == FillKind::Store);
+        }
+        let line = self.read_from_memory(memory, line_addr, kind);
+        if l2_config.inclusion != Inclusion::Exclusive {
+            let (l2, _) = self.l2.as_mut().unwrap();
+            let eviction = l2.set_line(line_addr, line.clone(), false);
+            self.evict_from_l2(memory, eviction, base_cycle_num);
+        }
+        (line, false)
+    }
+
+    fn evict_from_l1(&mut self, memory: &mut Memory, eviction: Eviction, base_cycle_num: u128) {
+        let Some((l2, l2_config)) = self.l2.as_mut() else {
+            self.evict_to_memory(memory, eviction, base_cycle_num);
+            return;
+        };
+        match (eviction, l2_config.inclusion) {
+            (Eviction::None, _) => {}
+            (Eviction::Clean { addr, line }, Inclusion::Exclusive) => {
+                let eviction = l2.set_line(addr, line, false);
+                self.evict_from_l2(memory, eviction, base_cycle_num);
+            }
+            (Eviction::Dirty { addr, line }, Inclusion::Exclusive) => {
+                let eviction = l2.set_line(addr, line, true);
+                self.evict_from_l2(memory, eviction, base_cycle_num);
+            }
+            (Eviction::Clean { .. }, _) => {}
+            (Eviction::Dirty { addr, line }, _) => {
+                // a non-inclusive L2 may have dropped the line already
+                if !l2.update_line(addr, &line) {
+                    self.evict_to_memory(memory, Eviction::Dirty { addr, line }, base_cycle_num);
+                }
+            }
+        }
+    }
+
+    fn evict_from_l2(&mut self, memory: &mut Memory, eviction: Eviction, base_cycle_num: u128) {
+        let inclusion = self.get_l2_config().unwrap().inclusion;
+        let eviction = match (eviction, inclusion) {
+            (Eviction::Clean { addr, line }, Inclusion::Inclusive) => {
+                self.back_invalidate(addr, line, false)
+            }
+            (Eviction::Dirty { addr, line }, Inclusion::Inclusive) => {
+                self.back_invalidate(addr, line, true)
+            }
+            (eviction, _) => eviction,
+        };
+        self.l2_stats.count_eviction(&eviction);
+        self.evict_to_memory(memory, eviction, base_cycle_num);
+    }
+
+    /// Invalidates the copy of a line leaving an inclusive L2, whose data is newer if it is dirty.
+    fn back_invalidate(&mut self, addr: Address, line: Vec<MemoryValue>, dirty: bool) -> Eviction {
+        match self.l1.take_line(addr) {
+            Some((l1_line, l1_dirty)) => {
+                self.back_invalidation_count += 1;
+                self.count_l1_removal(addr, FillKind::Load);
+                if l1_dirty {
+                    Eviction::Dirty {
+                        addr,
+                        line: l1_line,
+                    }
+                } else if dirty {
+                    Eviction::Dirty { addr, line }
+                } else {
+                    Eviction::Clean { addr, line }
+                }
+            }
+            None if dirty => Eviction::Dirty { addr, line },
+            None => Eviction::Clean { addr, line },
+        }
+    }
+
+    /// Handles a line leaving the last cache level.
+    fn evict_to_memory(&mut self, memory: &mut Memory, eviction: Eviction, base_cycle_num: u128) {
+        match eviction {
+            Eviction::None => {}
+            Eviction::Clean { .. } => {
+                self.stall_cycles.clean_eviction += self.stall_config.clean_eviction as u128;
+            }
+            Eviction::Dirty { addr, line } => {
+                memory.set_cache_line(addr, &line);
+                self.issue_memory_write(self.stall_config.write_back, base_cycle_num);
+            }
+        }
+    }
+
+    pub fn show_prefetch_stats(&self) {
+        let stats = &self.prefetch_stats;
+        println!("prefetch count: {}", stats.issue_count);
+        println!("useful prefetch count: {}", stats.useful_count);
+        println!("late prefetch count: {}", stats.late_count);
+        println!("useless prefetch count: {}", stats.useless_count);
+        println!(
+            "prefetch pollution miss count: {}",
+            stats.pollution_miss_count
+        );
+    }
+
+    pub fn show(&self) {
+        let config = self.l1.get_config();
+        let stats = &self.l1_stats;
+        println!(
+            "cache: {} bytes, {}-way, {}-byte lines, {} replacement",
+            config.size, config.way_num, config.line_size, config.policy
+        );
+        println!("memory access count: {}", stats.memory_access_count);
+        println!("cache hit count: {}", stats.cache_hit_count);
+        println!("cache hit rate: {:.5}%", stats.get_hit_rate());
+        println!("store cache miss count: {}", stats.store_cache_miss_count);
+        println!("clean eviction count: {}", stats.clean_eviction_count);
+        println!("write-back count: {}", stats.write_back_count);
+        if let Some(l2_config) = self.get_l2_config() {
+            let stats = &self.l2_stats;
+            println!(
+                "l2 cache: {} bytes, {}-way, {} replacement, {}",
+                l2_config.size, l2_config.way_num, l2_config.policy, l2_config.inclusion
+            );
+            println!("l2 access count: {}", stats.memory_access_count);
+            println!("l2 hit count: {}", stats.cache_hit_count);
+            println!("l2 hit rate: {:.5}%", stats.get_hit_rate());
+            println!("l2 load miss count: {}", stats.load_cache_miss_count);
+            println!("l2 store miss count: {}", stats.store_cache_miss_count);
+            println!("l2 clean eviction count: {}", stats.clean_eviction_count);
+            println!("l2 write-back count: {}", stats.write_back_count);
+            if l2_config.inclusion == Inclusion::Inclusive {
+                println!(
+                    "l2 back-invalidation count: {}",
+                    self.back_invalidation_count
+                );
+            }
+        }
+        println!("memory write count: {}", self.memory_write_count);
+        let stall_cycles = &self.stall_cycles;
+        println!(
+            "memory stall cycles: {} (latency: {}, load miss: {}, store miss: {}, clean eviction: {}, write: {})",
+            stall_cycles.get_total(),
+            stall_cycles.latency,
+            stall_cycles.load_miss,
+            stall_cycles.store_miss,
+            stall_cycles.clean_eviction,
+            stall_cycles.write
+        );
+    }
+
+    pub fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.l1.write_snapshot(writer);
+        writer.put_bool(self.l2.is_some());
+        if let Some((l2, l2_config)) = self.l2.as_ref() {
+            writer.put_usize(l2_config.inclusion as usize);
+            l2.write_snapshot(writer);
+        }
+        self.write_buffer.write_snapshot(writer);
+        self.l1_stats.write_snapshot(writer);
+        self.l2_stats.write_snapshot(writer);
+        writer.put_usize(self.back_invalidation_count);
+        writer.put_usize(self.memory_write_count);
+        self.stall_cycles.write_snapshot(writer);
+        self.prefetch_stats.write_snapshot(writer);
+        let mut prefetched_lines = self.prefetched_lines.iter().collect::<Vec<_>>();
+        prefetched_lines.sort();
+        writer.put_usize(prefetched_lines.len());
+        for (line_addr, arrival_cycle_num) in prefetched_lines {
+            writer.put_u32(*line_addr);
+            writer.put_u128(*arrival_cycle_num);
+        }
+        write_line_set(writer, &self.prefetch_victims);
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        self.l1.read_snapshot(reader)?;
+        if reader.get_bool()? != self.l2.is_some() {
+            return Err("the snapshot was taken with another number of cache levels".to_string());
+        }
+        if let Some((l2, l2_config)) = self.l2.as_mut() {
+            reader.expect_usize("l2 inclusion", l2_config.inclusion as usize)?;
+            l2.read_snapshot(reader)?;
+        }
+        self.write_buffer.read_snapshot(reader)?;
+        self.l1_stats.read_snapshot(reader)?;
+        self.l2_stats.read_snapshot(reader)?;
+        self.back_invalidation_count = reader.get_usize()?;
+        self.memory_write_count = reader.get_usize()?;
+        self.stall_cycles.read_snapshot(reader)?;
+        self.prefetch_stats.read_snapshot(reader)?;
+        self.prefetched_lines.clear();
+        for _ in 0..reader.get_usize()? {
+            let line_addr = reader.get_u32()?;
+            self.prefetched_lines.insert(line_addr, reader.get_u128()?);
+        }
+        self.prefetch_victims = read_line_set(reader)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_hierarchy(inclusion: Inclusion) -> CacheHierarchy {
+        let config = CacheConfig {
+            size: 32,
+            way_num: 1,
+            line_size: 16,
+            ..CacheConfig::default()
+        };
+        let l2_config = L2Config {
+            size: 64,
+            way_num: 1,
+            latency: 10,
+            inclusion,
+            ..L2Config::default()
+        };
+        let stall_config = StallConfig {
+            load_miss: 100,
+            ..StallConfig::default()
+        };
+        CacheHierarchy::new(config, Some(l2_config), stall_config)
+    }
+
+    #[test]
+    fn test_cache_hierarchy() {
+        let mut memory = Memory::new();
+        memory.store_word(0x40, 7);
+        // 0x00 and 0x20 share an L1 set, but not an L2 one
+        let mut hierarchy = create_hierarchy(Inclusion::NonInclusive);
+        for _ in 0..2 {
+            hierarchy.load_word(&mut memory, 0x00, 0);
+            hierarchy.load_word(&mut memory, 0x20, 0);
+        }
+        assert_eq!(hierarchy.l2_stats.cache_hit_count, 2);
+        assert_eq!(hierarchy.get_stall_cycle_num(), 4 * 10 + 2 * 100);
+
+        // 0x00 and 0x40 share an L2 set, so filling 0x40 drops the dirty 0x00 from both levels
+        let mut hierarchy = create_hierarchy(Inclusion::Inclusive);
+        hierarchy.store_word(&mut memory, 0x04, 5, 0);
+        hierarchy.store_word(&mut memory, 0x08, 6, 0);
+        assert_eq!(memory.load_word(0x08), 0);
+        assert_eq!(hierarchy.load_word(&mut memory, 0x40, 0).0, 7);
+        assert_eq!(hierarchy.back_invalidation_count, 1);
+        assert_eq!(hierarchy.l2_stats.write_back_count, 1);
+        assert_eq!(memory.load_word(0x08), 6);
+
+        // an L2 hit moves the line back into L1
+        let mut hierarchy = create_hierarchy(Inclusion::Exclusive);
+        hierarchy.load_word(&mut memory, 0x00, 0);
+        hierarchy.load_word(&mut memory, 0x20, 0);
+        assert_eq!(hierarchy.l2.as_ref().unwrap().0.peek_word(0x04), Some(5));
+        assert_eq!(hierarchy.load_word(&mut memory, 0x04, 0), (5, false));
+        assert_eq!(hierarchy.l2.as_ref().unwrap().0.peek_word(0x04), None);
+        assert_eq!(hierarchy.l2_stats.cache_hit_count, 1);
+    }
+
+    #[test]
+    fn test_prefetch_stats() {
+        let mut memory = Memory::new();
+        let config = CacheConfig {
+            size: 32,
+            way_num: 1,
+            line_size: 16,
+            ..CacheConfig::default()
+        };
+        let stall_config = StallConfig {
+            load_miss: 100,
+            ..StallConfig::default()
+        };
+        let mut hierarchy = CacheHierarchy::new(config, None, stall_config);
+        // the line arrives at cycle 100, so a use at cycle 60 waits for 40 cycles
+        hierarchy.prefetch_line(&mut memory, 0x10, 0);
+        assert_eq!(hierarchy.load_word(&mut memory, 0x14, 60), (0, true));
+        assert!(hierarchy.was_prefetch_hit());
+        // 0x10, 0x30 and 0x50 share set 1
+        hierarchy.prefetch_line(&mut memory, 0x30, 0);
+        hierarchy.prefetch_line(&mut memory, 0x50, 0);
+        // the miss on 0x10 also drops the unused 0x50
+        hierarchy.load_word(&mut memory, 0x10, 0);
+        assert_eq!(
+            hierarchy.prefetch_stats,
+            PrefetchStats {
+                issue_count: 3,
+                useful_count: 1,
+                late_count: 1,
+                useless_count: 2,
+                pollution_miss_count: 1
+            }
+        );
+        assert_eq!(hierarchy.get_stall_cycle_num(), 40 + 100);
+    }
+}
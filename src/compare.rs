@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use crate::decoder::*;
+use crate::disassembler::*;
+use crate::error::*;
+use crate::fpu_emulator::*;
+use crate::register::*;
+use crate::trace::*;
+use crate::utils::*;
+
+pub struct Comparator {
+    reader: TraceReader,
+    history: VecDeque<TraceRecord>,
+    context_size: usize,
+    diverged: bool,
+    stopped: bool,
+}
+
+fn describe_register_write(reg_write: Option<RegisterWrite>) -> String {
+    match reg_write {
+        Some(RegisterWrite {
+            register: register @ RegisterId::Float(_),
+            value,
+        }) => format!(
+            "{}=0x{:>08x} ({})",
+            register,
+            value,
+            FloatingPoint::new(value).get_f32_value()
+        ),
+        Some(RegisterWrite { register, value }) => {
+            format!("{}=0x{:>08x} ({})", register, value, u32_to_i32(value))
+        }
+        None => "-".to_string(),
+    }
+}
+
+fn describe_memory_access(mem_access: Option<MemoryAccess>) -> String {
+    match mem_access {
+        Some(mem_access) => format!(
+            "{} 0x{:>08x}: 0x{:>08x}",
+            if mem_access.is_store { "store" } else { "load" },
+            mem_access.addr,
+            mem_access.value
+        ),
+        None => "-".to_string(),
+    }
+}
+
+fn describe_instruction(record: &TraceRecord) -> String {
+    format!(
+        "{:>08x} ({})",
+        record.inst,
+        disassemble(decode_instruction(record.inst), record.pc)
+    )
+}
+
+impl Comparator {
+    pub fn new(
+        file_path: &str,
+        format: TraceFormat,
+        context_size: usize,
+    ) -> Result<Self, SimulatorError> {
+        Ok(Comparator {
+            reader: TraceReader::new(file_path, format)?,
+            history: VecDeque::with_capacity(context_size),
+            context_size,
+            diverged: false,
+            stopped: false,
+        })
+    }
+
+    pub fn has_diverged(&self) -> bool {
+        self.diverged
+    }
+
+    /// Whether the simulation should stop, either on divergence or at the end of the reference trace.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Compares a retired instruction with the next reference record.
+    pub fn check(&mut self, record: &TraceRecord) {
+        if self.stopped {
+            return;
+        }
+        let reference = match self.reader.next_record() {
+            Some(Ok(reference)) => reference,
+            Some(Err(e)) => {
+                println!("Failed in reading reference trace ({}).", e);
+                self.diverged = true;
+                self.stopped = true;
+                return;
+            }
+            None => {
+                println!(
+                    "Reference trace ended before instruction {} (pc 0x{:>08x}).",
+                    record.count, record.pc
+                );
+                self.stopped = true;
+                return;
+            }
+        };
+        if reference.pc == record.pc
+            && reference.inst == record.inst
+            && reference.reg_write == record.reg_write
+            && reference.mem_access == record.mem_access
+        {
+            if self.history.len() == self.context_size {
+                self.history.pop_front();
+            }
+            if self.context_size > 0 {
+                self.history.push_back(*record);
+            }
+            return;
+        }
+        self.diverged = true;
+        self.stopped = true;
+        self.show_divergence(record, &reference);
+    }
+
+    pub fn finish(&mut self) {
+        if self.diverged {
+            return;
+        }
+        match self.reader.next_record() {
+            Some(Ok(reference)) => {
+                println!(
+                    "Simulation ended before the reference trace (next reference pc 0x{:>08x}).",
+                    reference.pc
+                );
+                self.diverged = true;
+            }
+            Some(Err(e)) => {
+                println!("Failed in reading reference trace ({}).", e);
+                self.diverged = true;
+            }
+            None => println!("No divergence found."),
+        }
+    }
+
+    fn show_divergence(&self, record: &TraceRecord, reference: &TraceRecord) {
+        println!("---------- divergence ----------");
+        println!("instruction count: {}", record.count);
+        println!("       {: <40} reference", "simulator");
+        let rows = [
+            (
+                "pc",
+                format!("0x{:>08x}", record.pc),
+                format!("0x{:>08x}", reference.pc),
+            ),
+            (
+                "inst",
+                describe_instruction(record),
+                describe_instruction(reference),
+            ),
+            (
+                "rd",
+                describe_register_write(record.reg_write),
+                describe_register_write(reference.reg_write),
+            ),
+            (
+                "mem",
+                describe_memory_access(record.mem_access),
+                describe_memory_access(reference.mem_access),
+            ),
+        ];
+        for (name, simulator, reference) in rows {
+            let mark = if simulator == reference { " " } else { "*" };
+            print!("{}{: <6}", mark, name);
+            print_filled_with_space(&simulator, 40);
+            println!(" {}", reference);
+        }
+        println!(
+            "---------- preceding {} instructions ----------",
+            self.history.len()
+        );
+        for record in &self.history {
+            println!(
+                "{: >10} 0x{:>08x}: {: <40} {} {}",
+                record.count,
+                record.pc,
+                describe_instruction(record),
+                describe_register_write(record.reg_write),
+                describe_memory_access(record.mem_access)
+            );
+        }
+    }
+}
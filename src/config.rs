@@ -0,0 +1,209 @@
+use std::fs;
+
+use serde::Deserialize;
+
+pub use crate::branch_predictor::{BranchPredictorConfig, BranchPredictorKind};
+pub use crate::cache::{CacheConfig, WriteMissPolicy, WritePolicy};
+pub use crate::cache_hierarchy::{Inclusion, L2Config, StallConfig};
+use crate::error::*;
+pub use crate::fpu_scoreboard::FpuConfig;
+pub use crate::instruction_cache::ICacheConfig;
+pub use crate::pipeline::PipelineConfig;
+pub use crate::prefetcher::{PrefetchConfig, PrefetcherKind};
+pub use crate::replacement_policy::ReplacementPolicyKind;
+pub use crate::superscalar::SuperscalarConfig;
+
+/// Settings read from a TOML file; every table and key is optional.
+///
+/// ```toml
+/// [cache]
+/// size = 32768
+/// way_num = 4
+/// line_size = 64
+/// policy = "plru"
+/// write_policy = "write-through"
+/// write_miss_policy = "no-allocate"
+/// write_buffer_size = 4
+/// latency = 1
+///
+/// # L2 is only simulated when this table is present
+/// [l2]
+/// size = 262144
+/// way_num = 8
+/// latency = 12
+/// inclusion = "exclusive"
+///
+/// # the instruction cache is only simulated when this table is present
+/// [icache]
+/// size = 8192
+/// way_num = 2
+/// line_size = 32
+/// miss_penalty = 12960
+///
+/// # the data cache only prefetches when this table is present
+/// [prefetch]
+/// kind = "stride"
+/// degree = 2
+/// table_size = 64
+/// stream_num = 4
+///
+/// # cycles are predicted with a pipeline model only when this table is present
+/// [pipeline]
+/// stage_num = 5
+/// execute_stage = 3
+/// memory_stage = 4
+/// branch_stage = 4
+/// forwarding = true
+///
+/// # a superscalar core with the shape of the pipeline is only timed when this table is present
+/// [superscalar]
+/// issue_width = 2
+/// alu_num = 2
+/// branch_ends_group = true
+///
+/// # every branch and jump flushes the pipeline unless this table is present
+/// [branch_predictor]
+/// kind = "gshare"
+/// table_size = 1024
+/// history_len = 10
+/// btb_size = 64
+/// ras_size = 8
+///
+/// [fpu]
+/// pipelined = false
+/// count_stalls = false
+///
+/// [stall]
+/// load_miss = 12960
+/// store_miss = 12960
+/// write_back = 12960
+/// clean_eviction = 0
+/// memory_write = 12960
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub cache: CacheConfig,
+    pub l2: Option<L2Config>,
+    pub icache: Option<ICacheConfig>,
+    pub prefetch: Option<PrefetchConfig>,
+    pub pipeline: Option<PipelineConfig>,
+    pub superscalar: Option<SuperscalarConfig>,
+    pub branch_predictor: Option<BranchPredictorConfig>,
+    pub stall: StallConfig,
+    pub fpu: FpuConfig,
+}
+
+pub fn parse_config(text: &str) -> Result<Config, String> {
+    toml::from_str(text).map_err(|e| e.message().to_string())
+}
+
+pub fn load_config_file(file_path: &str) -> Result<Config, SimulatorError> {
+    let error = |reason: String| SimulatorError::InvalidConfig {
+        path: Some(file_path.to_string()),
+        reason,
+    };
+    let text = fs::read_to_string(file_path).map_err(|e| error(e.to_string()))?;
+    let config = parse_config(&text).map_err(error)?;
+    config.cache.validate().map_err(error)?;
+    if let Some(l2) = config.l2 {
+        l2.validate(&config.cache).map_err(error)?;
+    }
+    if let Some(icache) = config.icache {
+        icache.validate().map_err(error)?;
+    }
+    if let Some(prefetch) = config.prefetch {
+        prefetch.validate().map_err(error)?;
+    }
+    if let Some(pipeline) = config.pipeline {
+        pipeline.validate().map_err(error)?;
+    }
+    if let Some(superscalar) = config.superscalar {
+        superscalar.validate().map_err(error)?;
+    }
+    if let Some(branch_predictor) = config.branch_predictor {
+        branch_predictor.validate().map_err(error)?;
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty_config() {
+        assert_eq!(parse_config("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_cache_config() {
+        let config = parse_config("[cache]\nway_num = 4\nline_size = 64\n").unwrap();
+        assert_eq!(config.cache.size, CacheConfig::default().size);
+        assert_eq!(config.cache.way_num, 4);
+        assert_eq!(config.cache.line_size, 64);
+        assert_eq!(config.cache.get_line_num(), 64);
+        assert_eq!(config.stall, StallConfig::default());
+        assert!(parse_config("[cache]\nways = 4\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_stall_config() {
+        let config = parse_config("[stall]\nstore_miss = 100\n").unwrap();
+        assert_eq!(config.stall.store_miss, 100);
+        assert_eq!(config.stall.load_miss, StallConfig::default().load_miss);
+    }
+
+    #[test]
+    fn test_parse_l2_config() {
+        let config = parse_config("[l2]\ninclusion = \"inclusive\"\n").unwrap();
+        assert_eq!(config.l2.unwrap().inclusion, Inclusion::Inclusive);
+        assert_eq!(config.l2.unwrap().size, L2Config::default().size);
+    }
+
+    #[test]
+    fn test_parse_icache_config() {
+        let config = parse_config("[icache]\nline_size = 32\n").unwrap();
+        assert_eq!(config.icache.unwrap().line_size, 32);
+        assert_eq!(config.l2, None);
+    }
+
+    #[test]
+    fn test_parse_prefetch_config() {
+        let config = parse_config("[prefetch]\nkind = \"stream\"\n").unwrap();
+        assert_eq!(config.prefetch.unwrap().kind, PrefetcherKind::Stream);
+    }
+
+    #[test]
+    fn test_parse_pipeline_config() {
+        let config = parse_config("[pipeline]\nforwarding = false\n").unwrap();
+        assert!(!config.pipeline.unwrap().forwarding);
+        assert_eq!(config.pipeline.unwrap().stage_num, 5);
+    }
+
+    #[test]
+    fn test_parse_superscalar_config() {
+        let config = parse_config("[superscalar]\nissue_width = 4\n").unwrap();
+        assert_eq!(config.superscalar.unwrap().issue_width, 4);
+        assert_eq!(
+            config.superscalar.unwrap().alu_num,
+            SuperscalarConfig::default().alu_num
+        );
+    }
+
+    #[test]
+    fn test_parse_branch_predictor_config() {
+        let config = parse_config("[branch_predictor]\nkind = \"two-bit\"\n").unwrap();
+        assert_eq!(
+            config.branch_predictor.unwrap().kind,
+            BranchPredictorKind::TwoBit
+        );
+    }
+
+    #[test]
+    fn test_parse_fpu_config() {
+        let config = parse_config("[fpu]\npipelined = true\n").unwrap();
+        assert!(config.fpu.pipelined);
+        assert!(!config.fpu.count_stalls);
+    }
+}
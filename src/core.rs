@@ -1,94 +1,160 @@
 use indicatif::{ProgressBar, ProgressStyle};
 // use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::Read;
-use std::io::Write;
 use std::time::Instant;
 use std::vec;
 
+use crate::bin_loader::*;
+use crate::branch_predictor::*;
+use crate::branch_profile::*;
 use crate::cache::*;
+use crate::cache_hierarchy::*;
+use crate::compare::*;
+use crate::debugger::*;
 use crate::decoder::*;
+use crate::disassembler::*;
+use crate::error::*;
 use crate::fpu_emulator::*;
+use crate::fpu_scoreboard::*;
 use crate::instruction::*;
+use crate::instruction_cache::*;
 use crate::instruction_memory::*;
+use crate::io_device::*;
 use crate::memory::*;
+use crate::miss_classifier::*;
+use crate::miss_profile::*;
+use crate::pipeline::*;
+use crate::prefetcher::*;
 use crate::register::*;
-use crate::sld_loader::*;
+use crate::snapshot::*;
+use crate::superscalar::*;
+use crate::sweep::*;
+use crate::trace::*;
 use crate::types::*;
 use crate::utils::*;
+use crate::watchpoint::*;
 
 const INT_REGISTER_SIZE: usize = 32;
+const RECENT_PC_SIZE: usize = 16;
+const SP_INDEX: usize = 2;
 const FLOAT_REGISTER_SIZE: usize = 32;
 // const IO_ADDRESS: Address = 2147483648;
 
-const CACHE_MISS_STALL: usize = 108 * 120;
 const FLUSH_STALL: usize = 3;
 const FREQUENCY: usize = 120 * 1000000;
 const BAUD_RATE: usize = 115200;
 
+/// Counters the stalls of one instruction are taken from for the pipeline model.
+#[derive(Clone, Copy)]
+struct StallCounts {
+    fpu_op_count: usize,
+    flush_count: usize,
+    fetch_stall_cycle_num: u128,
+    memory_stall_cycle_num: u128,
+}
+
+fn predict_cycle_num(
+    instruction_count: InstructionCount,
+    flush_count: usize,
+    memory_stall_cycle_num: u128,
+) -> u128 {
+    instruction_count + flush_count as u128 * FLUSH_STALL as u128 + memory_stall_cycle_num
+}
+
 pub struct Core {
     memory: Memory,
-    cache: Cache,
-    memory_access_count: usize,
-    cache_hit_count: usize,
-    load_cache_miss_count: usize,
+    cache: CacheHierarchy,
+    prefetcher: Option<(Box<dyn Prefetcher>, PrefetchConfig)>,
+    instruction_cache: Option<InstructionCache>,
+    pipeline: Option<Pipeline>,
+    superscalar: Option<Pipeline>,
+    branch_predictor: Option<BranchPredictor>,
     instruction_memory: InstructionMemory,
     instruction_count: InstructionCount,
     int_registers: [IntRegister; INT_REGISTER_SIZE],
     float_registers: [FloatRegister; FLOAT_REGISTER_SIZE],
     pc: Address,
-    pc_stats: [(usize, usize); 1000000],
+    pc_stats: Vec<(usize, usize)>,
     inst_stats: [usize; 256],
     int_registers_access_counter: Vec<usize>,
     float_registers_access_counter: Vec<usize>,
     inv_map: InvMap,
     sqrt_map: SqrtMap,
-    sld_vec: Vec<String>,
-    sld_counter: usize,
+    input_device: Box<dyn InputDevice>,
+    output_device: Box<dyn OutputDevice>,
     output: Vec<u8>,
     decoded_instructions: Vec<Instruction>,
     use_cache: bool,
+    take_inst_stats: bool,
+    take_pc_stats: bool,
     load_stall_counter: usize,
     load_dest: Option<usize>,
     before_load_dest: Option<usize>,
-    fpu_stall_counter: usize,
+    fpu_scoreboard: FpuScoreboard,
     flush_counter: usize,
+    watchpoints: Watchpoints,
+    reg_write: Option<RegisterWrite>,
+    mem_access: Option<MemoryAccess>,
+    tracer: Option<Tracer>,
+    comparator: Option<Comparator>,
+    cache_sweep: Option<CacheSweep>,
+    miss_profile: Option<MissProfile>,
+    branch_profile: Option<BranchProfile>,
+    miss_classifier: Option<MissClassifier>,
+    recent_pcs: VecDeque<Address>,
 }
 
 impl Core {
     pub fn new() -> Self {
         let memory = Memory::new();
-        let cache = Cache::new();
-        let memory_access_count = 0;
-        let cache_hit_count = 0;
-        let load_cache_miss_count = 0;
+        let cache = CacheHierarchy::new(CacheConfig::default(), None, StallConfig::default());
+        let prefetcher = None;
+        let instruction_cache = None;
+        let pipeline = None;
+        let superscalar = None;
+        let branch_predictor = None;
         let instruction_memory = InstructionMemory::new();
         let instruction_count = 0;
         let int_registers = [IntRegister::new(); INT_REGISTER_SIZE];
         let float_registers = [FloatRegister::new(); FLOAT_REGISTER_SIZE];
         let pc = 0;
-        let pc_stats = [(0, 0); 1000000];
+        let pc_stats = vec![(0, 0); 1000000];
         let inst_stats = [0; 256];
         let int_registers_access_counter = vec![0; INT_REGISTER_SIZE];
         let float_registers_access_counter = vec![0; FLOAT_REGISTER_SIZE];
         let inv_map = create_inv_map();
         let sqrt_map = create_sqrt_map();
-        let sld_vec = vec![];
-        let sld_counter = 0;
+        let input_device: Box<dyn InputDevice> = Box::new(SldInput::new(vec![]));
+        let output_device: Box<dyn OutputDevice> = Box::new(NullOutput);
         let output = vec![];
         let decoded_instructions = vec![];
         let use_cache = true;
+        let take_inst_stats = false;
+        let take_pc_stats = false;
         let load_stall_counter = 0;
         let load_dest = None;
         let before_load_dest = None;
-        let fpu_stall_counter = 0;
+        let fpu_scoreboard = FpuScoreboard::new(FpuConfig::default());
         let flush_counter = 0;
-        Core {
+        let watchpoints = Watchpoints::new();
+        let reg_write = None;
+        let mem_access = None;
+        let tracer = None;
+        let comparator = None;
+        let cache_sweep = None;
+        let miss_profile = None;
+        let branch_profile = None;
+        let miss_classifier = None;
+        let recent_pcs = VecDeque::with_capacity(RECENT_PC_SIZE);
+        let mut core = Core {
             memory,
             cache,
-            memory_access_count,
-            cache_hit_count,
-            load_cache_miss_count,
+            prefetcher,
+            instruction_cache,
+            pipeline,
+            superscalar,
+            branch_predictor,
             instruction_memory,
             instruction_count,
             int_registers,
@@ -100,17 +166,31 @@ impl Core {
             float_registers_access_counter,
             inv_map,
             sqrt_map,
-            sld_vec,
-            sld_counter,
+            input_device,
+            output_device,
             output,
             decoded_instructions,
             use_cache,
+            take_inst_stats,
+            take_pc_stats,
             load_stall_counter,
             load_dest,
             before_load_dest,
-            fpu_stall_counter,
+            fpu_scoreboard,
             flush_counter,
-        }
+            watchpoints,
+            reg_write,
+            mem_access,
+            tracer,
+            comparator,
+            cache_sweep,
+            miss_profile,
+            branch_profile,
+            miss_classifier,
+            recent_pcs,
+        };
+        core.init();
+        core
     }
 
     pub fn get_inv_map(&self) -> &InvMap {
@@ -137,12 +217,23 @@ impl Core {
         self.instruction_count += 1;
     }
 
+    pub fn get_instruction_count(&self) -> InstructionCount {
+        self.instruction_count
+    }
+
     pub fn load_instruction(&mut self, addr: Address) -> InstructionValue {
         self.instruction_memory.load(addr)
     }
 
     pub fn store_instruction(&mut self, addr: Address, inst: InstructionValue) {
         self.instruction_memory.store(addr, inst);
+        if let Some(decoded) = self.decoded_instructions.get_mut(addr as usize >> 2) {
+            *decoded = decode_instruction(inst);
+        }
+    }
+
+    pub fn get_decoded_instruction(&self, addr: Address) -> Instruction {
+        self.decoded_instructions[addr as usize >> 2]
     }
 
     pub fn get_int_register(&mut self, index: usize) -> Int {
@@ -150,6 +241,7 @@ impl Core {
         if self.before_load_dest == Some(index) {
             self.load_stall_counter += 1;
         }
+        self.check_fpu_result(index);
         self.int_registers[index].get()
     }
 
@@ -158,6 +250,16 @@ impl Core {
         if index == ZERO {
             return; // zero register
         }
+        if !self.watchpoints.is_empty() {
+            let old_value = i32_to_u32(self.int_registers[index].get());
+            self.watchpoints
+                .check_int_register(self.pc, index, old_value, i32_to_u32(value));
+        }
+        self.reg_write = Some(RegisterWrite {
+            register: RegisterId::Int(index),
+            value: i32_to_u32(value),
+        });
+        self.fpu_scoreboard.write(index);
         self.int_registers[index].set(value);
     }
 
@@ -169,28 +271,91 @@ impl Core {
         if self.before_load_dest == Some(index + 32) {
             self.load_stall_counter += 1;
         }
+        self.check_fpu_result(index + 32);
         self.float_registers[index].get()
     }
 
+    /// Reads an integer register without touching access counters.
+    pub fn peek_int_register(&self, index: usize) -> Int {
+        self.int_registers[index].get()
+    }
+
+    /// Writes an integer register without touching access counters or watchpoints.
+    pub fn poke_int_register(&mut self, index: usize, value: Int) {
+        if index != ZERO {
+            self.int_registers[index].set(value);
+        }
+    }
+
+    /// Reads a float register without touching access counters.
+    pub fn peek_float_register(&self, index: usize) -> FloatingPoint {
+        if index == ZERO {
+            return FloatingPoint::new(0);
+        }
+        self.float_registers[index].get()
+    }
+
+    /// Writes a float register without touching access counters or watchpoints.
+    pub fn poke_float_register(&mut self, index: usize, value: FloatingPoint) {
+        self.float_registers[index].set(value);
+    }
+
     pub fn set_float_register(&mut self, index: usize, value: FloatingPoint) {
         self.float_registers_access_counter[index] += 1;
+        if !self.watchpoints.is_empty() {
+            let old_value = self.float_registers[index].get().get_32_bits();
+            self.watchpoints
+                .check_float_register(self.pc, index, old_value, value.get_32_bits());
+        }
+        self.reg_write = Some(RegisterWrite {
+            register: RegisterId::Float(index),
+            value: value.get_32_bits(),
+        });
+        self.fpu_scoreboard.write(index + 32);
         self.float_registers[index].set(value);
     }
 
     fn increment_memory_access_count(&mut self) {
-        self.memory_access_count += 1;
+        self.cache.count_access();
     }
 
-    fn increment_cache_hit_count(&mut self) {
-        self.cache_hit_count += 1;
+    /// `register` is numbered as in `Operands`.
+    fn check_fpu_result(&mut self, register: usize) {
+        if self.fpu_scoreboard.is_pending(register) {
+            let now = self.get_fpu_cycle_num();
+            self.fpu_scoreboard.read(register, now);
+        }
     }
 
-    pub fn increment_fpu_stall_counter(&mut self, value: usize) {
-        self.fpu_stall_counter += value;
+    /// Called by an FPU instruction after writing its result; `latency_stall` is the cycles it takes beyond the first.
+    pub fn start_fpu_operation(&mut self, op: InstructionId, latency_stall: usize) {
+        let dest = self.reg_write.map(|reg_write| match reg_write.register {
+            RegisterId::Int(index) => index,
+            RegisterId::Float(index) => index + 32,
+        });
+        let now = self.get_fpu_cycle_num();
+        self.fpu_scoreboard.start(op, latency_stall, dest, now);
     }
 
     fn show_fpu_stall_counter(&self) {
-        println!("fpu stall: {}", self.fpu_stall_counter);
+        let config = self.fpu_scoreboard.get_config();
+        println!(
+            "fpu stall: {} ({} fpu, {} the predicted cycle count)",
+            self.fpu_scoreboard.get_stall_cycle_num(),
+            if config.pipelined {
+                "pipelined"
+            } else {
+                "unpipelined"
+            },
+            if config.count_stalls {
+                "included in"
+            } else {
+                "left out of"
+            }
+        );
+        let inst_id_to_name_map = create_inst_id_to_name_map();
+        self.fpu_scoreboard
+            .show(|op| inst_id_to_name_map.get(&op).unwrap().to_string());
     }
 
     pub fn set_load_dest(&mut self, value: usize) {
@@ -205,19 +370,74 @@ impl Core {
         self.flush_counter += 1;
     }
 
-    fn increment_load_cache_miss_count(&mut self) {
-        self.load_cache_miss_count += 1;
+    /// Flushes the pipeline unless the branch predictor gets the transfer right; without one every transfer flushes.
+    /// Called before pc moves to the target.
+    pub fn resolve_control_transfer(&mut self, transfer: ControlTransfer) {
+        let is_mispredicted = match self.branch_predictor.as_mut() {
+            Some(branch_predictor) => branch_predictor.resolve(self.pc, &transfer),
+            None => true,
+        };
+        if is_mispredicted {
+            self.increment_flush_counter();
+        }
+        if let (Some(branch_profile), ControlTransfer::Branch { is_taken, .. }) =
+            (self.branch_profile.as_mut(), transfer)
+        {
+            branch_profile.record(self.pc, is_taken, is_mispredicted);
+        }
+    }
+
+    fn record_cache_access(&mut self, addr: Address, is_store: bool, is_hit: bool) {
+        let sp = i32_to_u32(self.peek_int_register(SP_INDEX));
+        if let Some(miss_profile) = self.miss_profile.as_mut() {
+            miss_profile.record(self.pc, addr, sp, is_store, is_hit);
+        }
+        if let Some(miss_classifier) = self.miss_classifier.as_mut() {
+            miss_classifier.access(addr, is_store, is_hit);
+        }
+    }
+
+    fn get_fetch_stall_cycle_num(&self) -> u128 {
+        self.instruction_cache
+            .as_ref()
+            .map_or(0, InstructionCache::get_stall_cycle_num)
+    }
+
+    /// Stalls of the FPU scoreboard, if the predicted cycle count includes them.
+    fn get_fpu_stall_cycle_num(&self) -> u128 {
+        if self.fpu_scoreboard.get_config().count_stalls {
+            self.fpu_scoreboard.get_stall_cycle_num()
+        } else {
+            0
+        }
+    }
+
+    fn get_analytic_cycle_num(&self) -> u128 {
+        self.get_base_cycle_num() + self.cache.get_stall_cycle_num()
     }
 
-    fn process_cache_miss(&mut self, addr: Address) {
-        let line_addr = addr & !((1 << self.cache.get_offset_bit_num()) - 1);
-        let line = self.memory.get_cache_line(line_addr);
-        let set_line_result = self.cache.set_line(line_addr, line);
-        if let Some(evicted_line) = set_line_result {
-            self.memory.set_cache_line(evicted_line);
+    /// Cycle the FPU scoreboard sees the current instruction at, with its own stalls whether they are counted or not.
+    fn get_fpu_cycle_num(&self) -> u128 {
+        self.get_analytic_cycle_num() - self.get_fpu_stall_cycle_num()
+            + self.fpu_scoreboard.get_stall_cycle_num()
+    }
+
+    fn get_predicted_cycle_num(&self) -> u128 {
+        match &self.pipeline {
+            Some(pipeline) => pipeline.get_cycle_num(),
+            None => self.get_analytic_cycle_num(),
         }
     }
 
+    /// Cycle count the data cache hierarchy adds its stalls to, both the real one and those of the cache sweep.
+    fn get_base_cycle_num(&self) -> u128 {
+        predict_cycle_num(
+            self.instruction_count,
+            self.flush_counter,
+            self.get_fetch_stall_cycle_num() + self.get_fpu_stall_cycle_num(),
+        )
+    }
+
     // #[allow(dead_code)]
     // pub fn load_byte(&mut self, addr: Address) -> Byte {
     //     self.increment_memory_access_count();
@@ -320,43 +540,190 @@ impl Core {
     //     }
     // }
 
-    pub fn read_int(&mut self) -> Word {
-        let value = self.sld_vec[self.sld_counter].parse::<i32>().unwrap();
-        self.sld_counter += 1;
-        value
+    pub fn set_input_device(&mut self, input_device: Box<dyn InputDevice>) {
+        self.input_device = input_device;
     }
 
-    pub fn read_float(&mut self) -> Word {
-        let value = self.sld_vec[self.sld_counter].parse::<f32>().unwrap();
-        let fp = FloatingPoint::new_f32(value);
-        self.sld_counter += 1;
-        u32_to_i32(fp.get_32_bits())
+    pub fn set_output_device(&mut self, output_device: Box<dyn OutputDevice>) {
+        self.output_device = output_device;
     }
 
-    pub fn load_word(&mut self, addr: Address) -> Word {
+    /// Replaces the cache hierarchy with an empty one of the given geometry and sets the stall cycles of memory events.
+    pub fn set_cache_config(
+        &mut self,
+        config: CacheConfig,
+        l2_config: Option<L2Config>,
+        stall_config: StallConfig,
+    ) -> Result<(), SimulatorError> {
+        let error = |reason| SimulatorError::InvalidConfig { path: None, reason };
+        config.validate().map_err(error)?;
+        if let Some(l2_config) = l2_config {
+            l2_config.validate(&config).map_err(error)?;
+        }
+        self.cache = CacheHierarchy::new(config, l2_config, stall_config);
+        Ok(())
+    }
+
+    /// Attaches a fresh prefetcher to the data cache, or detaches it.
+    pub fn set_prefetch_config(
+        &mut self,
+        prefetch_config: Option<PrefetchConfig>,
+    ) -> Result<(), SimulatorError> {
+        if let Some(prefetch_config) = prefetch_config {
+            prefetch_config
+                .validate()
+                .map_err(|reason| SimulatorError::InvalidConfig { path: None, reason })?;
+        }
+        self.prefetcher = prefetch_config
+            .map(|prefetch_config| (create_prefetcher(&prefetch_config), prefetch_config));
+        Ok(())
+    }
+
+    /// Puts an empty instruction cache on the fetch path, or removes it so that fetches cost nothing.
+    pub fn set_icache_config(
+        &mut self,
+        icache_config: Option<ICacheConfig>,
+    ) -> Result<(), SimulatorError> {
+        if let Some(icache_config) = icache_config {
+            icache_config
+                .validate()
+                .map_err(|reason| SimulatorError::InvalidConfig { path: None, reason })?;
+        }
+        self.instruction_cache = icache_config.map(InstructionCache::new);
+        Ok(())
+    }
+
+    /// Replaces the FPU scoreboard with an empty one; set before the pipeline model, which follows the same FPU.
+    pub fn set_fpu_config(&mut self, fpu_config: FpuConfig) {
+        self.fpu_scoreboard = FpuScoreboard::new(fpu_config);
+    }
+
+    /// Times the following instructions with a fresh pipeline model, or with the analytic model if `None`.
+    pub fn set_pipeline_config(
+        &mut self,
+        pipeline_config: Option<PipelineConfig>,
+    ) -> Result<(), SimulatorError> {
+        if let Some(pipeline_config) = pipeline_config {
+            pipeline_config
+                .validate()
+                .map_err(|reason| SimulatorError::InvalidConfig { path: None, reason })?;
+        }
+        let fpu_config = self.fpu_scoreboard.get_config();
+        self.pipeline = pipeline_config.map(|pipeline_config| {
+            Pipeline::new(pipeline_config, SuperscalarConfig::scalar(), fpu_config)
+        });
+        Ok(())
+    }
+
+    /// Times the following instructions on a fresh superscalar core too, with the shape of the pipeline model
+    /// or the default one, or stops doing so if `None`; set after the pipeline model.
+    pub fn set_superscalar_config(
+        &mut self,
+        superscalar_config: Option<SuperscalarConfig>,
+    ) -> Result<(), SimulatorError> {
+        if let Some(superscalar_config) = superscalar_config {
+            superscalar_config
+                .validate()
+                .map_err(|reason| SimulatorError::InvalidConfig { path: None, reason })?;
+        }
+        let pipeline_config = self
+            .pipeline
+            .as_ref()
+            .map_or(PipelineConfig::default(), Pipeline::get_config);
+        let fpu_config = self.fpu_scoreboard.get_config();
+        self.superscalar = superscalar_config.map(|superscalar_config| {
+            Pipeline::new(pipeline_config, superscalar_config, fpu_config)
+        });
+        Ok(())
+    }
+
+    /// Predicts control transfers with a fresh branch predictor, or flushes on every one of them if `None`.
+    pub fn set_branch_predictor_config(
+        &mut self,
+        branch_predictor_config: Option<BranchPredictorConfig>,
+    ) -> Result<(), SimulatorError> {
+        if let Some(branch_predictor_config) = branch_predictor_config {
+            branch_predictor_config
+                .validate()
+                .map_err(|reason| SimulatorError::InvalidConfig { path: None, reason })?;
+        }
+        self.branch_predictor = branch_predictor_config.map(BranchPredictor::new);
+        Ok(())
+    }
+
+    fn read_input(&mut self, kind: InputKind) -> Result<Word, SimulatorError> {
+        match self.input_device.read(kind) {
+            Ok(value) => Ok(u32_to_i32(value)),
+            Err(e) => Err(SimulatorError::InvalidInput {
+                pc: self.pc,
+                index: e.index,
+                token: e.token,
+                expected: kind.describe(),
+            }),
+        }
+    }
+
+    pub fn read_int(&mut self) -> Result<Word, SimulatorError> {
+        self.read_input(InputKind::Int)
+    }
+
+    pub fn read_float(&mut self) -> Result<Word, SimulatorError> {
+        self.read_input(InputKind::Float)
+    }
+
+    fn check_memory_address(&self, addr: Address) -> Result<(), SimulatorError> {
+        if addr as usize >= MEMORY_SIZE {
+            return Err(SimulatorError::MemoryOutOfRange { pc: self.pc, addr });
+        }
+        Ok(())
+    }
+
+    pub fn load_word(&mut self, addr: Address) -> Result<Word, SimulatorError> {
+        self.check_memory_address(addr)?;
+        let value = self.load_word_without_watch(addr);
+        self.mem_access = Some(MemoryAccess {
+            addr,
+            value: i32_to_u32(value),
+            is_store: false,
+        });
+        if !self.watchpoints.is_empty() {
+            self.watchpoints
+                .check_memory_read(self.pc, addr, i32_to_u32(value));
+        }
+        Ok(value)
+    }
+
+    fn load_word_without_watch(&mut self, addr: Address) -> Word {
         // if addr == IO_ADDRESS {
         //     let value = self.sld_vec[self.sld_counter].parse::<i32>().unwrap();
         //     self.sld_counter += 1;
         //     return value;
         // }
         self.increment_memory_access_count();
+        if self.cache_sweep.is_some() {
+            let base_cycle_num = self.get_base_cycle_num();
+            if let Some(cache_sweep) = self.cache_sweep.as_mut() {
+                cache_sweep.access(addr, false, base_cycle_num);
+            }
+        }
         if self.use_cache {
-            let cache_access = self.cache.get_word(addr);
-            match cache_access {
-                CacheAccess::HitWord(value) => {
-                    self.increment_cache_hit_count();
-                    value
-                }
-                CacheAccess::Miss => {
-                    self.increment_load_cache_miss_count();
-                    let value = self.memory.load_word(addr);
-                    self.process_cache_miss(addr);
-                    value
-                }
-                _ => {
-                    panic!("invalid cache access");
+            let base_cycle_num = self.get_base_cycle_num();
+            let (value, is_hit) = self.cache.load_word(&mut self.memory, addr, base_cycle_num);
+            self.record_cache_access(addr, false, is_hit);
+            if let Some((prefetcher, _)) = self.prefetcher.as_mut() {
+                let access = LoadAccess {
+                    pc: self.pc,
+                    addr,
+                    is_hit,
+                    is_prefetch_hit: self.cache.was_prefetch_hit(),
+                };
+                let line_size = self.cache.get_config().line_size;
+                for prefetch_addr in prefetcher.observe(&access, line_size) {
+                    self.cache
+                        .prefetch_line(&mut self.memory, prefetch_addr, base_cycle_num);
                 }
             }
+            value
         } else {
             self.memory.load_word(addr)
         }
@@ -391,36 +758,65 @@ impl Core {
     //     }
     // }
 
+    /// Reads a word without touching cache state or statistics.
+    pub fn peek_word(&self, addr: Address) -> Word {
+        if self.use_cache {
+            return self.cache.peek_word(&self.memory, addr);
+        }
+        self.memory.load_word(addr)
+    }
+
+    /// Writes a word to memory (and the cache line holding it) without touching cache state or statistics.
+    pub fn poke_word(&mut self, addr: Address, value: Word) -> Result<(), SimulatorError> {
+        self.check_memory_address(addr)?;
+        self.cache.poke_word(&mut self.memory, addr, value);
+        Ok(())
+    }
+
     pub fn print_char(&mut self, value: Word) {
         self.output.push(value as u8);
+        self.output_device.write(value as u8);
+    }
+
+    pub fn get_output(&self) -> &[u8] {
+        &self.output
     }
 
-    pub fn store_word(&mut self, addr: Address, value: Word) {
+    pub fn store_word(&mut self, addr: Address, value: Word) -> Result<(), SimulatorError> {
         // if addr == IO_ADDRESS {
         //     self.output.push(value as u8);
         //     return;
         // }
+        self.check_memory_address(addr)?;
+        if !self.watchpoints.is_empty() {
+            let old_value = i32_to_u32(self.peek_word(addr));
+            self.watchpoints
+                .check_memory_write(self.pc, addr, old_value, i32_to_u32(value));
+        }
+        self.mem_access = Some(MemoryAccess {
+            addr,
+            value: i32_to_u32(value),
+            is_store: true,
+        });
         self.increment_memory_access_count();
-        if self.use_cache {
-            let cache_access = self.cache.set_word(addr, value);
-            match cache_access {
-                CacheAccess::HitSet => {
-                    self.increment_cache_hit_count();
-                }
-                CacheAccess::Miss => {
-                    self.memory.store_word(addr, value);
-                    self.process_cache_miss(addr);
-                }
-                _ => {
-                    panic!("invalid cache access");
-                }
+        if self.cache_sweep.is_some() {
+            let base_cycle_num = self.get_base_cycle_num();
+            if let Some(cache_sweep) = self.cache_sweep.as_mut() {
+                cache_sweep.access(addr, true, base_cycle_num);
             }
+        }
+        if self.use_cache {
+            let base_cycle_num = self.get_base_cycle_num();
+            let is_hit = self
+                .cache
+                .store_word(&mut self.memory, addr, value, base_cycle_num);
+            self.record_cache_access(addr, true, is_hit);
         } else {
             self.memory.store_word(addr, value);
         }
+        Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn show_registers(&self) {
         for i in 0..INT_REGISTER_SIZE {
             print!("x{: <2} 0x{:>08x} ", i, self.int_registers[i].get());
@@ -440,6 +836,10 @@ impl Core {
         }
     }
 
+    pub fn get_watchpoints(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
     pub fn update_inst_stats(&mut self, inst_id: usize) {
         self.inst_stats[inst_id] += 1;
     }
@@ -468,29 +868,53 @@ impl Core {
 
     fn show_pc_stats(&self) {
         println!("---------- pc stats ----------");
-        let inst_id_to_name_map = create_inst_id_to_name_map();
         let mut pc_stats = vec![];
-        for (pc, (count, inst_id)) in self.pc_stats.iter().enumerate() {
+        for (pc, (count, _)) in self.pc_stats.iter().enumerate() {
             if *count == 0 {
                 continue;
             }
-            pc_stats.push((pc, count, inst_id_to_name_map.get(inst_id).unwrap()));
+            pc_stats.push((pc, count));
         }
         pc_stats.sort_by(|a, b| b.1.cmp(a.1));
         for pc_stat in &pc_stats {
-            let pc_inst_string = format!("{:>08}({})", pc_stat.0 * 4, pc_stat.2);
-            print_filled_with_space(&pc_inst_string, 25);
+            let pc = (pc_stat.0 * 4) as Address;
+            let inst = self.get_decoded_instruction(pc);
+            let pc_inst_string = format!("{:>08}({})", pc, disassemble(inst, pc));
+            print_filled_with_space(&pc_inst_string, 40);
             println!("{}", pc_stat.1);
         }
     }
 
+    fn show_miss_stats(&self) {
+        if let Some(miss_profile) = &self.miss_profile {
+            miss_profile.show(|pc| {
+                let inst = self.get_decoded_instruction(pc);
+                format!("{:>08}({})", pc, disassemble(inst, pc))
+            });
+        }
+    }
+
+    fn show_branch_stats(&self) {
+        if let Some(branch_profile) = &self.branch_profile {
+            branch_profile.show(|pc| {
+                let inst = self.get_decoded_instruction(pc);
+                format!("{:>08}({})", pc, disassemble(inst, pc))
+            });
+        }
+    }
+
     fn show_memory_stats(&self) {
-        println!("memory access count: {}", self.memory_access_count);
-        println!("cache hit count: {}", self.cache_hit_count);
-        println!(
-            "cache hit rate: {:.5}%",
-            self.cache_hit_count as f64 / self.memory_access_count as f64 * 100.0
-        );
+        self.cache.show();
+        if let Some((_, prefetch_config)) = &self.prefetcher {
+            println!(
+                "prefetcher: {} (degree {})",
+                prefetch_config.kind, prefetch_config.degree
+            );
+            self.cache.show_prefetch_stats();
+        }
+        if let Some(instruction_cache) = &self.instruction_cache {
+            instruction_cache.show();
+        }
     }
 
     fn show_output_result(&self) {
@@ -522,8 +946,35 @@ impl Core {
         }
     }
 
-    fn load_sld_file(&mut self, path: &str) {
-        self.sld_vec = load_sld_file(path);
+    fn get_trace_record(&self, pc: Address, inst_id: InstructionId) -> TraceRecord {
+        TraceRecord {
+            count: self.instruction_count,
+            pc,
+            inst: self.instruction_memory.load(pc),
+            inst_id,
+            reg_write: self.reg_write,
+            mem_access: self.mem_access,
+        }
+    }
+
+    fn write_trace(&mut self, pc: Address, inst_id: InstructionId) -> Result<(), SimulatorError> {
+        let record = self.get_trace_record(pc, inst_id);
+        let tracer = self.tracer.as_mut().unwrap();
+        if tracer.is_in_window(record.count) {
+            tracer.write(&record)?;
+        }
+        Ok(())
+    }
+
+    fn compare_trace(&mut self, pc: Address, inst_id: InstructionId) {
+        let record = self.get_trace_record(pc, inst_id);
+        self.comparator.as_mut().unwrap().check(&record);
+    }
+
+    pub fn has_diverged(&self) -> bool {
+        self.comparator
+            .as_ref()
+            .is_some_and(|comparator| comparator.has_diverged())
     }
 
     pub fn end(&mut self) {
@@ -531,35 +982,260 @@ impl Core {
     }
 
     fn decode_all_instructions(&mut self) {
-        for i in 0..INSTRUCTION_MEMORY_SIZE {
-            let inst = self.load_instruction(4 * i as Address);
-            let decoded = decode_instruction(inst);
-            self.decoded_instructions.push(decoded);
+        self.decoded_instructions = (0..INSTRUCTION_MEMORY_SIZE)
+            .map(|i| decode_instruction(self.instruction_memory.load(4 * i as Address)))
+            .collect();
+    }
+
+    pub fn load_program(&mut self, insts: &[InstructionValue]) -> Result<(), SimulatorError> {
+        if insts.len() > INSTRUCTION_MEMORY_SIZE / 4 {
+            return Err(SimulatorError::ProgramTooLarge { len: insts.len() });
+        }
+        for (i, inst) in insts.iter().enumerate() {
+            self.instruction_memory.store(4 * i as Address, *inst);
         }
+        self.decode_all_instructions();
+        Ok(())
+    }
+
+    pub fn load_bin_file(&mut self, bin_file: &str) -> Result<(), SimulatorError> {
+        self.load_program(&load_bin_file(bin_file)?)
+    }
+
+    fn record_recent_pc(&mut self, pc: Address) {
+        if self.recent_pcs.len() == RECENT_PC_SIZE {
+            self.recent_pcs.pop_front();
+        }
+        self.recent_pcs.push_back(pc);
     }
 
-    fn load_bin_file(&mut self, bin_file: &str) {
-        match File::open(bin_file) {
-            Err(e) => {
-                panic!("Failed in opening file ({}).", e);
+    /// FNV-1a hash of the instruction memory, to check that a snapshot is restored into the same program.
+    fn get_program_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for i in 0..INSTRUCTION_MEMORY_SIZE {
+            for byte in self.instruction_memory.load(4 * i as Address).to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
             }
-            Ok(mut file) => {
-                let mut buf = Vec::new();
-                file.read_to_end(&mut buf).unwrap();
-                let mut inst_count = 0;
-                let mut inst = 0;
-                for byte in &buf {
-                    inst += (*byte as u32) << ((inst_count % 4) * 8);
-                    inst_count += 1;
-                    if inst_count % 4 == 0 {
-                        self.store_instruction(inst_count - 4, inst);
-                        inst = 0;
-                    }
-                }
-                if inst_count % 4 != 0 {
-                    panic!("Reading file failed.\nThe size of sum of instructions is not a multiple of 4. {}", inst_count);
-                }
+        }
+        hash
+    }
+
+    pub fn save_snapshot(&self, file_path: &str) -> Result<(), SimulatorError> {
+        let mut writer = SnapshotWriter::new();
+        writer.put_u64(self.get_program_hash());
+        writer.put_bool(self.use_cache);
+        writer.put_u32(self.pc);
+        writer.put_u128(self.instruction_count);
+        for register in self.int_registers.iter() {
+            writer.put_u32(i32_to_u32(register.get()));
+        }
+        for register in self.float_registers.iter() {
+            writer.put_u32(register.get().get_32_bits());
+        }
+        self.memory.write_snapshot(&mut writer);
+        self.cache.write_snapshot(&mut writer);
+        writer.put_bool(self.prefetcher.is_some());
+        if let Some((prefetcher, prefetch_config)) = &self.prefetcher {
+            writer.put_usize(prefetch_config.kind as usize);
+            prefetcher.write_snapshot(&mut writer);
+        }
+        writer.put_bool(self.instruction_cache.is_some());
+        if let Some(instruction_cache) = &self.instruction_cache {
+            instruction_cache.write_snapshot(&mut writer);
+        }
+        writer.put_bool(self.pipeline.is_some());
+        if let Some(pipeline) = &self.pipeline {
+            pipeline.write_snapshot(&mut writer);
+        }
+        writer.put_bool(self.superscalar.is_some());
+        if let Some(superscalar) = &self.superscalar {
+            superscalar.write_snapshot(&mut writer);
+        }
+        writer.put_bool(self.branch_predictor.is_some());
+        if let Some(branch_predictor) = &self.branch_predictor {
+            writer.put_usize(branch_predictor.get_config().kind as usize);
+            branch_predictor.write_snapshot(&mut writer);
+        }
+        writer.put_usize(self.input_device.get_position());
+        writer.put_bytes(&self.output);
+
+        for counter in [self.load_stall_counter, self.flush_counter] {
+            writer.put_usize(counter);
+        }
+        self.fpu_scoreboard.write_snapshot(&mut writer);
+        writer.put_option_usize(self.load_dest);
+        writer.put_option_usize(self.before_load_dest);
+        for counter in self
+            .int_registers_access_counter
+            .iter()
+            .chain(self.float_registers_access_counter.iter())
+            .chain(self.inst_stats.iter())
+        {
+            writer.put_usize(*counter);
+        }
+        let pc_stats = self
+            .pc_stats
+            .iter()
+            .enumerate()
+            .filter(|(_, (count, _))| *count != 0);
+        writer.put_usize(pc_stats.clone().count());
+        for (index, (count, inst_id)) in pc_stats {
+            writer.put_usize(index);
+            writer.put_usize(*count);
+            writer.put_usize(*inst_id);
+        }
+        writer.put_usize(self.recent_pcs.len());
+        for pc in self.recent_pcs.iter() {
+            writer.put_u32(*pc);
+        }
+        writer.write_to_file(file_path)
+    }
+
+    /// The reports below are not saved in snapshots, so after a restore they only cover the resumed part of the run
+    /// while the global counters cover all of it.
+    fn warn_partial_reports(&self) {
+        let reports = [
+            ("miss stats", self.miss_profile.is_some()),
+            ("branch stats", self.branch_profile.is_some()),
+            ("miss classes", self.miss_classifier.is_some()),
+            ("cache sweep", self.cache_sweep.is_some()),
+            ("watchpoints", !self.watchpoints.is_empty()),
+        ]
+        .iter()
+        .filter(|(_, is_enabled)| *is_enabled)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+        if !reports.is_empty() {
+            eprintln!(
+                "warning: {} only cover the instructions executed after the snapshot",
+                reports.join(", ")
+            );
+        }
+    }
+
+    /// Restores the state saved by `save_snapshot`; the program and the devices have to be set up beforehand.
+    pub fn load_snapshot(&mut self, file_path: &str) -> Result<(), SimulatorError> {
+        let error = |reason: String| SimulatorError::InvalidSnapshot {
+            path: file_path.to_string(),
+            reason,
+        };
+        let mut reader = SnapshotReader::from_file(file_path)?;
+        self.read_snapshot(&mut reader).map_err(error)?;
+        if !reader.is_at_end() {
+            return Err(error("trailing data".to_string()));
+        }
+        Ok(())
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        if reader.get_u64()? != self.get_program_hash() {
+            return Err("the snapshot was taken with another program".to_string());
+        }
+        if reader.get_bool()? != self.use_cache {
+            return Err("the snapshot was taken with another cache mode".to_string());
+        }
+        self.pc = reader.get_u32()?;
+        self.instruction_count = reader.get_u128()?;
+        for register in self.int_registers.iter_mut() {
+            register.set(u32_to_i32(reader.get_u32()?));
+        }
+        for register in self.float_registers.iter_mut() {
+            register.set(FloatingPoint::new(reader.get_u32()?));
+        }
+        self.memory.read_snapshot(reader)?;
+        self.cache.read_snapshot(reader)?;
+        if reader.get_bool()? != self.prefetcher.is_some() {
+            return Err("the snapshot was taken with another prefetch mode".to_string());
+        }
+        if let Some((prefetcher, prefetch_config)) = self.prefetcher.as_mut() {
+            reader.expect_usize("prefetcher kind", prefetch_config.kind as usize)?;
+            prefetcher.read_snapshot(reader)?;
+        }
+        if reader.get_bool()? != self.instruction_cache.is_some() {
+            return Err("the snapshot was taken with another icache mode".to_string());
+        }
+        if let Some(instruction_cache) = self.instruction_cache.as_mut() {
+            instruction_cache.read_snapshot(reader)?;
+        }
+        if reader.get_bool()? != self.pipeline.is_some() {
+            return Err("the snapshot was taken with another timing model".to_string());
+        }
+        if let Some(pipeline) = self.pipeline.as_mut() {
+            pipeline.read_snapshot(reader)?;
+        }
+        if reader.get_bool()? != self.superscalar.is_some() {
+            return Err("the snapshot was taken with another superscalar mode".to_string());
+        }
+        if let Some(superscalar) = self.superscalar.as_mut() {
+            superscalar.read_snapshot(reader)?;
+        }
+        if reader.get_bool()? != self.branch_predictor.is_some() {
+            return Err("the snapshot was taken with another branch prediction mode".to_string());
+        }
+        if let Some(branch_predictor) = self.branch_predictor.as_mut() {
+            reader.expect_usize(
+                "branch predictor kind",
+                branch_predictor.get_config().kind as usize,
+            )?;
+            branch_predictor.read_snapshot(reader)?;
+        }
+        let input_position = reader.get_usize()?;
+        self.input_device
+            .skip_to(input_position)
+            .map_err(|_| format!("input ended before value {}", input_position))?;
+        self.output = reader.get_bytes()?;
+        for byte in self.output.iter() {
+            self.output_device.write(*byte);
+        }
+
+        for counter in [&mut self.load_stall_counter, &mut self.flush_counter] {
+            *counter = reader.get_usize()?;
+        }
+        self.fpu_scoreboard.read_snapshot(reader)?;
+        self.load_dest = reader.get_option_usize()?;
+        self.before_load_dest = reader.get_option_usize()?;
+        for counter in self
+            .int_registers_access_counter
+            .iter_mut()
+            .chain(self.float_registers_access_counter.iter_mut())
+            .chain(self.inst_stats.iter_mut())
+        {
+            *counter = reader.get_usize()?;
+        }
+        self.pc_stats.fill((0, 0));
+        for _ in 0..reader.get_usize()? {
+            let index = reader.get_usize()?;
+            if index >= self.pc_stats.len() {
+                return Err(format!("pc stats index {} out of range", index));
             }
+            self.pc_stats[index] = (reader.get_usize()?, reader.get_usize()?);
+        }
+        self.recent_pcs.clear();
+        for _ in 0..reader.get_usize()? {
+            let pc = reader.get_u32()?;
+            self.record_recent_pc(pc);
+        }
+        Ok(())
+    }
+
+    pub fn show_error_report(&self, error: &SimulatorError) {
+        println!("---------- error ----------");
+        println!("error: {}", error);
+        if error.get_pc().is_none() {
+            return;
+        }
+        println!("executed instruction count: {}", self.instruction_count);
+        println!("---------- registers ----------");
+        self.show_registers();
+        println!("---------- last {} pcs ----------", self.recent_pcs.len());
+        for &pc in &self.recent_pcs {
+            println!(
+                "0x{:>08x}: {:>08x}  {}",
+                pc,
+                self.instruction_memory.load(pc),
+                disassemble(self.get_decoded_instruction(pc), pc)
+            );
         }
     }
 
@@ -582,17 +1258,140 @@ impl Core {
         }
     }
 
-    pub fn run(&mut self, props: CoreProps) {
+    pub fn is_finished(&self) -> bool {
+        self.pc >= INSTRUCTION_MEMORY_SIZE as Address
+    }
+
+    /// Executes one instruction and returns its id, or `None` if the program has already ended.
+    pub fn step(&mut self) -> Result<Option<InstructionId>, SimulatorError> {
+        if self.is_finished() {
+            return Ok(None);
+        }
+        self.before_load_dest = self.load_dest;
+        self.load_dest = None;
+        self.reg_write = None;
+        self.mem_access = None;
+
+        let pc = self.get_pc();
+        let instruction = self.decoded_instructions[pc as usize >> 2];
+        let stall_counts = (self.pipeline.is_some() || self.superscalar.is_some())
+            .then(|| self.get_stall_counts());
+        if let Some(instruction_cache) = self.instruction_cache.as_mut() {
+            instruction_cache.fetch(pc);
+        }
+        self.record_recent_pc(pc);
+        let inst_id = exec_instruction(instruction, self)?;
+        self.fpu_scoreboard.retire();
+        if let Some(stall_counts) = stall_counts {
+            self.issue_to_pipeline(instruction, inst_id, stall_counts);
+        }
+        if self.take_inst_stats {
+            self.update_inst_stats(inst_id);
+        }
+        if self.take_pc_stats {
+            self.update_pc_stats(pc, inst_id);
+        }
+        if self.tracer.is_some() {
+            self.write_trace(pc, inst_id)?;
+        }
+        if self.comparator.is_some() {
+            self.compare_trace(pc, inst_id);
+        }
+        self.increment_instruction_count();
+        Ok(Some(inst_id))
+    }
+
+    fn get_stall_counts(&self) -> StallCounts {
+        StallCounts {
+            fpu_op_count: self.fpu_scoreboard.get_op_count(),
+            flush_count: self.flush_counter,
+            fetch_stall_cycle_num: self.get_fetch_stall_cycle_num(),
+            memory_stall_cycle_num: self.cache.get_stall_cycle_num(),
+        }
+    }
+
+    /// `before` holds the counts from before the instruction was fetched.
+    fn issue_to_pipeline(
+        &mut self,
+        inst: Instruction,
+        inst_id: InstructionId,
+        before: StallCounts,
+    ) {
+        let after = self.get_stall_counts();
+        let is_fpu = after.fpu_op_count != before.fpu_op_count;
+        let issued = IssuedInstruction {
+            operands: get_operands(inst, inst_id),
+            is_load: self.load_dest.is_some(),
+            is_memory: self.mem_access.is_some(),
+            is_fpu,
+            is_control_transfer: is_control_transfer(inst_id),
+            fpu_stall: if is_fpu {
+                self.fpu_scoreboard.get_latency_stall(inst_id)
+            } else {
+                0
+            },
+            flushes: after.flush_count != before.flush_count,
+            fetch_stall_cycle_num: after.fetch_stall_cycle_num - before.fetch_stall_cycle_num,
+            memory_stall_cycle_num: after.memory_stall_cycle_num - before.memory_stall_cycle_num,
+        };
+        if let Some(pipeline) = self.pipeline.as_mut() {
+            pipeline.issue(&issued);
+        }
+        if let Some(superscalar) = self.superscalar.as_mut() {
+            superscalar.issue(&issued);
+        }
+    }
+
+    /// Runs until pc reaches the given address, the program ends or a watchpoint stops the execution.
+    pub fn run_until(&mut self, pc: Address) -> Result<(), SimulatorError> {
+        while self.pc != pc {
+            match self.step()? {
+                Some(inst_id) => {
+                    if self.watchpoints.has_hits() && self.watchpoints.report_hits(inst_id) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs at most `n` instructions and returns the number of executed ones.
+    /// Stops early when the program ends or a watchpoint stops the execution.
+    pub fn run_for(&mut self, n: InstructionCount) -> Result<InstructionCount, SimulatorError> {
+        let start_count = self.instruction_count;
+        while self.instruction_count - start_count < n {
+            match self.step()? {
+                Some(inst_id) => {
+                    if self.watchpoints.has_hits() && self.watchpoints.report_hits(inst_id) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(self.instruction_count - start_count)
+    }
+
+    pub fn run(&mut self, props: CoreProps) -> Result<(), SimulatorError> {
         let start_time = Instant::now();
         let mut cycle_num: u128 = 0;
 
-        let mut ppm_file = File::create(props.ppm_file_path).unwrap();
-        let mut before_output_len = 0;
-
-        self.init();
-        self.load_bin_file(&props.bin_file_path);
-        self.load_sld_file(&props.sld_file_path);
-        self.decode_all_instructions();
+        self.set_input_device(props.input_device);
+        self.set_output_device(props.output_device);
+        self.load_bin_file(&props.bin_file_path)?;
+        self.use_cache = props.use_cache;
+        self.set_cache_config(props.cache_config, props.l2_config, props.stall_config)?;
+        self.set_prefetch_config(props.prefetch_config)?;
+        self.set_icache_config(props.icache_config)?;
+        self.set_fpu_config(props.fpu_config);
+        self.set_pipeline_config(props.pipeline_config)?;
+        self.set_superscalar_config(props.superscalar_config)?;
+        self.set_branch_predictor_config(props.branch_predictor_config)?;
+        if let Some(load_snapshot_path) = &props.load_snapshot_path {
+            self.load_snapshot(load_snapshot_path)?;
+        }
 
         let pb = ProgressBar::new(props.progress_bar_size);
         pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} \n {msg}")
@@ -605,42 +1404,120 @@ impl Core {
             .build()
             .unwrap();
 
-        self.use_cache = props.use_cache;
+        self.take_inst_stats = props.take_inst_stats;
+        self.take_pc_stats = props.take_pc_stats;
 
-        loop {
-            self.before_load_dest = self.load_dest;
-            self.load_dest = None;
+        let mut debugger = if props.debug {
+            Some(Debugger::new())
+        } else {
+            None
+        };
+        for (target, condition) in props.watchpoints {
+            self.watchpoints.add(target, condition, props.watch_action);
+        }
+        if let Some(trace_file_path) = props.trace_file_path {
+            self.tracer = Some(Tracer::new(
+                &trace_file_path,
+                props.trace_format,
+                props.trace_from,
+                props.trace_to,
+            )?);
+        }
+        if let Some(compare_file_path) = props.compare_file_path {
+            self.comparator = Some(Comparator::new(
+                &compare_file_path,
+                props.compare_format,
+                props.compare_context_size,
+            )?);
+        }
+        if props.take_miss_stats && self.use_cache {
+            self.miss_profile = Some(MissProfile::new(self.cache.get_config(), props.heap_start));
+        }
+        if props.take_branch_stats {
+            self.branch_profile = Some(BranchProfile::new(FLUSH_STALL));
+        }
+        if props.classify_misses && self.use_cache {
+            self.miss_classifier = Some(MissClassifier::new(self.cache.get_config()));
+        }
+        if !props.sweep_cache_configs.is_empty() {
+            self.cache_sweep = Some(CacheSweep::new(
+                &props.sweep_cache_configs,
+                props.l2_config,
+                props.stall_config,
+            ));
+        }
+        if props.load_snapshot_path.is_some() {
+            self.warn_partial_reports();
+        }
 
+        let mut error = None;
+        loop {
             cycle_num += 1;
-            if self.get_pc() >= INSTRUCTION_MEMORY_SIZE as Address {
+            if self.is_finished() {
                 pb.finish_with_message("End of program.");
                 break;
             }
 
-            let instrucion = self.decoded_instructions[self.get_pc() as usize >> 2];
-            let pc = self.get_pc();
-            let inst_id = exec_instruction(instrucion, self);
-            if props.take_inst_stats {
-                self.update_inst_stats(inst_id);
+            if props.save_snapshot_at == Some(self.instruction_count) {
+                if let Err(e) = self.save_snapshot(&props.snapshot_file_path) {
+                    pb.finish_with_message("Error.");
+                    error = Some(e);
+                    break;
+                }
+                println!(
+                    "Saved snapshot at instruction {} to {}.",
+                    self.instruction_count, props.snapshot_file_path
+                );
             }
-            if props.take_pc_stats {
-                self.update_pc_stats(pc, inst_id);
+
+            if let Some(debugger) = debugger.as_mut() {
+                if debugger.should_stop(self) && debugger.repl(self) == DebuggerAction::Quit {
+                    pb.finish_with_message("Quit.");
+                    break;
+                }
             }
-            if cycle_num % 10000000 == 0 {
+
+            let pc = self.get_pc();
+            let inst_id = match self.step() {
+                Ok(Some(inst_id)) => inst_id,
+                Ok(None) => break,
+                Err(e) => {
+                    pb.finish_with_message("Error.");
+                    error = Some(e);
+                    break;
+                }
+            };
+            if let Some(debugger) = debugger.as_mut() {
+                debugger.after_exec(self.get_decoded_instruction(pc));
+            }
+            if cycle_num.is_multiple_of(10000000) {
                 self.show_progress(props.progress_bar_size, &pb);
             }
+            if self.comparator.as_ref().is_some_and(Comparator::is_stopped) {
+                pb.finish_with_message("Stopped comparison.");
+                break;
+            }
 
-            self.increment_instruction_count();
-
-            if before_output_len != self.output.len() {
-                for i in before_output_len..self.output.len() {
-                    let byte = [self.output[i]];
-                    ppm_file.write_all(&byte).unwrap();
+            if self.watchpoints.has_hits() && self.watchpoints.report_hits(inst_id) {
+                let debugger = debugger.get_or_insert_with(Debugger::new);
+                if debugger.stop(self) == DebuggerAction::Quit {
+                    pb.finish_with_message("Quit.");
+                    break;
                 }
-                before_output_len = self.output.len();
             }
         }
 
+        let flush_result = self.tracer.as_mut().map_or(Ok(()), Tracer::flush);
+        let output_result = self.output_device.flush();
+        if let Some(error) = error {
+            return Err(error);
+        }
+        flush_result?;
+        output_result?;
+        if let Some(comparator) = self.comparator.as_mut() {
+            comparator.finish();
+        }
+
         if let Some(prof_file_path) = props.prof_file_path {
             if let Ok(report) = guard.report().build() {
                 let file = File::create(prof_file_path).unwrap();
@@ -648,14 +1525,22 @@ impl Core {
             };
         }
 
-        let cycle_num = self.instruction_count
-            + self.flush_counter as u128 * FLUSH_STALL as u128
-            + self.load_cache_miss_count as u128 * CACHE_MISS_STALL as u128;
+        let cycle_num = self.get_predicted_cycle_num();
         let cycle_time =
             cycle_num as f64 / FREQUENCY as f64 + self.output.len() as f64 * 8. / BAUD_RATE as f64;
 
         println!("flush count: {}", self.flush_counter);
-        println!("load cache miss count: {}", self.load_cache_miss_count);
+        println!(
+            "load cache miss count: {}",
+            self.cache.get_l1_stats().load_cache_miss_count
+        );
+        if let Some(miss_classifier) = &self.miss_classifier {
+            let counts = miss_classifier.get_counts();
+            println!(
+                "cache miss classes: compulsory {}, capacity {}, conflict {}",
+                counts.compulsory, counts.capacity, counts.conflict
+            );
+        }
         println!("predicted cycle count: {}", cycle_num);
         println!("predicted execution time: {:.2}s", cycle_time);
 
@@ -666,6 +1551,19 @@ impl Core {
             self.instruction_count as f64 / start_time.elapsed().as_micros() as f64
         );
         self.show_memory_stats();
+        if let Some(branch_predictor) = &self.branch_predictor {
+            branch_predictor.show();
+        }
+        if let Some(pipeline) = &self.pipeline {
+            pipeline.show("pipeline", "analytic", self.get_analytic_cycle_num());
+        }
+        if let Some(superscalar) = &self.superscalar {
+            superscalar.show(
+                "superscalar",
+                "single-issue",
+                self.get_predicted_cycle_num(),
+            );
+        }
         self.show_fpu_stall_counter();
         self.show_load_stall_counter();
         self.show_registers_access_counter();
@@ -675,20 +1573,172 @@ impl Core {
         if props.take_pc_stats {
             self.show_pc_stats();
         }
+        if props.take_miss_stats {
+            self.show_miss_stats();
+        }
+        if props.take_branch_stats {
+            self.show_branch_stats();
+        }
         if props.show_output {
             self.show_output_result();
         }
+        if let Some(cache_sweep) = &self.cache_sweep {
+            cache_sweep.show_results(self.get_base_cycle_num());
+        }
+        Ok(())
+    }
+}
+
+impl Default for Core {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 pub struct CoreProps {
     pub take_inst_stats: bool,
     pub take_pc_stats: bool,
+    /// Attribute cache misses to pcs, memory regions and cache sets; ignored without the cache.
+    pub take_miss_stats: bool,
+    /// Lowest address of the heap, below which accesses are counted as globals in the miss stats.
+    pub heap_start: Address,
+    /// Count the outcomes and flushes of every conditional branch.
+    pub take_branch_stats: bool,
+    /// Classify cache misses into compulsory, capacity and conflict ones; ignored without the cache.
+    pub classify_misses: bool,
     pub use_cache: bool,
+    pub cache_config: CacheConfig,
+    /// Second cache level shared by the real cache and the sweep; it must be valid for every L1.
+    pub l2_config: Option<L2Config>,
+    pub stall_config: StallConfig,
+    /// Instruction cache on the fetch path; fetches cost nothing without it.
+    pub icache_config: Option<ICacheConfig>,
+    /// Prefetcher installing lines into the data cache ahead of loads.
+    pub prefetch_config: Option<PrefetchConfig>,
+    pub fpu_config: FpuConfig,
+    /// Pipeline model predicting the cycle count; the analytic model is used without it.
+    pub pipeline_config: Option<PipelineConfig>,
+    /// Superscalar core timed alongside the single-issue one, with the shape of the pipeline model or the default one.
+    pub superscalar_config: Option<SuperscalarConfig>,
+    /// Predictor of control transfers; every branch and jump flushes the pipeline without it.
+    pub branch_predictor_config: Option<BranchPredictorConfig>,
     pub show_output: bool,
+    pub debug: bool,
+    pub watchpoints: Vec<(WatchTarget, WatchCondition)>,
+    pub watch_action: WatchAction,
+    pub trace_file_path: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_from: InstructionCount,
+    pub trace_to: Option<InstructionCount>,
+    pub compare_file_path: Option<String>,
+    pub compare_format: TraceFormat,
+    pub compare_context_size: usize,
+    /// Cache configurations simulated alongside the real cache; they must be valid.
+    pub sweep_cache_configs: Vec<CacheConfig>,
     pub progress_bar_size: u64,
     pub bin_file_path: String,
-    pub ppm_file_path: String,
-    pub sld_file_path: String,
+    pub save_snapshot_at: Option<InstructionCount>,
+    pub snapshot_file_path: String,
+    pub load_snapshot_path: Option<String>,
+    pub input_device: Box<dyn InputDevice>,
+    pub output_device: Box<dyn OutputDevice>,
     pub prof_file_path: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // in x5; fin f1; addi x6, x0, 0; loop: add x6, x6, x5; addi x5, x5, -1; bne x5, x0, loop;
+    // sw x6, 16(x0); addi x7, x0, 65; outchar x7; end
+    const PROGRAM: [InstructionValue; 10] = [
+        0x000002f4, 0x000010f4, 0x00000313, 0x00530333, 0xfff28293, 0xfe029ce3, 0x00602823,
+        0x04100393, 0x00700075, 0x00000073,
+    ];
+
+    #[test]
+    fn test_stepping_api() {
+        let mut core = Core::new();
+        core.load_program(&PROGRAM).unwrap();
+        core.set_input_device(Box::new(SldInput::from_text("3 1.5")));
+
+        assert!(core.step().unwrap().is_some());
+        assert_eq!(core.peek_int_register(5), 3);
+        core.run_until(0x18).unwrap();
+        assert_eq!(core.get_pc(), 0x18);
+        assert_eq!(core.peek_int_register(6), 6);
+        assert_eq!(core.peek_float_register(1).get_f32_value(), 1.5);
+        assert_eq!(core.run_for(2), Ok(2));
+        assert_eq!(core.peek_word(16), 6);
+        core.poke_word(16, 7).unwrap();
+        assert_eq!(core.peek_word(16), 7);
+        assert_eq!(core.run_for(10), Ok(2));
+        assert!(core.is_finished());
+        assert_eq!(core.get_output(), b"A");
+        assert_eq!(core.step(), Ok(None));
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let file_path = std::env::temp_dir().join("instruction-simulator-test.snapshot");
+        let file_path = file_path.to_str().unwrap();
+        let mut core = Core::new();
+        core.load_program(&PROGRAM).unwrap();
+        core.set_input_device(Box::new(SldInput::from_text("3 1.5")));
+        core.run_for(7).unwrap();
+        core.save_snapshot(file_path).unwrap();
+
+        let mut restored = Core::new();
+        restored.load_program(&PROGRAM).unwrap();
+        restored.set_input_device(Box::new(SldInput::from_text("3 1.5")));
+        restored.load_snapshot(file_path).unwrap();
+        std::fs::remove_file(file_path).unwrap();
+        assert_eq!(restored.get_instruction_count(), 7);
+        assert_eq!(restored.get_pc(), core.get_pc());
+        core.run_for(100).unwrap();
+        restored.run_for(100).unwrap();
+        assert_eq!(restored.peek_int_register(6), 6);
+        assert_eq!(restored.peek_word(16), core.peek_word(16));
+        assert_eq!(restored.get_output(), core.get_output());
+        assert_eq!(restored.cache.get_l1_stats(), core.cache.get_l1_stats());
+    }
+
+    #[test]
+    fn test_cache_sweep_matches_core() {
+        let mut core = Core::new();
+        core.load_program(&PROGRAM).unwrap();
+        core.set_input_device(Box::new(SldInput::from_text("3 1.5")));
+        let config = CacheConfig {
+            write_policy: WritePolicy::WriteThrough,
+            write_buffer_size: 2,
+            ..CacheConfig::default()
+        };
+        let stall_config = StallConfig::default();
+        core.set_cache_config(config, None, stall_config).unwrap();
+        core.set_icache_config(Some(ICacheConfig::default()))
+            .unwrap();
+        core.cache_sweep = Some(CacheSweep::new(&[config], None, stall_config));
+        core.run_for(100).unwrap();
+        let cache_sweep = core.cache_sweep.as_ref().unwrap();
+        assert_eq!(
+            cache_sweep.get_cycle_nums(core.get_base_cycle_num()),
+            [core.get_analytic_cycle_num()]
+        );
+    }
+
+    #[test]
+    fn test_input_error() {
+        let mut core = Core::new();
+        core.load_program(&PROGRAM).unwrap();
+        core.set_input_device(Box::new(SldInput::from_text("3")));
+        assert_eq!(
+            core.run_for(2),
+            Err(SimulatorError::InvalidInput {
+                pc: 4,
+                index: 1,
+                token: None,
+                expected: "a float"
+            })
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use std::io::{self, BufRead, Write};
+
+use crate::core::*;
+use crate::decoder::*;
+use crate::disassembler::*;
+use crate::memory::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::watchpoint::*;
+
+const JAL_OP: Op = 111;
+const JALR_OP: Op = 103;
+
+#[derive(PartialEq, Eq)]
+pub enum DebuggerAction {
+    Resume,
+    Quit,
+}
+
+pub struct Debugger {
+    breakpoints: Vec<(usize, Address)>,
+    next_breakpoint_id: usize,
+    step_remaining: Option<u64>,
+    finish_depth: Option<i64>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: vec![],
+            next_breakpoint_id: 1,
+            step_remaining: Some(0),
+            finish_depth: None,
+        }
+    }
+
+    pub fn should_stop(&mut self, core: &Core) -> bool {
+        if self.step_remaining == Some(0) {
+            self.step_remaining = None;
+            return true;
+        }
+        if self.finish_depth == Some(-1) {
+            self.finish_depth = None;
+            println!("Run till exit done.");
+            return true;
+        }
+        let pc = core.get_pc();
+        if let Some((id, _)) = self.breakpoints.iter().find(|(_, addr)| *addr == pc) {
+            println!("Breakpoint {} at 0x{:>08x}", id, pc);
+            self.step_remaining = None;
+            self.finish_depth = None;
+            return true;
+        }
+        false
+    }
+
+    pub fn after_exec(&mut self, inst: Instruction) {
+        if let Some(remaining) = self.step_remaining.as_mut() {
+            *remaining -= 1;
+        }
+        if let Some(depth) = self.finish_depth.as_mut() {
+            match inst {
+                Instruction::J(_, rd, JAL_OP) if rd as usize == RA => *depth += 1,
+                Instruction::I(_, rs1, 0, rd, JALR_OP) => {
+                    if rd as usize == RA {
+                        *depth += 1;
+                    } else if rd as usize == ZERO && rs1 as usize == RA {
+                        *depth -= 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn show_location(&self, core: &Core) {
+        let pc = core.get_pc();
+        println!(
+            "[{}] 0x{:>08x}: {}",
+            core.get_instruction_count(),
+            pc,
+            disassemble(core.get_decoded_instruction(pc), pc)
+        );
+    }
+
+    /// Stops the execution regardless of the current stepping state (e.g. on a watchpoint hit).
+    pub fn stop(&mut self, core: &mut Core) -> DebuggerAction {
+        self.step_remaining = None;
+        self.finish_depth = None;
+        self.repl(core)
+    }
+
+    pub fn repl(&mut self, core: &mut Core) -> DebuggerAction {
+        self.show_location(core);
+        let stdin = io::stdin();
+        loop {
+            print!("(sim) ");
+            io::stdout().flush().unwrap();
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    println!();
+                    return DebuggerAction::Quit;
+                }
+                Ok(_) => {}
+            }
+            if let Some(action) = self.exec_command(line.trim(), core) {
+                return action;
+            }
+        }
+    }
+
+    fn exec_command(&mut self, line: &str, core: &mut Core) -> Option<DebuggerAction> {
+        let mut tokens = line.split_whitespace();
+        let command = tokens.next()?;
+        let arg = tokens.next();
+        let rest = line[command.len()..].trim();
+        match command {
+            "s" | "step" => {
+                let n = match arg.map(parse_number) {
+                    None => 1,
+                    Some(Some(n)) if n > 0 => n as u64,
+                    _ => {
+                        println!("Invalid step count: {}", arg.unwrap());
+                        return None;
+                    }
+                };
+                self.step_remaining = Some(n);
+                Some(DebuggerAction::Resume)
+            }
+            "c" | "continue" => Some(DebuggerAction::Resume),
+            "b" | "break" => {
+                match arg.and_then(parse_number) {
+                    Some(pc) if pc.is_multiple_of(4) => {
+                        let id = self.next_breakpoint_id;
+                        self.next_breakpoint_id += 1;
+                        self.breakpoints.push((id, pc));
+                        println!("Breakpoint {} at 0x{:>08x}", id, pc);
+                    }
+                    _ => println!("Usage: break <pc> (word-aligned address)"),
+                }
+                None
+            }
+            "d" | "delete" => {
+                match arg {
+                    None => self.breakpoints.clear(),
+                    Some(arg) => match parse_number(arg) {
+                        Some(id) => {
+                            let before_len = self.breakpoints.len();
+                            self.breakpoints.retain(|(i, _)| *i != id as usize);
+                            if self.breakpoints.len() == before_len {
+                                println!("No breakpoint number {}.", id);
+                            }
+                        }
+                        None => println!("Usage: delete [breakpoint id]"),
+                    },
+                }
+                None
+            }
+            "i" | "info" => {
+                match arg {
+                    Some("r") | Some("regs") | Some("registers") => {
+                        println!("pc  0x{:>08x}", core.get_pc());
+                        core.show_registers();
+                    }
+                    Some("b") | Some("break") | Some("breakpoints") => {
+                        if self.breakpoints.is_empty() {
+                            println!("No breakpoints.");
+                        }
+                        for (id, pc) in &self.breakpoints {
+                            println!("{: <4} 0x{:>08x}", id, pc);
+                        }
+                    }
+                    Some("w") | Some("watch") | Some("watchpoints") => {
+                        core.get_watchpoints().show();
+                    }
+                    _ => println!("Usage: info regs | info breakpoints | info watchpoints"),
+                }
+                None
+            }
+            "watch" | "rwatch" | "awatch" => {
+                let kind = match command {
+                    "rwatch" => WatchKind::Read,
+                    "awatch" => WatchKind::Access,
+                    _ => WatchKind::Write,
+                };
+                match parse_watch_spec(rest, kind) {
+                    Ok((target, condition)) => {
+                        let id = core
+                            .get_watchpoints()
+                            .add(target, condition, WatchAction::Stop);
+                        println!("Watchpoint {}: {}", id, rest);
+                    }
+                    Err(e) => println!("{} (usage: {} <x<n>|f<n>|addr> [if <cond>])", e, command),
+                }
+                None
+            }
+            "unwatch" => {
+                match arg {
+                    None => core.get_watchpoints().clear(),
+                    Some(arg) => match parse_number(arg) {
+                        Some(id) => {
+                            if !core.get_watchpoints().remove(id as usize) {
+                                println!("No watchpoint number {}.", id);
+                            }
+                        }
+                        None => println!("Usage: unwatch [watchpoint id]"),
+                    },
+                }
+                None
+            }
+            "finish" => {
+                self.finish_depth = Some(0);
+                Some(DebuggerAction::Resume)
+            }
+            "q" | "quit" => Some(DebuggerAction::Quit),
+            "h" | "help" => {
+                show_help();
+                None
+            }
+            _ if command.starts_with("x/") || command == "x" => {
+                let count = match command.strip_prefix("x/") {
+                    None => Some(1),
+                    Some(n) => parse_number(n),
+                };
+                match (count, arg.and_then(parse_number)) {
+                    (Some(count), Some(addr)) => examine_memory(core, addr, count),
+                    _ => println!("Usage: x/<n> <addr>"),
+                }
+                None
+            }
+            _ => {
+                println!("Unknown command: {} (try 'help')", command);
+                None
+            }
+        }
+    }
+}
+
+fn examine_memory(core: &Core, addr: Address, count: u32) {
+    if !addr.is_multiple_of(WORD_SIZE as Address) {
+        println!("Address 0x{:>08x} is not word-aligned.", addr);
+        return;
+    }
+    for i in 0..count {
+        let word_addr = addr as u64 + i as u64 * WORD_SIZE as u64;
+        if word_addr >= MEMORY_SIZE as u64 {
+            println!();
+            println!("Cannot access memory at 0x{:>08x}.", word_addr);
+            return;
+        }
+        if i % 4 == 0 {
+            if i != 0 {
+                println!();
+            }
+            print!("0x{:>08x}:", word_addr);
+        }
+        print!(" 0x{:>08x}", core.peek_word(word_addr as Address));
+    }
+    println!();
+}
+
+fn show_help() {
+    println!("step [n]          execute n instructions (default 1)");
+    println!("continue          resume until a breakpoint or the end of program");
+    println!("break <pc>        set a breakpoint at pc");
+    println!("delete [id]       delete a breakpoint (all breakpoints if no id is given)");
+    println!("info regs         show registers");
+    println!("info breakpoints  show breakpoints");
+    println!("info watchpoints  show watchpoints");
+    println!("watch <target>    stop when target is written (target: x<n>, f<n> or addr)");
+    println!("rwatch <addr>     stop when addr is read");
+    println!("awatch <addr>     stop when addr is read or written");
+    println!("  ... if <cond>   only stop if cond holds (changed, nan, inf, == <value>)");
+    println!("unwatch [id]      delete a watchpoint (all watchpoints if no id is given)");
+    println!("x/<n> <addr>      show n words of memory from addr");
+    println!("finish            resume until the current function returns");
+    println!("quit              stop the simulation");
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse::<u32>().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALL: Instruction = Instruction::J(8, RA as Rd, JAL_OP);
+    const RET: Instruction = Instruction::I(0, RA as Rs1, 0, ZERO as Rd, JALR_OP);
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("0x1c"), Some(0x1c));
+        assert_eq!(parse_number("28"), Some(28));
+        assert_eq!(parse_number("0x"), None);
+        assert_eq!(parse_number("1c"), None);
+        assert_eq!(parse_number("-4"), None);
+    }
+
+    #[test]
+    fn test_exec_command() {
+        let mut core = Core::new();
+        let mut debugger = Debugger::new();
+        assert!(debugger.should_stop(&core));
+
+        let action = debugger.exec_command("step 3", &mut core);
+        assert!(action == Some(DebuggerAction::Resume));
+        assert_eq!(debugger.step_remaining, Some(3));
+        assert!(debugger.exec_command("step 0", &mut core).is_none());
+        assert!(debugger.exec_command("step x", &mut core).is_none());
+        assert_eq!(debugger.step_remaining, Some(3));
+
+        assert!(debugger.exec_command("break 0x10", &mut core).is_none());
+        assert!(debugger.exec_command("break 0x12", &mut core).is_none());
+        assert!(debugger.exec_command("b 32", &mut core).is_none());
+        assert_eq!(debugger.breakpoints, [(1, 0x10), (2, 32)]);
+        assert!(debugger.exec_command("delete 1", &mut core).is_none());
+        assert!(debugger.exec_command("delete 5", &mut core).is_none());
+        assert_eq!(debugger.breakpoints, [(2, 32)]);
+        assert!(debugger.exec_command("delete", &mut core).is_none());
+        assert!(debugger.breakpoints.is_empty());
+
+        assert!(debugger.exec_command("x/4 0x0", &mut core).is_none());
+        assert!(debugger.exec_command("x/y 0x0", &mut core).is_none());
+        assert!(debugger.exec_command("jump 0x10", &mut core).is_none());
+        assert!(debugger.exec_command("", &mut core).is_none());
+        let action = debugger.exec_command("continue", &mut core);
+        assert!(action == Some(DebuggerAction::Resume));
+        let action = debugger.exec_command("quit", &mut core);
+        assert!(action == Some(DebuggerAction::Quit));
+    }
+
+    #[test]
+    fn test_finish_tracks_call_depth() {
+        let core = Core::new();
+        let mut debugger = Debugger::new();
+        assert!(debugger.should_stop(&core));
+        debugger.finish_depth = Some(0);
+        // a nested call and its return leave the current function running
+        for inst in [CALL, RET] {
+            debugger.after_exec(inst);
+            assert!(!debugger.should_stop(&core));
+        }
+        debugger.after_exec(RET);
+        assert!(debugger.should_stop(&core));
+        assert_eq!(debugger.finish_depth, None);
+    }
+}
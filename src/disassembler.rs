@@ -0,0 +1,135 @@
+use crate::decoder::*;
+use crate::instruction::*;
+use crate::types::*;
+
+fn x(index: u8) -> String {
+    format!("x{}", index)
+}
+
+fn f(index: u8) -> String {
+    format!("f{}", index)
+}
+
+fn target(pc: Address, offset: i32) -> String {
+    format!(
+        "0x{:>08x}",
+        (pc as i32).wrapping_add(offset << 1) as Address
+    )
+}
+
+fn unknown(inst: Instruction) -> String {
+    format!("unknown {:?}", inst)
+}
+
+pub fn disassemble(inst: Instruction, pc: Address) -> String {
+    match inst {
+        Instruction::I(imm, rs1, funct3, rd, op) => {
+            let extended_imm = sign_extention_i16(imm, 12);
+            match (op, funct3) {
+                (3, 0b010) => format!("lw {}, {}({})", x(rd), extended_imm, x(rs1)),
+                (19, 0b000) => format!("addi {}, {}, {}", x(rd), x(rs1), extended_imm),
+                (19, 0b001) => format!("slli {}, {}, {}", x(rd), x(rs1), imm & 0x1f),
+                (19, 0b101) if (imm >> 5) & 0b1111111 == 0b0100000 => {
+                    format!("srai {}, {}, {}", x(rd), x(rs1), imm & 0x1f)
+                }
+                (103, 0b000) => format!("jalr {}, {}({})", x(rd), extended_imm << 1, x(rs1)),
+                (7, 0b010) => format!("flw {}, {}({})", f(rd), extended_imm, x(rs1)),
+                (115, 0b000) => "end".to_string(),
+                (116, 0b000) => format!("in {}", x(rd)),
+                (116, 0b001) => format!("fin {}", f(rd)),
+                _ => unknown(inst),
+            }
+        }
+        Instruction::R(funct7, rs2, rs1, funct3, rd, op) => match (op, funct7, funct3) {
+            (51, 0b0000000, 0b000) => format!("add {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            (51, 0b0100000, 0b000) => format!("sub {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            (51, 0b0000000, 0b100) => format!("xor {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            (83, 0b0000000, _) => format!("fadd {}, {}, {}", f(rd), f(rs1), f(rs2)),
+            (83, 0b0000100, _) => format!("fsub {}, {}, {}", f(rd), f(rs1), f(rs2)),
+            (83, 0b0001000, _) => format!("fmul {}, {}, {}", f(rd), f(rs1), f(rs2)),
+            (83, 0b0001100, _) => format!("fdiv {}, {}, {}", f(rd), f(rs1), f(rs2)),
+            (83, 0b0101100, _) => format!("fsqrt {}, {}", f(rd), f(rs1)),
+            (83, 0b0010000, 0b000) => format!("fsgnj {}, {}, {}", f(rd), f(rs1), f(rs2)),
+            (83, 0b0010000, 0b001) => format!("fsgnjn {}, {}, {}", f(rd), f(rs1), f(rs2)),
+            (83, 0b1010000, 0b010) => format!("feq {}, {}, {}", x(rd), f(rs1), f(rs2)),
+            (83, 0b1010000, 0b001) => format!("flt {}, {}, {}", x(rd), f(rs1), f(rs2)),
+            (83, 0b1010000, 0b000) => format!("fle {}, {}, {}", x(rd), f(rs1), f(rs2)),
+            (83, 0b1100000, _) => format!("fcvt.w.s {}, {}", x(rd), f(rs1)),
+            (83, 0b1101000, _) => format!("fcvt.s.w {}, {}", f(rd), x(rs1)),
+            _ => unknown(inst),
+        },
+        Instruction::S(imm, rs2, rs1, funct3, op) => {
+            let extended_imm = sign_extention_i16(imm, 12);
+            match (op, funct3) {
+                (35, 0b010) => format!("sw {}, {}({})", x(rs2), extended_imm, x(rs1)),
+                (39, 0b010) => format!("fsw {}, {}({})", f(rs2), extended_imm, x(rs1)),
+                (117, 0b000) => format!("outchar {}", x(rs2)),
+                _ => unknown(inst),
+            }
+        }
+        Instruction::B(imm, rs2, rs1, funct3, op) => {
+            let target = target(pc, sign_extention_i16(imm, 12) as i32);
+            let (name, reg): (&str, fn(u8) -> String) = match (op, funct3) {
+                (99, 0b000) => ("beq", x),
+                (99, 0b001) => ("bne", x),
+                (99, 0b100) => ("blt", x),
+                (99, 0b101) => ("bge", x),
+                (100, 0b000) => ("fbeq", f),
+                (100, 0b001) => ("fbne", f),
+                (100, 0b100) => ("fblt", f),
+                (100, 0b101) => ("fble", f),
+                _ => return unknown(inst),
+            };
+            format!("{} {}, {}, {}", name, reg(rs1), reg(rs2), target)
+        }
+        Instruction::J(imm, rd, 111) => {
+            format!("jal {}, {}", x(rd), target(pc, sign_extention_i32(imm, 20)))
+        }
+        Instruction::U(imm, rd, 55) => format!("lui {}, 0x{:x}", x(rd), imm),
+        _ => unknown(inst),
+    }
+}
+
+pub fn show_disassembly(insts: &[InstructionValue]) {
+    for (i, inst) in insts.iter().enumerate() {
+        let pc = 4 * i as Address;
+        println!(
+            "{:>08x}: {:>08x}  {}",
+            pc,
+            inst,
+            disassemble(decode_instruction(*inst), pc)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disassemble_value(inst: InstructionValue, pc: Address) -> String {
+        disassemble(decode_instruction(inst), pc)
+    }
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(disassemble_value(0xffc10113, 0), "addi x2, x2, -4");
+        assert_eq!(disassemble_value(0x00012083, 0), "lw x1, 0(x2)");
+        assert_eq!(disassemble_value(0xfe612c23, 0), "sw x6, -8(x2)");
+        assert_eq!(disassemble_value(0x00008067, 0), "jalr x0, 0(x1)");
+        assert_eq!(
+            disassemble_value(0x0002d863, 0x10),
+            "bge x5, x0, 0x00000020"
+        );
+        assert_eq!(disassemble_value(0xfedff06f, 0x24), "jal x0, 0x00000010");
+        assert_eq!(disassemble_value(0x038000ef, 0x2c), "jal x1, 0x00000064");
+        assert_eq!(disassemble_value(0x002081d3, 0), "fadd f3, f1, f2");
+        assert_eq!(disassemble_value(0xc0010653, 0), "fcvt.w.s x12, f2");
+        assert_eq!(
+            disassemble_value(0x0020cc64, 0x58),
+            "fblt f1, f2, 0x00000070"
+        );
+        assert_eq!(disassemble_value(0x12345537, 0), "lui x10, 0x12345");
+        assert_eq!(disassemble_value(0x00000073, 0), "end");
+        assert_eq!(disassemble_value(0x0000127f, 0), "unknown Other");
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionField {
+    Op,
+    Funct3,
+    Funct7,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SimulatorError {
+    InvalidInstruction {
+        pc: Address,
+        inst: InstructionValue,
+        field: InstructionField,
+        value: u32,
+    },
+    MemoryOutOfRange {
+        pc: Address,
+        addr: Address,
+    },
+    InvalidBinFile {
+        path: String,
+        reason: String,
+    },
+    ProgramTooLarge {
+        len: usize,
+    },
+    /// A file or stream the simulator reads or writes failed, on opening it or later.
+    DeviceUnavailable {
+        path: String,
+        reason: String,
+    },
+    InvalidSldFile {
+        path: String,
+        reason: String,
+    },
+    InvalidSnapshot {
+        path: String,
+        reason: String,
+    },
+    InvalidConfig {
+        path: Option<String>,
+        reason: String,
+    },
+    InvalidInput {
+        pc: Address,
+        index: usize,
+        token: Option<String>,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for InstructionField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstructionField::Op => write!(f, "op"),
+            InstructionField::Funct3 => write!(f, "funct3"),
+            InstructionField::Funct7 => write!(f, "funct7"),
+        }
+    }
+}
+
+impl fmt::Display for SimulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulatorError::InvalidInstruction {
+                pc,
+                inst,
+                field,
+                value,
+            } => write!(
+                f,
+                "invalid instruction 0x{:>08x} at pc 0x{:>08x} (unexpected {}: 0b{:b})",
+                inst, pc, field, value
+            ),
+            SimulatorError::MemoryOutOfRange { pc, addr } => write!(
+                f,
+                "memory access out of range at pc 0x{:>08x} (address 0x{:>08x})",
+                pc, addr
+            ),
+            SimulatorError::InvalidBinFile { path, reason } => {
+                write!(f, "failed in loading {} ({})", path, reason)
+            }
+            SimulatorError::ProgramTooLarge { len } => write!(
+                f,
+                "program of {} instructions does not fit in instruction memory",
+                len
+            ),
+            SimulatorError::DeviceUnavailable { path, reason } => {
+                write!(f, "failed in accessing {} ({})", path, reason)
+            }
+            SimulatorError::InvalidSldFile { path, reason } => {
+                write!(f, "failed in converting {} ({})", path, reason)
+            }
+            SimulatorError::InvalidSnapshot { path, reason } => {
+                write!(f, "failed in handling snapshot {} ({})", path, reason)
+            }
+            SimulatorError::InvalidConfig {
+                path: Some(path),
+                reason,
+            } => write!(f, "invalid configuration in {} ({})", path, reason),
+            SimulatorError::InvalidConfig { path: None, reason } => {
+                write!(f, "invalid configuration ({})", reason)
+            }
+            SimulatorError::InvalidInput {
+                pc,
+                index,
+                token: Some(token),
+                expected,
+            } => write!(
+                f,
+                "invalid input at pc 0x{:>08x} (token {} \"{}\" is not {})",
+                pc, index, token, expected
+            ),
+            SimulatorError::InvalidInput {
+                pc,
+                index,
+                token: None,
+                expected,
+            } => write!(
+                f,
+                "input exhausted at pc 0x{:>08x} (expected {} as token {})",
+                pc, expected, index
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SimulatorError {}
+
+impl SimulatorError {
+    pub fn get_pc(&self) -> Option<Address> {
+        match self {
+            SimulatorError::InvalidInstruction { pc, .. }
+            | SimulatorError::MemoryOutOfRange { pc, .. }
+            | SimulatorError::InvalidInput { pc, .. } => Some(*pc),
+            SimulatorError::InvalidBinFile { .. }
+            | SimulatorError::ProgramTooLarge { .. }
+            | SimulatorError::DeviceUnavailable { .. }
+            | SimulatorError::InvalidSldFile { .. }
+            | SimulatorError::InvalidSnapshot { .. }
+            | SimulatorError::InvalidConfig { .. } => None,
+        }
+    }
+
+    /// Exit code of the simulator process; 1 is used for trace divergence and 2 by clap.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SimulatorError::InvalidInstruction { .. } => 3,
+            SimulatorError::MemoryOutOfRange { .. } => 4,
+            SimulatorError::InvalidBinFile { .. } | SimulatorError::ProgramTooLarge { .. } => 5,
+            SimulatorError::InvalidInput { .. } | SimulatorError::InvalidSldFile { .. } => 6,
+            SimulatorError::DeviceUnavailable { .. } => 7,
+            SimulatorError::InvalidSnapshot { .. } => 8,
+            SimulatorError::InvalidConfig { .. } => 9,
+        }
+    }
+}
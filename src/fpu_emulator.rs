@@ -59,6 +59,16 @@ impl FloatingPoint {
     pub fn get_32_bits(&self) -> u32 {
         self.value
     }
+
+    pub fn is_nan(&self) -> bool {
+        let (_, e, m) = self.get_1_8_23_bits();
+        e == 255 && m != 0
+    }
+
+    pub fn is_infinite(&self) -> bool {
+        let (_, e, m) = self.get_1_8_23_bits();
+        e == 255 && m == 0
+    }
 }
 
 fn to_n_bits_u32(num: u32, n: u32) -> u32 {
@@ -434,7 +444,7 @@ pub fn fp_to_int(this: FloatingPoint) -> Int {
 
 pub fn int_to_fp(x: Int) -> FloatingPoint {
     if USE_OUR_FPU {
-        if x == std::i32::MIN {
+        if x == i32::MIN {
             return FloatingPoint { value: 0xcf000000 };
         }
         if x == 0 {
@@ -806,7 +816,7 @@ mod tests {
                 for m in min_m..=max_m {
                     let op = (s << 31) + (e << 23) + m;
                     let float = f32::from_bits(op) as f64;
-                    if float < std::i32::MIN as f64 || float > std::i32::MAX as f64 {
+                    if float < i32::MIN as f64 || float > i32::MAX as f64 {
                         continue;
                     }
                     let fp = FloatingPoint::new(op);
@@ -822,13 +832,11 @@ mod tests {
     use float_next_after::NextAfter;
     #[test]
     fn test_int_to_fp() {
-        for x in std::i32::MIN..=std::i32::MAX {
+        for x in i32::MIN..=i32::MAX {
             if x % 1000000 == 0 {
                 print!(
                     "\r{:.0}%",
-                    (x as f32 - std::i32::MIN as f32)
-                        / (std::i32::MAX as f32 - std::i32::MIN as f32 + 1.)
-                        * 100.0
+                    (x as f32 - i32::MIN as f32) / (i32::MAX as f32 - i32::MIN as f32 + 1.) * 100.0
                 );
                 stdout().flush().unwrap();
             }
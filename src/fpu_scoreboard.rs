@@ -0,0 +1,246 @@
+use serde::Deserialize;
+
+use crate::snapshot::*;
+use crate::types::*;
+use crate::utils::*;
+
+const REGISTER_NUM: usize = 64;
+const OP_ID_NUM: usize = 256;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FpuConfig {
+    /// Whether the FPU starts an operation every cycle; otherwise an operation waits for the previous one to finish.
+    pub pipelined: bool,
+    /// Whether the predicted cycle count includes the stalls charged by the scoreboard, which are reported either way.
+    pub count_stalls: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct FpuOpStats {
+    count: usize,
+    /// Cycles the operation takes beyond the first one.
+    latency_stall: usize,
+    /// Cycles instructions waited for results of the operation.
+    dependency_stall_cycle_num: u128,
+    /// Cycles operations waited for the FPU to finish this one.
+    structural_stall_cycle_num: u128,
+}
+
+/// Tracks the pending results of the FPU to charge stalls only to the instructions that have to wait for them.
+pub struct FpuScoreboard {
+    config: FpuConfig,
+    /// Cycle the pending result of each register is ready at and the operation producing it,
+    /// numbered as in `Operands`.
+    pending_results: [Option<(u128, InstructionId)>; REGISTER_NUM],
+    /// Cycle the FPU takes the next operation at and the operation it is busy with.
+    busy_until: Option<(u128, InstructionId)>,
+    /// Longest wait of the current instruction, the operation it waits for and whether it waits for the FPU itself.
+    wait: Option<(u128, InstructionId, bool)>,
+    stall_cycle_num: u128,
+    op_count: usize,
+    op_stats: Vec<FpuOpStats>,
+}
+
+impl FpuScoreboard {
+    pub fn new(config: FpuConfig) -> Self {
+        FpuScoreboard {
+            config,
+            pending_results: [None; REGISTER_NUM],
+            busy_until: None,
+            wait: None,
+            stall_cycle_num: 0,
+            op_count: 0,
+            op_stats: vec![FpuOpStats::default(); OP_ID_NUM],
+        }
+    }
+
+    pub fn get_config(&self) -> FpuConfig {
+        self.config
+    }
+
+    pub fn get_stall_cycle_num(&self) -> u128 {
+        self.stall_cycle_num
+    }
+
+    pub fn get_op_count(&self) -> usize {
+        self.op_count
+    }
+
+    /// Cycles `op` takes beyond the first one, as given when it last started.
+    pub fn get_latency_stall(&self, op: InstructionId) -> usize {
+        self.op_stats[op].latency_stall
+    }
+
+    pub fn is_pending(&self, register: usize) -> bool {
+        self.pending_results[register].is_some()
+    }
+
+    fn wait_until(&mut self, cycle: u128, op: InstructionId, is_structural: bool, now: u128) {
+        if cycle > now && self.wait.is_none_or(|(wait, _, _)| cycle - now > wait) {
+            self.wait = Some((cycle - now, op, is_structural));
+        }
+    }
+
+    /// Called when the current instruction reads `register` at cycle `now`.
+    pub fn read(&mut self, register: usize, now: u128) {
+        if let Some((ready_cycle, op)) = self.pending_results[register] {
+            self.wait_until(ready_cycle, op, false, now);
+        }
+    }
+
+    /// Called when the current instruction writes `register` without the FPU.
+    pub fn write(&mut self, register: usize) {
+        self.pending_results[register] = None;
+    }
+
+    /// Called when the current instruction starts `op` on the FPU at cycle `now` after reading its operands.
+    pub fn start(
+        &mut self,
+        op: InstructionId,
+        latency_stall: usize,
+        dest: Option<usize>,
+        now: u128,
+    ) {
+        if let (false, Some((free_cycle, busy_op))) = (self.config.pipelined, self.busy_until) {
+            self.wait_until(free_cycle, busy_op, true, now);
+        }
+        let start_cycle = now + self.wait.map_or(0, |(wait, _, _)| wait);
+        let end_cycle = start_cycle + 1 + latency_stall as u128;
+        if let Some(dest) = dest {
+            self.pending_results[dest] = Some((end_cycle, op));
+        }
+        self.busy_until = Some((end_cycle, op));
+        self.op_count += 1;
+        let stats = &mut self.op_stats[op];
+        stats.count += 1;
+        stats.latency_stall = latency_stall;
+    }
+
+    /// Charges the wait of the instruction that has just been executed.
+    pub fn retire(&mut self) {
+        if let Some((wait, op, is_structural)) = self.wait.take() {
+            self.stall_cycle_num += wait;
+            let stats = &mut self.op_stats[op];
+            if is_structural {
+                stats.structural_stall_cycle_num += wait;
+            } else {
+                stats.dependency_stall_cycle_num += wait;
+            }
+        }
+    }
+
+    /// `get_op_name` gives the name of an operation from its instruction id.
+    pub fn show(&self, get_op_name: impl Fn(InstructionId) -> String) {
+        print_filled_with_space(&"fpu op".to_string(), 8);
+        println!(
+            " {:>10} {:>8} {:>12} {:>12}",
+            "count", "latency", "dependency", "structural"
+        );
+        for (op, stats) in self.op_stats.iter().enumerate() {
+            if stats.count == 0 {
+                continue;
+            }
+            print_filled_with_space(&get_op_name(op), 8);
+            println!(
+                " {:>10} {:>8} {:>12} {:>12}",
+                stats.count,
+                stats.latency_stall + 1,
+                stats.dependency_stall_cycle_num,
+                stats.structural_stall_cycle_num
+            );
+        }
+    }
+
+    fn write_pending(writer: &mut SnapshotWriter, pending: Option<(u128, InstructionId)>) {
+        writer.put_bool(pending.is_some());
+        if let Some((cycle, op)) = pending {
+            writer.put_u128(cycle);
+            writer.put_usize(op);
+        }
+    }
+
+    fn read_pending(reader: &mut SnapshotReader) -> Result<Option<(u128, InstructionId)>, String> {
+        if !reader.get_bool()? {
+            return Ok(None);
+        }
+        let cycle = reader.get_u128()?;
+        let op = reader.get_usize()?;
+        if op >= OP_ID_NUM {
+            return Err(format!("fpu op {} out of range", op));
+        }
+        Ok(Some((cycle, op)))
+    }
+
+    pub fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        for pending in self.pending_results.iter() {
+            Self::write_pending(writer, *pending);
+        }
+        Self::write_pending(writer, self.busy_until);
+        writer.put_u128(self.stall_cycle_num);
+        writer.put_usize(self.op_count);
+        for stats in self.op_stats.iter() {
+            writer.put_usize(stats.count);
+            writer.put_usize(stats.latency_stall);
+            writer.put_u128(stats.dependency_stall_cycle_num);
+            writer.put_u128(stats.structural_stall_cycle_num);
+        }
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        for pending in self.pending_results.iter_mut() {
+            *pending = Self::read_pending(reader)?;
+        }
+        self.busy_until = Self::read_pending(reader)?;
+        self.wait = None;
+        self.stall_cycle_num = reader.get_u128()?;
+        self.op_count = reader.get_usize()?;
+        for stats in self.op_stats.iter_mut() {
+            stats.count = reader.get_usize()?;
+            stats.latency_stall = reader.get_usize()?;
+            stats.dependency_stall_cycle_num = reader.get_u128()?;
+            stats.structural_stall_cycle_num = reader.get_u128()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FDIV: InstructionId = 12;
+    const FADD: InstructionId = 9;
+
+    /// Runs `fdiv f1, f2, f3; fadd f4, f5, f6; fadd f7, f1, f4` one instruction per cycle.
+    fn run(config: FpuConfig) -> FpuScoreboard {
+        let mut scoreboard = FpuScoreboard::new(config);
+        scoreboard.start(FDIV, 10, Some(33), 0);
+        scoreboard.retire();
+        let now = 1 + scoreboard.get_stall_cycle_num();
+        scoreboard.start(FADD, 2, Some(36), now);
+        scoreboard.retire();
+        let now = 2 + scoreboard.get_stall_cycle_num();
+        scoreboard.read(33, now);
+        scoreboard.read(36, now);
+        scoreboard.start(FADD, 2, Some(39), now);
+        scoreboard.retire();
+        scoreboard
+    }
+
+    #[test]
+    fn test_fpu_scoreboard() {
+        // the first fadd waits for fdiv to leave the FPU, and the second one for the first
+        let scoreboard = run(FpuConfig::default());
+        assert_eq!(scoreboard.op_stats[FDIV].structural_stall_cycle_num, 10);
+        assert_eq!(scoreboard.op_stats[FADD].dependency_stall_cycle_num, 2);
+        assert_eq!(scoreboard.get_stall_cycle_num(), 12);
+        // only the second fadd waits, for fdiv
+        let scoreboard = run(FpuConfig {
+            pipelined: true,
+            ..FpuConfig::default()
+        });
+        assert_eq!(scoreboard.op_stats[FDIV].dependency_stall_cycle_num, 9);
+        assert_eq!(scoreboard.get_stall_cycle_num(), 9);
+    }
+}
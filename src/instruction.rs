@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 
+use crate::branch_predictor::*;
 use crate::core::*;
 use crate::decoder::*;
+use crate::error::*;
 use crate::fpu_emulator::*;
 use crate::types::*;
 use crate::utils::*;
@@ -79,7 +81,20 @@ pub fn sign_extention_i32(value: i32, before_bit: usize) -> i32 {
     }
 }
 
-pub fn exec_instruction(inst: Instruction, core: &mut Core) -> InstructionId {
+fn invalid_instruction(core: &mut Core, field: InstructionField, value: u32) -> SimulatorError {
+    let pc = core.get_pc();
+    SimulatorError::InvalidInstruction {
+        pc,
+        inst: core.load_instruction(pc),
+        field,
+        value,
+    }
+}
+
+pub fn exec_instruction(
+    inst: Instruction,
+    core: &mut Core,
+) -> Result<InstructionId, SimulatorError> {
     match inst {
         Instruction::I(imm, rs1, funct3, rd, op) => {
             exec_i_instruction(imm, rs1, funct3, rd, op, core)
@@ -99,7 +114,8 @@ pub fn exec_instruction(inst: Instruction, core: &mut Core) -> InstructionId {
             exec_r4_instruction(fs3, funct2, fs2, fs1, funct3, rd, op, core)
         }
         Instruction::Other => {
-            panic!("unexpected instruction: {:?}", inst);
+            let op = core.load_instruction(core.get_pc()) & 0x7f;
+            Err(invalid_instruction(core, InstructionField::Op, op))
         }
     }
 }
@@ -111,21 +127,25 @@ pub fn exec_i_instruction(
     rd: Rd,
     op: Op,
     core: &mut Core,
-) -> InstructionId {
-    match op {
+) -> Result<InstructionId, SimulatorError> {
+    Ok(match op {
         3 => match funct3 {
             0b010 => {
                 // lw
                 let extended_imm = sign_extention_i16(imm, 12) as i32;
                 let addr = (core.get_int_register(rs1 as usize) + extended_imm) as Address;
-                let value = core.load_word(addr) as Int;
+                let value = core.load_word(addr)? as Int;
                 core.set_int_register(rd as usize, value);
                 core.increment_pc();
                 core.set_load_dest(rd as usize);
                 LW
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3);
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         19 => match funct3 {
@@ -157,12 +177,20 @@ pub fn exec_i_instruction(
                         SRAI
                     }
                     _ => {
-                        panic!("unexpected funct7: {}", funct7);
+                        return Err(invalid_instruction(
+                            core,
+                            InstructionField::Funct7,
+                            funct7 as u32,
+                        ));
                     }
                 }
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3);
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         103 => match funct3 {
@@ -172,12 +200,20 @@ pub fn exec_i_instruction(
                 let jump_address =
                     (core.get_int_register(rs1 as usize) + (extended_imm << 1)) as Address;
                 core.set_int_register(rd as usize, core.get_pc() as Int + 4);
+                core.resolve_control_transfer(ControlTransfer::IndirectJump {
+                    target: jump_address,
+                    is_call: rd as usize == RA,
+                    is_return: rs1 as usize == RA && rd as usize == ZERO,
+                });
                 core.set_pc(jump_address);
-                core.increment_flush_counter();
                 JALR
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3);
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         7 => match funct3 {
@@ -185,14 +221,18 @@ pub fn exec_i_instruction(
                 // flw
                 let extended_imm = sign_extention_i16(imm, 12) as i32;
                 let addr = (core.get_int_register(rs1 as usize) + extended_imm) as Address;
-                let value = FloatingPoint::new(i32_to_u32(core.load_word(addr)));
+                let value = FloatingPoint::new(i32_to_u32(core.load_word(addr)?));
                 core.set_float_register(rd as usize, value);
                 core.increment_pc();
                 core.set_load_dest(rd as usize + 32);
                 FLW
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3)
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         115 => match funct3 {
@@ -202,32 +242,40 @@ pub fn exec_i_instruction(
                 END
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3)
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         116 => match funct3 {
             0b000 => {
                 // in
-                let value = core.read_int();
+                let value = core.read_int()?;
                 core.set_int_register(rd as usize, value);
                 core.increment_pc();
                 IN
             }
             0b001 => {
                 // fin
-                let value = core.read_float();
+                let value = core.read_float()?;
                 core.set_float_register(rd as usize, FloatingPoint::new(i32_to_u32(value)));
                 core.increment_pc();
                 FIN
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3)
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         _ => {
-            panic!("unexpected op: {}", op);
+            return Err(invalid_instruction(core, InstructionField::Op, op as u32));
         }
-    }
+    })
 }
 
 fn exec_r_instruction(
@@ -238,8 +286,8 @@ fn exec_r_instruction(
     rd: Rd,
     op: Op,
     core: &mut Core,
-) -> InstructionId {
-    match op {
+) -> Result<InstructionId, SimulatorError> {
+    Ok(match op {
         51 => match funct3 {
             0b000 => match funct7 {
                 0b0000000 => {
@@ -259,7 +307,11 @@ fn exec_r_instruction(
                     SUB
                 }
                 _ => {
-                    panic!("unexpected funct7: {}", funct7);
+                    return Err(invalid_instruction(
+                        core,
+                        InstructionField::Funct7,
+                        funct7 as u32,
+                    ));
                 }
             },
             0b100 => match funct7 {
@@ -272,11 +324,19 @@ fn exec_r_instruction(
                     XOR
                 }
                 _ => {
-                    panic!("unexpected funct7: {}", funct7);
+                    return Err(invalid_instruction(
+                        core,
+                        InstructionField::Funct7,
+                        funct7 as u32,
+                    ));
                 }
             },
             _ => {
-                panic!("unexpected funct3: {}", funct3);
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         83 => match funct7 {
@@ -286,7 +346,7 @@ fn exec_r_instruction(
                     core.get_float_register(rs1 as usize) + core.get_float_register(rs2 as usize);
                 core.set_float_register(rd as usize, value);
                 core.increment_pc();
-                core.increment_fpu_stall_counter(FADD_STALL);
+                core.start_fpu_operation(FADD, FADD_STALL);
                 FADD
             }
             0b0000100 => {
@@ -295,7 +355,7 @@ fn exec_r_instruction(
                     core.get_float_register(rs1 as usize) - core.get_float_register(rs2 as usize);
                 core.set_float_register(rd as usize, value);
                 core.increment_pc();
-                core.increment_fpu_stall_counter(FSUB_STALL);
+                core.start_fpu_operation(FSUB, FSUB_STALL);
                 FSUB
             }
             0b0001000 => {
@@ -304,7 +364,7 @@ fn exec_r_instruction(
                     core.get_float_register(rs1 as usize) * core.get_float_register(rs2 as usize);
                 core.set_float_register(rd as usize, value);
                 core.increment_pc();
-                core.increment_fpu_stall_counter(FMUL_STALL);
+                core.start_fpu_operation(FMUL, FMUL_STALL);
                 FMUL
             }
             0b0001100 => {
@@ -316,7 +376,7 @@ fn exec_r_instruction(
                 );
                 core.set_float_register(rd as usize, value);
                 core.increment_pc();
-                core.increment_fpu_stall_counter(FDIV_STALL);
+                core.start_fpu_operation(FDIV, FDIV_STALL);
                 FDIV
             }
             0b0101100 => {
@@ -324,7 +384,7 @@ fn exec_r_instruction(
                 let value = sqrt_fp(core.get_float_register(rs1 as usize), core.get_sqrt_map());
                 core.set_float_register(rd as usize, value);
                 core.increment_pc();
-                core.increment_fpu_stall_counter(FSQRT_STALL);
+                core.start_fpu_operation(FSQRT, FSQRT_STALL);
                 FSQRT
             }
             0b0010000 => match funct3 {
@@ -349,11 +409,19 @@ fn exec_r_instruction(
                     FSGNJN
                 }
                 _ => {
-                    panic!("unexpected funct3: {}", funct3)
+                    return Err(invalid_instruction(
+                        core,
+                        InstructionField::Funct3,
+                        funct3 as u32,
+                    ));
                 }
             },
             0b0010100 => {
-                panic!("unexpected funct7: {}", funct7)
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct7,
+                    funct7 as u32,
+                ));
             }
             0b1010000 => match funct3 {
                 0b010 => {
@@ -367,7 +435,7 @@ fn exec_r_instruction(
                     };
                     core.set_int_register(rd as usize, value);
                     core.increment_pc();
-                    core.increment_fpu_stall_counter(FEQ_STALL);
+                    core.start_fpu_operation(FEQ, FEQ_STALL);
                     FEQ
                 }
                 0b001 => {
@@ -381,7 +449,7 @@ fn exec_r_instruction(
                     };
                     core.set_int_register(rd as usize, value);
                     core.increment_pc();
-                    core.increment_fpu_stall_counter(FLT_STALL);
+                    core.start_fpu_operation(FLT, FLT_STALL);
                     FLT
                 }
                 0b000 => {
@@ -395,11 +463,15 @@ fn exec_r_instruction(
                     };
                     core.set_int_register(rd as usize, value);
                     core.increment_pc();
-                    core.increment_fpu_stall_counter(FLE_STALL);
+                    core.start_fpu_operation(FLE, FLE_STALL);
                     FLE
                 }
                 _ => {
-                    panic!("unexpected funct3: {}", funct3)
+                    return Err(invalid_instruction(
+                        core,
+                        InstructionField::Funct3,
+                        funct3 as u32,
+                    ));
                 }
             },
             0b1100000 => {
@@ -407,7 +479,7 @@ fn exec_r_instruction(
                 let value = fp_to_int(core.get_float_register(rs1 as usize));
                 core.set_int_register(rd as usize, value);
                 core.increment_pc();
-                core.increment_fpu_stall_counter(FCVTWS_STALL);
+                core.start_fpu_operation(FCVTWS, FCVTWS_STALL);
                 FCVTWS
             }
             0b1101000 => {
@@ -415,17 +487,21 @@ fn exec_r_instruction(
                 let value = int_to_fp(core.get_int_register(rs1 as usize));
                 core.set_float_register(rd as usize, value);
                 core.increment_pc();
-                core.increment_fpu_stall_counter(FCVTSW_STALL);
+                core.start_fpu_operation(FCVTSW, FCVTSW_STALL);
                 FCVTSW
             }
             _ => {
-                panic!("unexpected funct7: {}", funct7)
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct7,
+                    funct7 as u32,
+                ));
             }
         },
         _ => {
-            panic!("unexpected op: {}", op);
+            return Err(invalid_instruction(core, InstructionField::Op, op as u32));
         }
-    }
+    })
 }
 
 fn exec_s_instruction(
@@ -435,20 +511,24 @@ fn exec_s_instruction(
     funct3: Funct3,
     op: Op,
     core: &mut Core,
-) -> InstructionId {
-    match op {
+) -> Result<InstructionId, SimulatorError> {
+    Ok(match op {
         35 => match funct3 {
             // sw
             0b010 => {
                 let extended_imm = sign_extention_i16(imm, 12) as i32;
                 let addr = (core.get_int_register(rs1 as usize) + extended_imm) as Address;
                 let rs2_value = core.get_int_register(rs2 as usize);
-                core.store_word(addr, rs2_value as Word);
+                core.store_word(addr, rs2_value as Word)?;
                 core.increment_pc();
                 SW
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3);
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         39 => match funct3 {
@@ -457,12 +537,16 @@ fn exec_s_instruction(
                 let extended_imm = sign_extention_i16(imm, 12) as i32;
                 let addr = (core.get_int_register(rs1 as usize) + extended_imm) as Address;
                 let rs2_value = core.get_float_register(rs2 as usize);
-                core.store_word(addr, u32_to_i32(rs2_value.get_32_bits()));
+                core.store_word(addr, u32_to_i32(rs2_value.get_32_bits()))?;
                 core.increment_pc();
                 FSW
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3)
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         117 => match funct3 {
@@ -474,12 +558,27 @@ fn exec_s_instruction(
                 OUTCHAR
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3)
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         _ => {
-            panic!("unexpected op: {}", op);
+            return Err(invalid_instruction(core, InstructionField::Op, op as u32));
         }
+    })
+}
+
+fn branch(imm: Imm12, is_taken: bool, core: &mut Core) {
+    let extended_imm = sign_extention_i16(imm, 12) as i32;
+    let target = core.get_pc().wrapping_add((extended_imm << 1) as Address);
+    core.resolve_control_transfer(ControlTransfer::Branch { target, is_taken });
+    if is_taken {
+        core.set_pc(target);
+    } else {
+        core.increment_pc();
     }
 }
 
@@ -490,131 +589,120 @@ fn exec_b_instruction(
     funct3: Funct3,
     op: Op,
     core: &mut Core,
-) -> InstructionId {
-    match op {
+) -> Result<InstructionId, SimulatorError> {
+    Ok(match op {
         99 => match funct3 {
             0b000 => {
                 // beq
-                let extended_imm = sign_extention_i16(imm, 12) as i32;
-                if core.get_int_register(rs1 as usize) == core.get_int_register(rs2 as usize) {
-                    core.set_pc(core.get_pc() + (extended_imm << 1) as Address);
-                } else {
-                    core.increment_pc();
-                }
-                core.increment_flush_counter();
+                let is_taken =
+                    core.get_int_register(rs1 as usize) == core.get_int_register(rs2 as usize);
+                branch(imm, is_taken, core);
                 BEQ
             }
             0b001 => {
                 // bne
-                let extended_imm = sign_extention_i16(imm, 12) as i32;
-                if core.get_int_register(rs1 as usize) != core.get_int_register(rs2 as usize) {
-                    core.set_pc(core.get_pc() + (extended_imm << 1) as Address);
-                } else {
-                    core.increment_pc();
-                }
-                core.increment_flush_counter();
+                let is_taken =
+                    core.get_int_register(rs1 as usize) != core.get_int_register(rs2 as usize);
+                branch(imm, is_taken, core);
                 BNE
             }
             0b100 => {
                 // blt
-                let extended_imm = sign_extention_i16(imm, 12) as i32;
-                if core.get_int_register(rs1 as usize) < core.get_int_register(rs2 as usize) {
-                    core.set_pc(core.get_pc() + (extended_imm << 1) as Address);
-                } else {
-                    core.increment_pc();
-                }
-                core.increment_flush_counter();
+                let is_taken =
+                    core.get_int_register(rs1 as usize) < core.get_int_register(rs2 as usize);
+                branch(imm, is_taken, core);
                 BLT
             }
             0b101 => {
                 // bge
-                let extended_imm = sign_extention_i16(imm, 12) as i32;
-                if core.get_int_register(rs1 as usize) >= core.get_int_register(rs2 as usize) {
-                    core.set_pc(core.get_pc() + (extended_imm << 1) as Address);
-                } else {
-                    core.increment_pc();
-                }
-                core.increment_flush_counter();
+                let is_taken =
+                    core.get_int_register(rs1 as usize) >= core.get_int_register(rs2 as usize);
+                branch(imm, is_taken, core);
                 BGE
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3);
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         100 => match funct3 {
             0b000 => {
                 // fbeq
-                let extended_imm = sign_extention_i16(imm, 12) as i32;
-                if core.get_float_register(rs1 as usize) == core.get_float_register(rs2 as usize) {
-                    core.set_pc(core.get_pc() + (extended_imm << 1) as Address);
-                } else {
-                    core.increment_pc();
-                }
-                core.increment_flush_counter();
+                let is_taken =
+                    core.get_float_register(rs1 as usize) == core.get_float_register(rs2 as usize);
+                branch(imm, is_taken, core);
                 FBEQ
             }
             0b001 => {
                 // fbne
-                let extended_imm = sign_extention_i16(imm, 12) as i32;
-                if core.get_float_register(rs1 as usize) != core.get_float_register(rs2 as usize) {
-                    core.set_pc(core.get_pc() + (extended_imm << 1) as Address);
-                } else {
-                    core.increment_pc();
-                }
-                core.increment_flush_counter();
+                let is_taken =
+                    core.get_float_register(rs1 as usize) != core.get_float_register(rs2 as usize);
+                branch(imm, is_taken, core);
                 FBNE
             }
             0b100 => {
                 // fblt
-                let extended_imm = sign_extention_i16(imm, 12) as i32;
-                if core.get_float_register(rs1 as usize) < core.get_float_register(rs2 as usize) {
-                    core.set_pc(core.get_pc() + (extended_imm << 1) as Address);
-                } else {
-                    core.increment_pc();
-                }
-                core.increment_flush_counter();
+                let is_taken =
+                    core.get_float_register(rs1 as usize) < core.get_float_register(rs2 as usize);
+                branch(imm, is_taken, core);
                 FBLT
             }
             0b101 => {
                 // fble
-                let extended_imm = sign_extention_i16(imm, 12) as i32;
-                if core.get_float_register(rs1 as usize) <= core.get_float_register(rs2 as usize) {
-                    core.set_pc(core.get_pc() + (extended_imm << 1) as Address);
-                } else {
-                    core.increment_pc();
-                }
-                core.increment_flush_counter();
+                let is_taken =
+                    core.get_float_register(rs1 as usize) <= core.get_float_register(rs2 as usize);
+                branch(imm, is_taken, core);
                 FBLE
             }
             _ => {
-                panic!("unexpected funct3: {}", funct3);
+                return Err(invalid_instruction(
+                    core,
+                    InstructionField::Funct3,
+                    funct3 as u32,
+                ));
             }
         },
         _ => {
-            panic!("unexpected op: {}", op);
+            return Err(invalid_instruction(core, InstructionField::Op, op as u32));
         }
-    }
+    })
 }
 
-fn exec_j_instruction(imm: Imm20, rd: Rd, op: Op, core: &mut Core) -> InstructionId {
-    match op {
+fn exec_j_instruction(
+    imm: Imm20,
+    rd: Rd,
+    op: Op,
+    core: &mut Core,
+) -> Result<InstructionId, SimulatorError> {
+    Ok(match op {
         111 => {
             // jal
             let extended_imm = sign_extention_i32(imm, 20);
             let jump_address = (core.get_pc() as i32 + (extended_imm << 1)) as Address;
             core.set_int_register(rd as usize, core.get_pc() as Int + 4);
+            core.resolve_control_transfer(ControlTransfer::Jump {
+                target: jump_address,
+                is_call: rd as usize == RA,
+            });
             core.set_pc(jump_address);
-            core.increment_flush_counter();
             JAL
         }
         _ => {
-            panic!("unexpected op: {}", op);
+            return Err(invalid_instruction(core, InstructionField::Op, op as u32));
         }
-    }
+    })
 }
 
-fn exec_u_instruction(imm: Imm20, rd: Rd, op: Op, core: &mut Core) -> InstructionId {
-    match op {
+fn exec_u_instruction(
+    imm: Imm20,
+    rd: Rd,
+    op: Op,
+    core: &mut Core,
+) -> Result<InstructionId, SimulatorError> {
+    Ok(match op {
         55 => {
             // lui
             let upimm = imm << 12;
@@ -624,9 +712,9 @@ fn exec_u_instruction(imm: Imm20, rd: Rd, op: Op, core: &mut Core) -> Instructio
             LUI
         }
         _ => {
-            panic!("unexpected op: {}", op);
+            return Err(invalid_instruction(core, InstructionField::Op, op as u32));
         }
-    }
+    })
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -638,9 +726,76 @@ fn exec_r4_instruction(
     _funct3: Funct3,
     _rd: Rd,
     op: Op,
-    _core: &mut Core,
-) -> InstructionId {
-    panic!("unexpected op: {}", op);
+    core: &mut Core,
+) -> Result<InstructionId, SimulatorError> {
+    Err(invalid_instruction(core, InstructionField::Op, op as u32))
+}
+
+/// Registers an executed instruction reads and writes, numbered as in `Core::set_load_dest` (float registers from 32).
+/// The zero registers are left out since their values never change.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Operands {
+    pub sources: [Option<usize>; 2],
+    pub dest: Option<usize>,
+}
+
+fn int_operand(index: u8) -> Option<usize> {
+    (index as usize != ZERO).then_some(index as usize)
+}
+
+fn float_operand(index: u8) -> Option<usize> {
+    (index as usize != ZERO).then_some(index as usize + 32)
+}
+
+/// `inst_id` is the id `exec_instruction` returned for `inst`.
+pub fn get_operands(inst: Instruction, inst_id: InstructionId) -> Operands {
+    let (sources, dest) = match (inst, inst_id) {
+        (Instruction::I(_, rs1, _, rd, _), LW | ADDI | SLLI | SRAI | JALR) => {
+            ([int_operand(rs1), None], int_operand(rd))
+        }
+        (Instruction::I(_, rs1, _, rd, _), FLW) => ([int_operand(rs1), None], float_operand(rd)),
+        (Instruction::I(_, _, _, rd, _), IN) => ([None, None], int_operand(rd)),
+        (Instruction::I(_, _, _, rd, _), FIN) => ([None, None], float_operand(rd)),
+        (Instruction::R(_, rs2, rs1, _, rd, _), ADD | SUB | XOR) => {
+            ([int_operand(rs1), int_operand(rs2)], int_operand(rd))
+        }
+        (Instruction::R(_, rs2, rs1, _, rd, _), FADD | FSUB | FMUL | FDIV | FSGNJ | FSGNJN) => {
+            ([float_operand(rs1), float_operand(rs2)], float_operand(rd))
+        }
+        (Instruction::R(_, _, rs1, _, rd, _), FSQRT) => {
+            ([float_operand(rs1), None], float_operand(rd))
+        }
+        (Instruction::R(_, rs2, rs1, _, rd, _), FEQ | FLT | FLE) => {
+            ([float_operand(rs1), float_operand(rs2)], int_operand(rd))
+        }
+        (Instruction::R(_, _, rs1, _, rd, _), FCVTWS) => {
+            ([float_operand(rs1), None], int_operand(rd))
+        }
+        (Instruction::R(_, _, rs1, _, rd, _), FCVTSW) => {
+            ([int_operand(rs1), None], float_operand(rd))
+        }
+        (Instruction::S(_, rs2, rs1, _, _), SW) => ([int_operand(rs1), int_operand(rs2)], None),
+        (Instruction::S(_, rs2, rs1, _, _), FSW) => ([int_operand(rs1), float_operand(rs2)], None),
+        (Instruction::S(_, rs2, _, _, _), OUTCHAR) => ([int_operand(rs2), None], None),
+        (Instruction::B(_, rs2, rs1, _, _), BEQ | BNE | BLT | BGE) => {
+            ([int_operand(rs1), int_operand(rs2)], None)
+        }
+        (Instruction::B(_, rs2, rs1, _, _), FBEQ | FBNE | FBLT | FBLE) => {
+            ([float_operand(rs1), float_operand(rs2)], None)
+        }
+        (Instruction::J(_, rd, _), JAL) | (Instruction::U(_, rd, _), LUI) => {
+            ([None, None], int_operand(rd))
+        }
+        _ => ([None, None], None),
+    };
+    Operands { sources, dest }
+}
+
+pub fn is_control_transfer(inst_id: InstructionId) -> bool {
+    matches!(
+        inst_id,
+        BEQ | BNE | BLT | BGE | FBEQ | FBNE | FBLT | FBLE | JAL | JALR
+    )
 }
 
 pub fn create_inst_id_to_name_map() -> HashMap<InstructionId, String> {
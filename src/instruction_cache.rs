@@ -0,0 +1,148 @@
+use serde::Deserialize;
+
+use crate::cache::*;
+use crate::replacement_policy::*;
+use crate::snapshot::*;
+use crate::types::*;
+
+/// Geometry of the instruction cache; instructions themselves are still read from the decoded program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ICacheConfig {
+    /// Total capacity in bytes.
+    pub size: usize,
+    pub way_num: usize,
+    /// Line size in bytes.
+    pub line_size: usize,
+    pub policy: ReplacementPolicyKind,
+    /// Seed of the random replacement policy.
+    pub seed: u64,
+    /// Cycles the fetch stalls for on a miss.
+    pub miss_penalty: usize,
+}
+
+impl Default for ICacheConfig {
+    fn default() -> Self {
+        ICacheConfig {
+            size: 4 * 1024,
+            way_num: 1,
+            line_size: 16,
+            policy: ReplacementPolicyKind::Lru,
+            seed: 0,
+            miss_penalty: 108 * 120,
+        }
+    }
+}
+
+impl ICacheConfig {
+    fn get_cache_config(&self) -> CacheConfig {
+        CacheConfig {
+            size: self.size,
+            way_num: self.way_num,
+            line_size: self.line_size,
+            policy: self.policy,
+            seed: self.seed,
+            ..CacheConfig::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.get_cache_config()
+            .validate()
+            .map_err(|reason| format!("icache {}", reason))
+    }
+}
+
+/// Tracks which instruction lines are cached to charge the fetch misses.
+pub struct InstructionCache {
+    cache: Cache,
+    config: ICacheConfig,
+    access_count: usize,
+    hit_count: usize,
+}
+
+impl InstructionCache {
+    /// The config is assumed to have passed `ICacheConfig::validate`.
+    pub fn new(config: ICacheConfig) -> Self {
+        InstructionCache {
+            cache: Cache::new(config.get_cache_config()),
+            config,
+            access_count: 0,
+            hit_count: 0,
+        }
+    }
+
+    pub fn fetch(&mut self, pc: Address) {
+        self.access_count += 1;
+        if let CacheAccess::HitWord(_) = self.cache.get_word(pc) {
+            self.hit_count += 1;
+            return;
+        }
+        let line_addr = self.cache.get_line_addr(pc);
+        let line = vec![0; self.cache.get_config().get_words_per_line()];
+        self.cache.set_line(line_addr, line, false);
+    }
+
+    pub fn get_stall_cycle_num(&self) -> u128 {
+        (self.access_count - self.hit_count) as u128 * self.config.miss_penalty as u128
+    }
+
+    /// Hit rate in percent, 0 before the first fetch.
+    pub fn get_hit_rate(&self) -> f64 {
+        if self.access_count == 0 {
+            return 0.0;
+        }
+        self.hit_count as f64 / self.access_count as f64 * 100.0
+    }
+
+    pub fn show(&self) {
+        let config = &self.config;
+        println!(
+            "icache: {} bytes, {}-way, {}-byte lines, {} replacement",
+            config.size, config.way_num, config.line_size, config.policy
+        );
+        println!("icache access count: {}", self.access_count);
+        println!("icache hit count: {}", self.hit_count);
+        println!("icache hit rate: {:.5}%", self.get_hit_rate());
+        println!("icache miss stall cycles: {}", self.get_stall_cycle_num());
+    }
+
+    pub fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.cache.write_snapshot(writer);
+        writer.put_usize(self.access_count);
+        writer.put_usize(self.hit_count);
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        self.cache.read_snapshot(reader)?;
+        self.access_count = reader.get_usize()?;
+        self.hit_count = reader.get_usize()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instruction_cache() {
+        let config = ICacheConfig {
+            size: 32,
+            line_size: 16,
+            miss_penalty: 100,
+            ..ICacheConfig::default()
+        };
+        let mut icache = InstructionCache::new(config);
+        assert_eq!(icache.get_hit_rate(), 0.0);
+        // a loop over 0x00..0x30 thrashes set 0
+        for _ in 0..2 {
+            for pc in (0..0x30).step_by(4) {
+                icache.fetch(pc);
+            }
+        }
+        assert_eq!(icache.access_count, 24);
+        assert_eq!(icache.hit_count, 24 - 5);
+        assert_eq!(icache.get_stall_cycle_num(), 500);
+    }
+}
@@ -0,0 +1,399 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Stdout, Write};
+use std::rc::Rc;
+
+use crate::error::*;
+use crate::fpu_emulator::*;
+use crate::sld_loader::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputKind {
+    Int,
+    Float,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputError {
+    pub index: usize,
+    pub token: Option<String>,
+}
+
+/// Source of the values read by `in` and `fin`.
+pub trait InputDevice {
+    /// Reads the next value and returns the bits of the word it is loaded as.
+    fn read(&mut self, kind: InputKind) -> Result<u32, InputError>;
+
+    /// Number of values read so far.
+    fn get_position(&self) -> usize;
+
+    /// Skips values until `position` values have been read, e.g. when restoring a snapshot.
+    fn skip_to(&mut self, position: usize) -> Result<(), InputError>;
+}
+
+/// Sink of the bytes written by `outchar`.
+pub trait OutputDevice {
+    /// A device that fails stops writing and reports the failure on `flush`,
+    /// so that the program still runs to the end, e.g. when stdout is piped into `head`.
+    fn write(&mut self, byte: u8);
+
+    fn flush(&mut self) -> Result<(), SimulatorError> {
+        Ok(())
+    }
+}
+
+impl InputKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            InputKind::Int => "an integer",
+            InputKind::Float => "a float",
+        }
+    }
+}
+
+/// Whitespace-separated decimal tokens, as in sld files.
+pub struct SldInput {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl SldInput {
+    pub fn new(tokens: Vec<String>) -> Self {
+        SldInput {
+            tokens,
+            position: 0,
+        }
+    }
+
+    pub fn from_text(text: &str) -> Self {
+        Self::new(text.split_whitespace().map(str::to_string).collect())
+    }
+
+    pub fn from_file(file_path: &str) -> Self {
+        Self::new(load_sld_file(file_path))
+    }
+}
+
+impl InputDevice for SldInput {
+    fn read(&mut self, kind: InputKind) -> Result<u32, InputError> {
+        let token = self.tokens.get(self.position);
+        let value = token.and_then(|token| match kind {
+            InputKind::Int => token.parse::<i32>().ok().map(|value| value as u32),
+            InputKind::Float => token
+                .parse::<f32>()
+                .ok()
+                .map(|value| FloatingPoint::new_f32(value).get_32_bits()),
+        });
+        match value {
+            Some(value) => {
+                self.position += 1;
+                Ok(value)
+            }
+            None => Err(InputError {
+                index: self.position,
+                token: token.cloned(),
+            }),
+        }
+    }
+
+    fn get_position(&self) -> usize {
+        self.position
+    }
+
+    fn skip_to(&mut self, position: usize) -> Result<(), InputError> {
+        if position > self.tokens.len() {
+            return Err(InputError {
+                index: self.tokens.len(),
+                token: None,
+            });
+        }
+        self.position = position;
+        Ok(())
+    }
+}
+
+/// Raw 4-byte little-endian words, as the board receives them over UART.
+pub struct UartInput {
+    reader: Box<dyn Read>,
+    position: usize,
+}
+
+impl UartInput {
+    pub fn new(reader: Box<dyn Read>) -> Self {
+        UartInput {
+            reader,
+            position: 0,
+        }
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self::new(Box::new(Cursor::new(bytes)))
+    }
+
+    pub fn from_file(file_path: &str) -> io::Result<Self> {
+        Ok(Self::new(Box::new(BufReader::new(File::open(file_path)?))))
+    }
+
+    pub fn from_stdin() -> Self {
+        Self::new(Box::new(io::stdin()))
+    }
+}
+
+impl InputDevice for UartInput {
+    fn read(&mut self, _kind: InputKind) -> Result<u32, InputError> {
+        let mut bytes = [0; 4];
+        let mut len = 0;
+        while len < bytes.len() {
+            match self.reader.read(&mut bytes[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        if len < bytes.len() {
+            let token = bytes[..len]
+                .iter()
+                .map(|byte| format!("{:>02x}", byte))
+                .collect::<Vec<_>>()
+                .join(" ");
+            return Err(InputError {
+                index: self.position,
+                token: if len == 0 { None } else { Some(token) },
+            });
+        }
+        self.position += 1;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn get_position(&self) -> usize {
+        self.position
+    }
+
+    /// The stream cannot be rewound, so only skipping forward is possible.
+    fn skip_to(&mut self, position: usize) -> Result<(), InputError> {
+        if position < self.position {
+            return Err(InputError {
+                index: position,
+                token: None,
+            });
+        }
+        while self.position < position {
+            self.read(InputKind::Int)?;
+        }
+        Ok(())
+    }
+}
+
+/// Discards all output; the core keeps its own copy of the output anyway.
+pub struct NullOutput;
+
+impl OutputDevice for NullOutput {
+    fn write(&mut self, _byte: u8) {}
+}
+
+/// Writer that keeps the first error and ignores the writes after it.
+struct CheckedWriter<W: Write> {
+    path: String,
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> CheckedWriter<W> {
+    fn new(path: &str, writer: W) -> Self {
+        CheckedWriter {
+            path: path.to_string(),
+            writer,
+            error: None,
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        if self.error.is_none() {
+            self.error = self.writer.write_all(&[byte]).err();
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), SimulatorError> {
+        if self.error.is_none() {
+            self.error = self.writer.flush().err();
+        }
+        match &self.error {
+            Some(e) => Err(SimulatorError::DeviceUnavailable {
+                path: self.path.clone(),
+                reason: e.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct FileOutput {
+    writer: CheckedWriter<BufWriter<File>>,
+}
+
+impl FileOutput {
+    pub fn new(file_path: &str) -> io::Result<Self> {
+        Ok(FileOutput {
+            writer: CheckedWriter::new(file_path, BufWriter::new(File::create(file_path)?)),
+        })
+    }
+}
+
+impl OutputDevice for FileOutput {
+    fn write(&mut self, byte: u8) {
+        self.writer.write(byte);
+    }
+
+    fn flush(&mut self) -> Result<(), SimulatorError> {
+        self.writer.flush()
+    }
+}
+
+pub struct StdoutOutput {
+    writer: CheckedWriter<BufWriter<Stdout>>,
+}
+
+impl StdoutOutput {
+    pub fn new() -> Self {
+        StdoutOutput {
+            writer: CheckedWriter::new("stdout", BufWriter::new(io::stdout())),
+        }
+    }
+}
+
+impl Default for StdoutOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutputDevice for StdoutOutput {
+    fn write(&mut self, byte: u8) {
+        self.writer.write(byte);
+    }
+
+    fn flush(&mut self) -> Result<(), SimulatorError> {
+        self.writer.flush()
+    }
+}
+
+/// Keeps the output in a buffer shared with its clones, so that it can be inspected after the run.
+#[derive(Clone, Default)]
+pub struct BufferOutput {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl BufferOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_bytes(&self) -> Vec<u8> {
+        self.buffer.borrow().clone()
+    }
+}
+
+impl OutputDevice for BufferOutput {
+    fn write(&mut self, byte: u8) {
+        self.buffer.borrow_mut().push(byte);
+    }
+}
+
+/// Writes every byte to all of the given devices.
+pub struct TeeOutput {
+    devices: Vec<Box<dyn OutputDevice>>,
+}
+
+impl TeeOutput {
+    pub fn new(devices: Vec<Box<dyn OutputDevice>>) -> Self {
+        TeeOutput { devices }
+    }
+}
+
+impl OutputDevice for TeeOutput {
+    fn write(&mut self, byte: u8) {
+        for device in self.devices.iter_mut() {
+            device.write(byte);
+        }
+    }
+
+    /// Flushes every device and reports the first failure.
+    fn flush(&mut self) -> Result<(), SimulatorError> {
+        let mut result = Ok(());
+        for device in self.devices.iter_mut() {
+            let device_result = device.flush();
+            if result.is_ok() {
+                result = device_result;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sld_input() {
+        let mut input = SldInput::from_text("3 -1\n 1.5 x");
+        assert_eq!(input.read(InputKind::Int), Ok(3));
+        assert_eq!(input.read(InputKind::Int), Ok(0xffffffff));
+        assert_eq!(input.read(InputKind::Float), Ok(0x3fc00000));
+        assert_eq!(
+            input.read(InputKind::Float),
+            Err(InputError {
+                index: 3,
+                token: Some("x".to_string())
+            })
+        );
+        assert_eq!(
+            SldInput::from_text("").read(InputKind::Int),
+            Err(InputError {
+                index: 0,
+                token: None
+            })
+        );
+    }
+
+    #[test]
+    fn test_uart_input() {
+        let mut input = UartInput::from_bytes(vec![3, 0, 0, 0, 0x00, 0x00, 0xc0, 0x3f, 0xff]);
+        assert_eq!(input.read(InputKind::Int), Ok(3));
+        assert_eq!(input.read(InputKind::Float), Ok(0x3fc00000));
+        assert_eq!(
+            input.read(InputKind::Int),
+            Err(InputError {
+                index: 2,
+                token: Some("ff".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_tee_output() {
+        let first = BufferOutput::new();
+        let second = BufferOutput::new();
+        let mut tee = TeeOutput::new(vec![Box::new(first.clone()), Box::new(second.clone())]);
+        tee.write(b'P');
+        tee.write(b'3');
+        assert_eq!(first.get_bytes(), b"P3");
+        assert_eq!(second.get_bytes(), b"P3");
+    }
+
+    #[test]
+    fn test_checked_writer() {
+        let mut buffer = [0; 1];
+        let mut writer = CheckedWriter::new("closed", &mut buffer[..]);
+        writer.write(b'P');
+        assert!(writer.flush().is_ok());
+        // the slice is full, so the write fails and the ones after it are dropped
+        writer.write(b'3');
+        writer.write(b'\n');
+        assert!(matches!(
+            writer.flush(),
+            Err(SimulatorError::DeviceUnavailable { path, .. }) if path == "closed"
+        ));
+    }
+}
@@ -0,0 +1,46 @@
+//! Simulator for CPUEX-Group2 computer.
+//!
+//! `Core` can be driven by `Core::run` with `CoreProps` as the command line tool does,
+//! or stepped instruction by instruction with I/O supplied through `InputDevice` and `OutputDevice`.
+pub mod bin_loader;
+mod branch_predictor;
+mod branch_profile;
+mod cache;
+mod cache_hierarchy;
+mod compare;
+pub mod config;
+pub mod core;
+mod debugger;
+pub mod decoder;
+pub mod disassembler;
+pub mod error;
+pub mod fpu_emulator;
+mod fpu_scoreboard;
+mod instruction;
+mod instruction_cache;
+mod instruction_memory;
+pub mod io_device;
+mod memory;
+mod miss_classifier;
+mod miss_profile;
+mod pipeline;
+mod prefetcher;
+mod register;
+mod replacement_policy;
+pub mod sld_converter;
+mod sld_loader;
+mod snapshot;
+mod superscalar;
+mod sweep;
+pub mod trace;
+pub mod types;
+mod utils;
+pub mod watchpoint;
+mod write_buffer;
+
+pub use crate::core::{Core, CoreProps};
+pub use crate::decoder::{decode_instruction, Instruction};
+pub use crate::error::SimulatorError;
+pub use crate::fpu_emulator::FloatingPoint;
+pub use crate::io_device::{InputDevice, OutputDevice};
+pub use crate::register::RegisterId;
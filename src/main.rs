@@ -1,16 +1,14 @@
-mod cache;
-mod core;
-mod decoder;
-mod fpu_emulator;
-mod instruction;
-mod instruction_memory;
-mod memory;
-mod register;
-mod sld_loader;
-mod types;
-mod utils;
-use crate::core::*;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use instruction_simulator::bin_loader::*;
+use instruction_simulator::config::*;
+use instruction_simulator::core::*;
+use instruction_simulator::disassembler::*;
+use instruction_simulator::error::*;
+use instruction_simulator::io_device::*;
+use instruction_simulator::sld_converter::*;
+use instruction_simulator::trace::*;
+use instruction_simulator::types::*;
+use instruction_simulator::watchpoint::*;
 
 /// Simulator for CPUEX-Group2 computer
 #[derive(Parser, Debug)]
@@ -28,11 +26,175 @@ struct Args {
     #[arg(long)]
     ppm: Option<String>,
 
+    /// Name of a raw input file for UART mode (`-` for stdin).
+    /// If this flag is set, `in` and `fin` read 4-byte little-endian words from the file instead of the sld file.
+    #[arg(long)]
+    uart: Option<String>,
+
+    /// UART mode with the sld file.
+    /// If this flag is set, the sld file is converted to the words the board receives (as `sld2bin` does) before running.
+    #[arg(long, conflicts_with = "uart")]
+    sld_uart: bool,
+
+    /// Destination of the output.
+    #[arg(long, value_enum, default_value = "file")]
+    output: OutputMode,
+
     /// No cache mode.
     /// If this flag is set, the simulator won't use cache.
     #[arg(short, long)]
     no_cache: bool,
 
+    /// Name of a TOML config file with the cache parameters and the stall cycles of memory events (see `Config` for the keys).
+    /// The cache flags below override the values in the file.
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Cache size in bytes (16384 by default).
+    #[arg(long)]
+    cache_size: Option<usize>,
+
+    /// Cache associativity (1 by default).
+    #[arg(long)]
+    cache_ways: Option<usize>,
+
+    /// Cache line size in bytes (16 by default).
+    #[arg(long)]
+    cache_line_size: Option<usize>,
+
+    /// Cache replacement policy (lru by default).
+    #[arg(long, value_enum)]
+    cache_policy: Option<ReplacementPolicyKind>,
+
+    /// Seed of the random cache replacement policy (0 by default).
+    #[arg(long)]
+    cache_seed: Option<u64>,
+
+    /// Cache write policy (write-back by default).
+    #[arg(long, value_enum)]
+    cache_write_policy: Option<WritePolicy>,
+
+    /// Cache policy on store misses (allocate by default).
+    #[arg(long, value_enum)]
+    cache_write_miss_policy: Option<WriteMissPolicy>,
+
+    /// Number of entries of the write buffer between the cache and memory (0 by default, which means none).
+    #[arg(long)]
+    write_buffer_size: Option<usize>,
+
+    /// Cycles added to every cache access (0 by default).
+    #[arg(long)]
+    cache_latency: Option<usize>,
+
+    /// L2 cache size in bytes (131072 by default).
+    /// Any of the L2 flags enables an L2 between the cache and memory; its line size is that of the cache.
+    #[arg(long)]
+    l2_size: Option<usize>,
+
+    /// L2 cache associativity (4 by default).
+    #[arg(long)]
+    l2_ways: Option<usize>,
+
+    /// L2 cache replacement policy (lru by default).
+    #[arg(long, value_enum)]
+    l2_policy: Option<ReplacementPolicyKind>,
+
+    /// Cycles added to every L2 access (10 by default).
+    #[arg(long)]
+    l2_latency: Option<usize>,
+
+    /// Whether the L2 holds the lines of the cache (non-inclusive by default).
+    #[arg(long, value_enum)]
+    l2_inclusion: Option<Inclusion>,
+
+    /// Prefetcher of the data cache (none by default).
+    #[arg(long, value_enum)]
+    prefetcher: Option<PrefetcherKind>,
+
+    /// Number of lines the prefetcher fetches ahead (1 by default); enables a next-line prefetcher if none is given.
+    #[arg(long)]
+    prefetch_degree: Option<usize>,
+
+    /// Instruction cache size in bytes (4096 by default).
+    /// Any of the icache flags puts an instruction cache on the fetch path, which is free otherwise.
+    #[arg(long)]
+    icache_size: Option<usize>,
+
+    /// Instruction cache associativity (1 by default).
+    #[arg(long)]
+    icache_ways: Option<usize>,
+
+    /// Instruction cache line size in bytes (16 by default).
+    #[arg(long)]
+    icache_line_size: Option<usize>,
+
+    /// Cycles a fetch stalls for on an instruction cache miss (12960 by default).
+    #[arg(long)]
+    icache_miss_penalty: Option<usize>,
+
+    /// Predict the cycle count with a pipeline model that accounts for data hazards and FPU latencies.
+    /// Any of the pipeline flags enables it; the analytic model is used otherwise.
+    #[arg(long)]
+    pipeline: bool,
+
+    /// Number of pipeline stages (5 by default).
+    #[arg(long)]
+    pipeline_stages: Option<usize>,
+
+    /// Pipeline stage where branches and jumps redirect the fetch (4 by default).
+    #[arg(long)]
+    branch_stage: Option<usize>,
+
+    /// Read operands from the register file after write-back instead of forwarding them.
+    #[arg(long)]
+    no_forwarding: bool,
+
+    /// Let the FPU start an operation every cycle instead of waiting for the previous one to finish.
+    #[arg(long)]
+    pipelined_fpu: bool,
+
+    /// Add the FPU stalls of instructions waiting for results or for the FPU to the predicted cycle count.
+    #[arg(long)]
+    fpu_stalls: bool,
+
+    /// Also time the program on an in-order superscalar core with the shape of the pipeline model,
+    /// reported alongside the single-issue prediction. Any of the superscalar flags enables it.
+    #[arg(long)]
+    superscalar: bool,
+
+    /// Instructions the superscalar core issues per cycle (2 by default).
+    #[arg(long)]
+    issue_width: Option<usize>,
+
+    /// Integer ALUs of the superscalar core, next to one FPU and one load/store unit (2 by default).
+    #[arg(long)]
+    alu_num: Option<usize>,
+
+    /// Let the superscalar core issue instructions in the same cycle after a branch or jump.
+    #[arg(long)]
+    issue_after_branch: bool,
+
+    /// Direction predictor of conditional branches (none by default, which flushes on every branch and jump).
+    /// Any of the branch predictor flags enables prediction, with a btb for `jal` and a return address stack.
+    #[arg(long, value_enum)]
+    branch_predictor: Option<BranchPredictorKind>,
+
+    /// Entries of the table of the one-bit, two-bit and gshare predictors (1024 by default).
+    #[arg(long)]
+    branch_table_size: Option<usize>,
+
+    /// Branch history length of gshare (10 by default).
+    #[arg(long)]
+    branch_history_len: Option<usize>,
+
+    /// Entries of the branch target buffer, 0 for none (64 by default).
+    #[arg(long)]
+    btb_size: Option<usize>,
+
+    /// Entries of the return address stack, 0 for none (8 by default).
+    #[arg(long)]
+    ras_size: Option<usize>,
+
     /// Take instruction statistics.
     #[arg(short, long)]
     inst_stats: bool,
@@ -41,10 +203,79 @@ struct Args {
     #[arg(long)]
     pc_stats: bool,
 
+    /// Take cache miss statistics by pc, memory region and cache set.
+    #[arg(long)]
+    miss_stats: bool,
+
+    /// Take statistics of every conditional branch, most flushed first.
+    #[arg(long)]
+    branch_stats: bool,
+
+    /// Lowest address of the heap for the miss statistics; accesses below it are counted as globals.
+    /// Accepts a decimal or a 0x-prefixed hexadecimal address.
+    #[arg(long, value_parser = parse_address, default_value = "0")]
+    heap_start: Address,
+
+    /// Classify cache misses into compulsory, capacity and conflict misses with a fully-associative shadow cache.
+    #[arg(long)]
+    classify_misses: bool,
+
     /// Show output.
-    #[arg(short, long)]
+    #[arg(long)]
     show_output: bool,
 
+    /// Debug mode.
+    /// If this flag is set, the simulator will stop before the first instruction and wait for debugger commands.
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Watch a register or memory address and stop when it is written.
+    /// The value is `<x<n>|f<n>|addr> [if <changed|nan|inf|== value>]` (e.g. "f3 if nan").
+    #[arg(long, value_parser = parse_write_watch_spec)]
+    watch: Vec<(WatchTarget, WatchCondition)>,
+
+    /// Watch a memory address and stop when it is read.
+    #[arg(long, value_parser = parse_read_watch_spec)]
+    rwatch: Vec<(WatchTarget, WatchCondition)>,
+
+    /// Watch a memory address and stop when it is read or written.
+    #[arg(long, value_parser = parse_access_watch_spec)]
+    awatch: Vec<(WatchTarget, WatchCondition)>,
+
+    /// Only log watchpoint hits instead of stopping the execution.
+    #[arg(long)]
+    watch_log: bool,
+
+    /// Trace mode.
+    /// If this flag is set with a file name, the simulator will write every retired instruction to the file.
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// Format of the trace file.
+    #[arg(long, value_enum, default_value = "text")]
+    trace_format: TraceFormat,
+
+    /// Index of the first instruction to trace (0-origin).
+    #[arg(long, default_value = "0")]
+    trace_from: InstructionCount,
+
+    /// Index of the instruction at which tracing stops (exclusive).
+    #[arg(long)]
+    trace_to: Option<InstructionCount>,
+
+    /// Save a snapshot of the machine state when the given number of instructions have been executed.
+    #[arg(long)]
+    save_snapshot_at: Option<InstructionCount>,
+
+    /// Name of the snapshot file to save (defaults to the binary file name with the extension .snapshot).
+    #[arg(long)]
+    snapshot_file: Option<String>,
+
+    /// Restore the machine state from a snapshot file before running.
+    /// The binary, input, cache mode and cache geometry have to be the same as when the snapshot was saved.
+    #[arg(long)]
+    load_snapshot: Option<String>,
+
     /// Show progress bar.
     /// If this flag is set with a value, the simulator will show progress bar.
     /// The value of this flag is the total size of output ppm file.
@@ -55,31 +286,455 @@ struct Args {
     /// If this flag is set with a file name, the simulator will output framegraph with the given file name.
     #[arg(long)]
     prof: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() {
-    let mut core = Core::new();
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum OutputMode {
+    /// Write to the ppm file.
+    File,
+    /// Write to stdout.
+    Stdout,
+    /// Write to both the ppm file and stdout.
+    Tee,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Disassemble a binary file instead of simulating it.
+    Disasm {
+        /// Name of the binary file (defaults to the value of --bin).
+        file: Option<String>,
+    },
+    /// Convert an sld file to the raw words sent to the board over UART (for --uart).
+    Sld2bin {
+        /// Name of the sld file.
+        sld: String,
+
+        /// Name of the output file (defaults to the sld file name with the extension .uart).
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Simulate in lockstep with a reference trace and stop at the first divergence.
+    Compare {
+        /// Name of the reference trace file.
+        reference: String,
+
+        /// Format of the reference trace file.
+        #[arg(long, value_enum, default_value = "text")]
+        format: TraceFormat,
+
+        /// Number of preceding instructions shown on divergence.
+        #[arg(long, default_value = "10")]
+        context: usize,
+    },
+    /// Simulate every combination of the given cache parameters in a single run and show a table of the results.
+    /// The cache used for execution is still the one given by the cache flags.
+    Sweep {
+        /// Cache sizes in bytes.
+        #[arg(
+            long,
+            value_delimiter = ',',
+            default_value = "4096,8192,16384,32768,65536"
+        )]
+        sizes: Vec<usize>,
+
+        /// Cache associativities.
+        #[arg(long, value_delimiter = ',', default_value = "1,2,4")]
+        ways: Vec<usize>,
+
+        /// Cache line sizes in bytes.
+        #[arg(long, value_delimiter = ',', default_value = "16,32,64")]
+        line_sizes: Vec<usize>,
 
+        /// Cache replacement policies.
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "lru")]
+        policies: Vec<ReplacementPolicyKind>,
+    },
+}
+
+fn parse_write_watch_spec(spec: &str) -> Result<(WatchTarget, WatchCondition), String> {
+    parse_watch_spec(spec, WatchKind::Write)
+}
+
+fn parse_read_watch_spec(spec: &str) -> Result<(WatchTarget, WatchCondition), String> {
+    parse_watch_spec(spec, WatchKind::Read)
+}
+
+fn parse_access_watch_spec(spec: &str) -> Result<(WatchTarget, WatchCondition), String> {
+    parse_watch_spec(spec, WatchKind::Access)
+}
+
+fn parse_address(s: &str) -> Result<Address, String> {
+    let result = match s.strip_prefix("0x") {
+        Some(hex) => Address::from_str_radix(hex, 16),
+        None => s.parse::<Address>(),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+fn exit_with_error(e: SimulatorError) -> ! {
+    eprintln!("error: {}", e);
+    std::process::exit(e.exit_code());
+}
+
+fn convert_sld_to_file(sld_file_path: &str, output_file_path: &str) -> Result<(), SimulatorError> {
+    let bytes = convert_sld_file(sld_file_path)?;
+    std::fs::write(output_file_path, &bytes).map_err(|e| SimulatorError::DeviceUnavailable {
+        path: output_file_path.to_string(),
+        reason: e.to_string(),
+    })?;
+    println!("Wrote {} words to {}.", bytes.len() / 4, output_file_path);
+    Ok(())
+}
+
+fn create_input_device(
+    sld_file_path: &str,
+    uart_file_path: Option<String>,
+    sld_uart: bool,
+) -> Result<Box<dyn InputDevice>, SimulatorError> {
+    match uart_file_path.as_deref() {
+        None if sld_uart => Ok(Box::new(UartInput::from_bytes(convert_sld_file(
+            sld_file_path,
+        )?))),
+        None => Ok(Box::new(SldInput::from_file(sld_file_path))),
+        Some("-") => Ok(Box::new(UartInput::from_stdin())),
+        Some(path) => match UartInput::from_file(path) {
+            Ok(input) => Ok(Box::new(input)),
+            Err(e) => Err(SimulatorError::DeviceUnavailable {
+                path: path.to_string(),
+                reason: e.to_string(),
+            }),
+        },
+    }
+}
+
+fn create_output_device(
+    output_mode: OutputMode,
+    ppm_file_path: &str,
+) -> Result<Box<dyn OutputDevice>, SimulatorError> {
+    let open_file = || match FileOutput::new(ppm_file_path) {
+        Ok(output) => Ok(Box::new(output)),
+        Err(e) => Err(SimulatorError::DeviceUnavailable {
+            path: ppm_file_path.to_string(),
+            reason: e.to_string(),
+        }),
+    };
+    Ok(match output_mode {
+        OutputMode::File => open_file()?,
+        OutputMode::Stdout => Box::new(StdoutOutput::new()),
+        OutputMode::Tee => Box::new(TeeOutput::new(vec![
+            open_file()?,
+            Box::new(StdoutOutput::new()),
+        ])),
+    })
+}
+
+/// Combinations that cannot form a cache (e.g. more ways than lines) or do not fit the L2 are skipped.
+/// The other parameters are taken from `base`.
+fn create_sweep_cache_configs(
+    sizes: &[usize],
+    ways: &[usize],
+    line_sizes: &[usize],
+    policies: &[ReplacementPolicyKind],
+    base: CacheConfig,
+    l2_config: Option<L2Config>,
+) -> Vec<CacheConfig> {
+    let mut configs = vec![];
+    for &size in sizes {
+        for &way_num in ways {
+            for &line_size in line_sizes {
+                for &policy in policies {
+                    let config = CacheConfig {
+                        size,
+                        way_num,
+                        line_size,
+                        policy,
+                        ..base
+                    };
+                    let result = config.validate().and_then(|()| {
+                        l2_config.map_or(Ok(()), |l2_config| l2_config.validate(&config))
+                    });
+                    match result {
+                        Ok(()) => configs.push(config),
+                        Err(reason) => {
+                            eprintln!("warning: skipped a sweep configuration ({})", reason)
+                        }
+                    }
+                }
+            }
+        }
+    }
+    configs
+}
+
+fn main() {
     let args = Args::parse();
+    let mut sweep_command = None;
+    let (compare_file_path, compare_format, compare_context_size) = match args.command {
+        Some(Command::Disasm { file }) => {
+            let file_path = file.unwrap_or(args.bin);
+            match load_bin_file(&file_path) {
+                Ok(insts) => show_disassembly(&insts),
+                Err(e) => exit_with_error(e),
+            }
+            return;
+        }
+        Some(Command::Sld2bin { sld, output }) => {
+            let output = output.unwrap_or(format!("{}.uart", sld.trim_end_matches(".sld")));
+            if let Err(e) = convert_sld_to_file(&sld, &output) {
+                exit_with_error(e);
+            }
+            return;
+        }
+        Some(Command::Compare {
+            reference,
+            format,
+            context,
+        }) => (Some(reference), format, context),
+        Some(command @ Command::Sweep { .. }) => {
+            sweep_command = Some(command);
+            (None, TraceFormat::Text, 0)
+        }
+        None => (None, TraceFormat::Text, 0),
+    };
+
+    let mut core = Core::new();
     let use_cache = !args.no_cache;
+    let config = match &args.config {
+        Some(config_file_path) => {
+            load_config_file(config_file_path).unwrap_or_else(|e| exit_with_error(e))
+        }
+        None => Config::default(),
+    };
+    let cache_config = CacheConfig {
+        size: args.cache_size.unwrap_or(config.cache.size),
+        way_num: args.cache_ways.unwrap_or(config.cache.way_num),
+        line_size: args.cache_line_size.unwrap_or(config.cache.line_size),
+        policy: args.cache_policy.unwrap_or(config.cache.policy),
+        seed: args.cache_seed.unwrap_or(config.cache.seed),
+        write_policy: args.cache_write_policy.unwrap_or(config.cache.write_policy),
+        write_miss_policy: args
+            .cache_write_miss_policy
+            .unwrap_or(config.cache.write_miss_policy),
+        write_buffer_size: args
+            .write_buffer_size
+            .unwrap_or(config.cache.write_buffer_size),
+        latency: args.cache_latency.unwrap_or(config.cache.latency),
+    };
+    if let Err(reason) = cache_config.validate() {
+        exit_with_error(SimulatorError::InvalidConfig { path: None, reason });
+    }
+    let has_l2_flag = args.l2_size.is_some()
+        || args.l2_ways.is_some()
+        || args.l2_policy.is_some()
+        || args.l2_latency.is_some()
+        || args.l2_inclusion.is_some();
+    let l2_config = if config.l2.is_some() || has_l2_flag {
+        let base = config.l2.unwrap_or_default();
+        Some(L2Config {
+            size: args.l2_size.unwrap_or(base.size),
+            way_num: args.l2_ways.unwrap_or(base.way_num),
+            policy: args.l2_policy.unwrap_or(base.policy),
+            latency: args.l2_latency.unwrap_or(base.latency),
+            inclusion: args.l2_inclusion.unwrap_or(base.inclusion),
+            ..base
+        })
+    } else {
+        None
+    };
+    if let Some(Err(reason)) = l2_config.map(|l2_config| l2_config.validate(&cache_config)) {
+        exit_with_error(SimulatorError::InvalidConfig { path: None, reason });
+    }
+    let has_icache_flag = args.icache_size.is_some()
+        || args.icache_ways.is_some()
+        || args.icache_line_size.is_some()
+        || args.icache_miss_penalty.is_some();
+    let icache_config = if config.icache.is_some() || has_icache_flag {
+        let base = config.icache.unwrap_or_default();
+        Some(ICacheConfig {
+            size: args.icache_size.unwrap_or(base.size),
+            way_num: args.icache_ways.unwrap_or(base.way_num),
+            line_size: args.icache_line_size.unwrap_or(base.line_size),
+            miss_penalty: args.icache_miss_penalty.unwrap_or(base.miss_penalty),
+            ..base
+        })
+    } else {
+        None
+    };
+    if let Some(Err(reason)) = icache_config.map(|icache_config| icache_config.validate()) {
+        exit_with_error(SimulatorError::InvalidConfig { path: None, reason });
+    }
+    let prefetch_config =
+        if config.prefetch.is_some() || args.prefetcher.is_some() || args.prefetch_degree.is_some()
+        {
+            let base = config.prefetch.unwrap_or_default();
+            Some(PrefetchConfig {
+                kind: args.prefetcher.unwrap_or(base.kind),
+                degree: args.prefetch_degree.unwrap_or(base.degree),
+                ..base
+            })
+        } else {
+            None
+        };
+    if let Some(Err(reason)) = prefetch_config.map(|prefetch_config| prefetch_config.validate()) {
+        exit_with_error(SimulatorError::InvalidConfig { path: None, reason });
+    }
+    let pipeline_config = if config.pipeline.is_some()
+        || args.pipeline
+        || args.pipeline_stages.is_some()
+        || args.branch_stage.is_some()
+        || args.no_forwarding
+    {
+        let base = config.pipeline.unwrap_or_default();
+        Some(PipelineConfig {
+            stage_num: args.pipeline_stages.unwrap_or(base.stage_num),
+            branch_stage: args.branch_stage.unwrap_or(base.branch_stage),
+            forwarding: base.forwarding && !args.no_forwarding,
+            ..base
+        })
+    } else {
+        None
+    };
+    if let Some(Err(reason)) = pipeline_config.map(|pipeline_config| pipeline_config.validate()) {
+        exit_with_error(SimulatorError::InvalidConfig { path: None, reason });
+    }
+    let superscalar_config = if config.superscalar.is_some()
+        || args.superscalar
+        || args.issue_width.is_some()
+        || args.alu_num.is_some()
+        || args.issue_after_branch
+    {
+        let base = config.superscalar.unwrap_or_default();
+        Some(SuperscalarConfig {
+            issue_width: args.issue_width.unwrap_or(base.issue_width),
+            alu_num: args.alu_num.unwrap_or(base.alu_num),
+            branch_ends_group: base.branch_ends_group && !args.issue_after_branch,
+        })
+    } else {
+        None
+    };
+    if let Some(Err(reason)) =
+        superscalar_config.map(|superscalar_config| superscalar_config.validate())
+    {
+        exit_with_error(SimulatorError::InvalidConfig { path: None, reason });
+    }
+    let has_branch_predictor_flag = args.branch_predictor.is_some()
+        || args.branch_table_size.is_some()
+        || args.branch_history_len.is_some()
+        || args.btb_size.is_some()
+        || args.ras_size.is_some();
+    let branch_predictor_config = if config.branch_predictor.is_some() || has_branch_predictor_flag
+    {
+        let base = config.branch_predictor.unwrap_or_default();
+        Some(BranchPredictorConfig {
+            kind: args.branch_predictor.unwrap_or(base.kind),
+            table_size: args.branch_table_size.unwrap_or(base.table_size),
+            history_len: args.branch_history_len.unwrap_or(base.history_len),
+            btb_size: args.btb_size.unwrap_or(base.btb_size),
+            ras_size: args.ras_size.unwrap_or(base.ras_size),
+        })
+    } else {
+        None
+    };
+    if let Some(Err(reason)) = branch_predictor_config.map(|config| config.validate()) {
+        exit_with_error(SimulatorError::InvalidConfig { path: None, reason });
+    }
+    let sweep_cache_configs = match sweep_command {
+        Some(Command::Sweep {
+            sizes,
+            ways,
+            line_sizes,
+            policies,
+        }) => create_sweep_cache_configs(
+            &sizes,
+            &ways,
+            &line_sizes,
+            &policies,
+            cache_config,
+            l2_config,
+        ),
+        _ => vec![],
+    };
     let take_inst_stats = args.inst_stats;
     let take_pc_stats = args.pc_stats;
+    let take_miss_stats = args.miss_stats;
+    let take_branch_stats = args.branch_stats;
+    let heap_start = args.heap_start;
+    let classify_misses = args.classify_misses;
     let show_output = args.show_output;
+    let debug = args.debug;
+    let watchpoints = [args.watch, args.rwatch, args.awatch].concat();
+    let watch_action = if args.watch_log {
+        WatchAction::Log
+    } else {
+        WatchAction::Stop
+    };
+    let trace_file_path = args.trace;
+    let trace_format = args.trace_format;
+    let trace_from = args.trace_from;
+    let trace_to = args.trace_to;
     let progress_bar_size = args.progress_bar_size;
     let bin_file_path = args.bin.clone();
     let ppm_file_path = args.ppm.unwrap_or(args.bin.replace(".bin", ".ppm"));
-    let sld_file_path = args.sld;
+    let save_snapshot_at = args.save_snapshot_at;
+    let snapshot_file_path = args
+        .snapshot_file
+        .unwrap_or(args.bin.replace(".bin", ".snapshot"));
+    let load_snapshot_path = args.load_snapshot;
+    let input_device = create_input_device(&args.sld, args.uart, args.sld_uart)
+        .unwrap_or_else(|e| exit_with_error(e));
+    let output_device =
+        create_output_device(args.output, &ppm_file_path).unwrap_or_else(|e| exit_with_error(e));
     let prof_file_path = args.prof;
     let props = CoreProps {
         use_cache,
+        cache_config,
+        l2_config,
+        stall_config: config.stall,
+        fpu_config: FpuConfig {
+            pipelined: config.fpu.pipelined || args.pipelined_fpu,
+            count_stalls: config.fpu.count_stalls || args.fpu_stalls,
+        },
+        icache_config,
+        prefetch_config,
+        pipeline_config,
+        superscalar_config,
+        branch_predictor_config,
         take_inst_stats,
         take_pc_stats,
+        take_miss_stats,
+        take_branch_stats,
+        heap_start,
+        classify_misses,
         show_output,
+        debug,
+        watchpoints,
+        watch_action,
+        trace_file_path,
+        trace_format,
+        trace_from,
+        trace_to,
+        compare_file_path,
+        compare_format,
+        compare_context_size,
+        sweep_cache_configs,
         progress_bar_size,
         bin_file_path,
-        ppm_file_path,
-        sld_file_path,
+        save_snapshot_at,
+        snapshot_file_path,
+        load_snapshot_path,
+        input_device,
+        output_device,
         prof_file_path,
     };
-    core.run(props);
+    if let Err(e) = core.run(props) {
+        core.show_error_report(&e);
+        std::process::exit(e.exit_code());
+    }
+    if core.has_diverged() {
+        std::process::exit(1);
+    }
 }
@@ -1,18 +1,25 @@
-use crate::cache::LINE_SIZE;
+use crate::snapshot::*;
 use crate::types::*;
 use crate::utils::*;
 pub const MEMORY_SIZE: usize = 128 * 1024 * 1024;
 pub const WORD_SIZE: usize = 4;
+const PAGE_SIZE: usize = 4 * 1024;
+const PAGE_NUM: usize = MEMORY_SIZE / PAGE_SIZE;
 
 pub struct Memory {
-    values: [MemoryValue; MEMORY_SIZE / WORD_SIZE],
+    values: Vec<MemoryValue>,
+    touched_pages: Vec<bool>,
 }
 
 impl Memory {
     pub fn new() -> Self {
         let init_val = 0;
-        let values = [init_val; MEMORY_SIZE / WORD_SIZE];
-        Memory { values }
+        let values = vec![init_val; MEMORY_SIZE / WORD_SIZE];
+        let touched_pages = vec![false; PAGE_NUM];
+        Memory {
+            values,
+            touched_pages,
+        }
     }
 
     // #[allow(dead_code)]
@@ -65,19 +72,53 @@ impl Memory {
 
     pub fn store_word(&mut self, addr: Address, value: Word) {
         self.values[addr as usize >> 2] = i32_to_u32(value);
+        self.touched_pages[addr as usize / PAGE_SIZE] = true;
+    }
+
+    pub fn get_cache_line(&self, addr: Address, line_size: usize) -> Vec<MemoryValue> {
+        (0..line_size / WORD_SIZE)
+            .map(|i| i32_to_u32(self.load_word(addr + i as Address * 4)))
+            .collect()
     }
 
-    pub fn get_cache_line(&self, addr: Address) -> [MemoryValue; LINE_SIZE / WORD_SIZE] {
-        let mut line = [0; LINE_SIZE / WORD_SIZE];
-        for (i, value) in line.iter_mut().enumerate() {
-            *value = i32_to_u32(self.load_word(addr + i as Address * 4));
+    /// Saves only the pages that have ever been written, since the others are still zero.
+    pub fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        let words_per_page = PAGE_SIZE / WORD_SIZE;
+        let touched_pages = (0..PAGE_NUM).filter(|&page| self.touched_pages[page]);
+        writer.put_usize(touched_pages.clone().count());
+        for page in touched_pages {
+            writer.put_usize(page);
+            for value in &self.values[page * words_per_page..(page + 1) * words_per_page] {
+                writer.put_u32(*value);
+            }
+        }
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        let words_per_page = PAGE_SIZE / WORD_SIZE;
+        // the other pages have never been written and are still zero
+        for page in 0..PAGE_NUM {
+            if self.touched_pages[page] {
+                self.values[page * words_per_page..(page + 1) * words_per_page].fill(0);
+                self.touched_pages[page] = false;
+            }
+        }
+        for _ in 0..reader.get_usize()? {
+            let page = reader.get_usize()?;
+            if page >= PAGE_NUM {
+                return Err(format!("page {} out of range", page));
+            }
+            self.touched_pages[page] = true;
+            for i in 0..words_per_page {
+                self.values[page * words_per_page + i] = reader.get_u32()?;
+            }
         }
-        line
+        Ok(())
     }
 
-    pub fn set_cache_line(&mut self, line: [(Address, MemoryValue); LINE_SIZE / WORD_SIZE]) {
-        for (addr, value) in line.iter() {
-            self.store_word(*addr, u32_to_i32(*value));
+    pub fn set_cache_line(&mut self, addr: Address, line: &[MemoryValue]) {
+        for (i, value) in line.iter().enumerate() {
+            self.store_word(addr + i as Address * 4, u32_to_i32(*value));
         }
     }
 }
@@ -0,0 +1,122 @@
+use linked_hash_map::LinkedHashMap;
+use std::collections::HashSet;
+
+use crate::cache::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MissClassCounts {
+    /// First access to the line.
+    pub compulsory: usize,
+    /// Also a miss in a fully-associative LRU cache of the same capacity.
+    pub capacity: usize,
+    /// A hit in the fully-associative cache, so caused by the mapping to sets.
+    pub conflict: usize,
+}
+
+/// Classifies the misses of a cache into the three Cs with a fully-associative LRU shadow cache of equal capacity
+/// and the set of lines ever touched.
+pub struct MissClassifier {
+    /// Lines of the shadow cache, least recently used first.
+    shadow: LinkedHashMap<Address, ()>,
+    touched_lines: HashSet<Address>,
+    line_capacity: usize,
+    line_size: usize,
+    allocates_on_store_miss: bool,
+    counts: MissClassCounts,
+}
+
+impl MissClassifier {
+    pub fn new(config: CacheConfig) -> Self {
+        let line_capacity = config.size / config.line_size;
+        MissClassifier {
+            shadow: LinkedHashMap::with_capacity(line_capacity),
+            touched_lines: HashSet::new(),
+            line_capacity,
+            line_size: config.line_size,
+            allocates_on_store_miss: config.allocates_on_store_miss(),
+            counts: MissClassCounts::default(),
+        }
+    }
+
+    pub fn get_counts(&self) -> MissClassCounts {
+        self.counts
+    }
+
+    /// Feeds every access of the real cache, with whether it hit there.
+    pub fn access(&mut self, addr: Address, is_store: bool, is_hit: bool) {
+        let line_addr = addr & !(self.line_size as Address - 1);
+        let is_shadow_hit = self.shadow.get_refresh(&line_addr).is_some();
+        // a line can be touched first by a hit, e.g. after the prefetcher brought it in
+        let is_first_access = self.touched_lines.insert(line_addr);
+        if !is_hit {
+            if is_first_access {
+                self.counts.compulsory += 1;
+            } else if is_shadow_hit {
+                self.counts.conflict += 1;
+            } else {
+                self.counts.capacity += 1;
+            }
+        }
+        // the shadow cache follows the allocation policy of the real one
+        if !is_shadow_hit && (!is_store || self.allocates_on_store_miss) {
+            if self.shadow.len() == self.line_capacity {
+                self.shadow.pop_front();
+            }
+            self.shadow.insert(line_addr, ());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_miss_classifier() {
+        let config = CacheConfig {
+            size: 32,
+            way_num: 1,
+            line_size: 16,
+            ..CacheConfig::default()
+        };
+        let mut classifier = MissClassifier::new(config);
+        // 0x00 and 0x20 share a set of the direct-mapped cache, but fit in the shadow cache
+        classifier.access(0x00, false, false);
+        classifier.access(0x20, false, false);
+        classifier.access(0x00, false, false);
+        // 0x10 pushes 0x20 out of the shadow cache
+        classifier.access(0x10, false, false);
+        classifier.access(0x20, false, false);
+        assert_eq!(
+            classifier.get_counts(),
+            MissClassCounts {
+                compulsory: 3,
+                capacity: 1,
+                conflict: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_miss_after_first_hit_is_not_compulsory() {
+        let config = CacheConfig {
+            size: 32,
+            way_num: 1,
+            line_size: 16,
+            ..CacheConfig::default()
+        };
+        let mut classifier = MissClassifier::new(config);
+        // a hit on a prefetched line, then a miss on it after the real cache dropped it
+        classifier.access(0x00, false, true);
+        classifier.access(0x04, false, false);
+        assert_eq!(
+            classifier.get_counts(),
+            MissClassCounts {
+                compulsory: 0,
+                capacity: 0,
+                conflict: 1
+            }
+        );
+    }
+}
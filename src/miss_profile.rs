@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use crate::cache::*;
+use crate::instruction_memory::*;
+use crate::types::*;
+use crate::utils::*;
+
+const SHOWN_SET_NUM: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MemoryRegion {
+    Globals,
+    Heap,
+    Stack,
+}
+
+const REGIONS: [MemoryRegion; 3] = [
+    MemoryRegion::Globals,
+    MemoryRegion::Heap,
+    MemoryRegion::Stack,
+];
+
+impl MemoryRegion {
+    /// The stack grows down from the top of memory, and the heap grows up from `heap_start` above the globals.
+    /// A zero stack pointer means the program has not set one up yet.
+    fn classify(addr: Address, sp: Address, heap_start: Address) -> Self {
+        if sp != 0 && addr >= sp {
+            MemoryRegion::Stack
+        } else if addr < heap_start {
+            MemoryRegion::Globals
+        } else {
+            MemoryRegion::Heap
+        }
+    }
+
+    fn get_name(&self) -> &'static str {
+        match self {
+            MemoryRegion::Globals => "globals",
+            MemoryRegion::Heap => "heap",
+            MemoryRegion::Stack => "stack",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct AccessCounts {
+    access_count: usize,
+    load_miss_count: usize,
+    store_miss_count: usize,
+}
+
+impl AccessCounts {
+    fn record(&mut self, is_store: bool, is_hit: bool) {
+        self.access_count += 1;
+        if !is_hit {
+            if is_store {
+                self.store_miss_count += 1;
+            } else {
+                self.load_miss_count += 1;
+            }
+        }
+    }
+
+    fn get_miss_count(&self) -> usize {
+        self.load_miss_count + self.store_miss_count
+    }
+
+    fn show(&self) {
+        println!(
+            " {:>10} {:>10} {:>10} {:>10} {:>9.5}%",
+            self.access_count,
+            self.access_count - self.get_miss_count(),
+            self.load_miss_count,
+            self.store_miss_count,
+            self.get_miss_count() as f64 / self.access_count as f64 * 100.0
+        );
+    }
+}
+
+/// Attributes the L1 misses of the core to the instructions, memory regions and cache sets causing them.
+pub struct MissProfile {
+    pc_counts: Vec<AccessCounts>,
+    region_counts: [AccessCounts; 3],
+    /// Misses of each line, from which the misses of each set are summed up.
+    line_miss_counts: HashMap<Address, usize>,
+    heap_start: Address,
+    config: CacheConfig,
+}
+
+impl MissProfile {
+    pub fn new(config: CacheConfig, heap_start: Address) -> Self {
+        MissProfile {
+            pc_counts: vec![AccessCounts::default(); INSTRUCTION_MEMORY_SIZE],
+            region_counts: [AccessCounts::default(); 3],
+            line_miss_counts: HashMap::new(),
+            heap_start,
+            config,
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        pc: Address,
+        addr: Address,
+        sp: Address,
+        is_store: bool,
+        is_hit: bool,
+    ) {
+        self.pc_counts[(pc >> 2) as usize].record(is_store, is_hit);
+        let region = MemoryRegion::classify(addr, sp, self.heap_start);
+        self.region_counts[region as usize].record(is_store, is_hit);
+        if !is_hit {
+            let line_addr = addr & !(self.config.line_size as Address - 1);
+            *self.line_miss_counts.entry(line_addr).or_insert(0) += 1;
+        }
+    }
+
+    fn get_set_index(&self, line_addr: Address) -> usize {
+        line_addr as usize / self.config.line_size % self.config.get_line_num()
+    }
+
+    /// Returns the misses and the number of distinct lines missed of every set with misses, most missed first.
+    fn get_set_miss_counts(&self) -> Vec<(usize, usize, usize)> {
+        let mut set_counts = HashMap::new();
+        for (line_addr, miss_count) in self.line_miss_counts.iter() {
+            let counts = set_counts
+                .entry(self.get_set_index(*line_addr))
+                .or_insert((0, 0));
+            counts.0 += miss_count;
+            counts.1 += 1;
+        }
+        let mut set_counts = set_counts
+            .into_iter()
+            .map(|(set, (miss_count, line_num))| (set, miss_count, line_num))
+            .collect::<Vec<_>>();
+        set_counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        set_counts
+    }
+
+    fn show_header(name: &str, width: usize) {
+        print_filled_with_space(&name.to_string(), width);
+        println!(
+            " {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "accesses", "hits", "load miss", "store miss", "miss rate"
+        );
+    }
+
+    /// `describe_pc` gives the text shown for the instruction at a pc.
+    pub fn show(&self, describe_pc: impl Fn(Address) -> String) {
+        println!("---------- miss stats ----------");
+        Self::show_header("pc", 40);
+        let mut pc_counts = self
+            .pc_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, counts)| counts.access_count != 0)
+            .collect::<Vec<_>>();
+        pc_counts.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.get_miss_count()));
+        for (index, counts) in pc_counts {
+            print_filled_with_space(&describe_pc((index * 4) as Address), 40);
+            counts.show();
+        }
+
+        println!();
+        Self::show_header("region", 8);
+        for region in REGIONS {
+            let counts = &self.region_counts[region as usize];
+            if counts.access_count != 0 {
+                print_filled_with_space(&region.get_name().to_string(), 8);
+                counts.show();
+            }
+        }
+
+        let set_counts = self.get_set_miss_counts();
+        let miss_count = set_counts.iter().map(|counts| counts.1).sum::<usize>();
+        println!();
+        println!(
+            "misses in {} of {} sets (top {} shown):",
+            set_counts.len(),
+            self.config.get_line_num(),
+            SHOWN_SET_NUM.min(set_counts.len())
+        );
+        println!(
+            "{:>8} {:>10} {:>10} {:>10}",
+            "set", "misses", "share", "lines"
+        );
+        for (set, set_miss_count, line_num) in set_counts.iter().take(SHOWN_SET_NUM) {
+            println!(
+                "{:>8} {:>10} {:>9.5}% {:>10}",
+                set,
+                set_miss_count,
+                *set_miss_count as f64 / miss_count as f64 * 100.0,
+                line_num
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_miss_profile() {
+        let config = CacheConfig {
+            size: 64,
+            way_num: 1,
+            line_size: 16,
+            ..CacheConfig::default()
+        };
+        let mut profile = MissProfile::new(config, 0x100);
+        profile.record(0, 0x10, 0x1000, false, false);
+        profile.record(0, 0x50, 0x1000, false, false);
+        profile.record(4, 0x200, 0x1000, true, true);
+        profile.record(8, 0x1004, 0x1000, true, false);
+        assert_eq!(profile.pc_counts[0].load_miss_count, 2);
+        assert_eq!(
+            profile.region_counts[MemoryRegion::Globals as usize].access_count,
+            2
+        );
+        assert_eq!(
+            profile.region_counts[MemoryRegion::Heap as usize].access_count,
+            1
+        );
+        assert_eq!(
+            profile.region_counts[MemoryRegion::Stack as usize].store_miss_count,
+            1
+        );
+        // 0x10 and 0x50 conflict in set 1
+        assert_eq!(profile.get_set_miss_counts(), [(1, 2, 2), (0, 1, 1)]);
+    }
+}
@@ -0,0 +1,475 @@
+use serde::Deserialize;
+
+use crate::fpu_scoreboard::*;
+use crate::instruction::*;
+use crate::snapshot::*;
+use crate::superscalar::*;
+
+const REGISTER_NUM: usize = 64;
+
+/// Shape of the in-order pipeline; stages are numbered from 1, the fetch stage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub stage_num: usize,
+    /// Stage where the ALU and the FPU compute and operands are needed.
+    pub execute_stage: usize,
+    /// Stage where loads get their data and where data cache stalls hold the pipeline.
+    pub memory_stage: usize,
+    /// Stage where a flushing branch or jump redirects the fetch.
+    pub branch_stage: usize,
+    /// Whether results are forwarded to the execute stage as soon as they are computed,
+    /// instead of being read from the register file after write-back.
+    pub forwarding: bool,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        // a taken branch costs `branch_stage - 1` cycles, which is `FLUSH_STALL` of the analytic model
+        PipelineConfig {
+            stage_num: 5,
+            execute_stage: 3,
+            memory_stage: 4,
+            branch_stage: 4,
+            forwarding: true,
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.execute_stage < 2 {
+            return Err("pipeline execute stage must come after the fetch stage".to_string());
+        }
+        for (name, stage) in [
+            ("memory stage", self.memory_stage),
+            ("branch stage", self.branch_stage),
+        ] {
+            if stage < self.execute_stage || stage > self.stage_num {
+                return Err(format!(
+                    "pipeline {} must be between the execute stage ({}) and the last stage ({})",
+                    name, self.execute_stage, self.stage_num
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What an executed instruction did, as far as the timing is concerned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IssuedInstruction {
+    pub operands: Operands,
+    pub is_load: bool,
+    /// Whether the instruction uses the load/store unit.
+    pub is_memory: bool,
+    pub is_fpu: bool,
+    pub is_control_transfer: bool,
+    /// Cycles the FPU takes beyond the first one.
+    pub fpu_stall: usize,
+    /// Whether the instruction flushes the pipeline and redirects the fetch.
+    pub flushes: bool,
+    pub fetch_stall_cycle_num: u128,
+    /// Cycles the data cache hierarchy stalled the memory stage for.
+    pub memory_stall_cycle_num: u128,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StallCause {
+    Flush,
+    Fetch,
+    Data,
+    LoadUse,
+    Fpu,
+    Memory,
+}
+
+/// Cycles an instruction waited before the execute stage, by what it waited for.
+/// A wait with several causes is split among them, the earliest resolved cause first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStallCycles {
+    pub flush: u128,
+    /// Instruction cache misses.
+    pub fetch: u128,
+    /// Operands computed by the ALU or the FPU.
+    pub data: u128,
+    /// Operands loaded from memory.
+    pub load_use: u128,
+    /// FPU operations waiting for the FPU to finish the previous one.
+    pub fpu: u128,
+    /// The memory stage held by a data cache stall.
+    pub memory: u128,
+}
+
+impl PipelineStallCycles {
+    fn get_mut(&mut self, cause: StallCause) -> &mut u128 {
+        match cause {
+            StallCause::Flush => &mut self.flush,
+            StallCause::Fetch => &mut self.fetch,
+            StallCause::Data => &mut self.data,
+            StallCause::LoadUse => &mut self.load_use,
+            StallCause::Fpu => &mut self.fpu,
+            StallCause::Memory => &mut self.memory,
+        }
+    }
+
+    pub fn get_total(&self) -> u128 {
+        self.flush + self.fetch + self.data + self.load_use + self.fpu + self.memory
+    }
+}
+
+/// Computes the cycle every instruction enters the execute stage and retires in an in-order pipeline,
+/// which issues one instruction per cycle unless it is given a wider `SuperscalarConfig`.
+pub struct Pipeline {
+    config: PipelineConfig,
+    /// Instructions entering the execute stage in the cycle of the last one.
+    issue_group: IssueGroup,
+    fpu_config: FpuConfig,
+    /// First cycle the execute stage can use the value of each register, numbered as in `Operands`.
+    ready_cycles: [u128; REGISTER_NUM],
+    /// Whether the last write of each register was a load.
+    is_loaded: [bool; REGISTER_NUM],
+    /// Earliest cycle the FPU takes the next operation at.
+    fpu_free_cycle: u128,
+    /// Earliest cycle after the memory stage held by the last instruction holding it.
+    memory_free_cycle: u128,
+    /// Cycle that instruction entered the execute stage at; the rest of its issue group moves along with it.
+    memory_hold_cycle: u128,
+    next_fetch_cycle: u128,
+    is_redirected: bool,
+    retire_cycle: Option<u128>,
+    /// Instructions retired at `retire_cycle`.
+    retire_count: usize,
+    instruction_count: u128,
+    stall_cycles: PipelineStallCycles,
+}
+
+impl Pipeline {
+    /// The configs are assumed to have passed their `validate`.
+    pub fn new(
+        config: PipelineConfig,
+        superscalar_config: SuperscalarConfig,
+        fpu_config: FpuConfig,
+    ) -> Self {
+        Pipeline {
+            config,
+            issue_group: IssueGroup::new(superscalar_config),
+            fpu_config,
+            ready_cycles: [0; REGISTER_NUM],
+            is_loaded: [false; REGISTER_NUM],
+            fpu_free_cycle: 0,
+            memory_free_cycle: 0,
+            memory_hold_cycle: 0,
+            next_fetch_cycle: 0,
+            is_redirected: false,
+            retire_cycle: None,
+            retire_count: 0,
+            instruction_count: 0,
+            stall_cycles: PipelineStallCycles::default(),
+        }
+    }
+
+    pub fn get_config(&self) -> PipelineConfig {
+        self.config
+    }
+
+    #[cfg(test)]
+    pub fn get_issue_group(&self) -> &IssueGroup {
+        &self.issue_group
+    }
+
+    /// Cycles from the first fetch to the last retirement.
+    pub fn get_cycle_num(&self) -> u128 {
+        self.retire_cycle.map_or(0, |cycle| cycle + 1)
+    }
+
+    pub fn issue(&mut self, inst: &IssuedInstruction) {
+        let config = &self.config;
+        let execute_stage = config.execute_stage as u128;
+        let fetched_cycle = self.next_fetch_cycle + execute_stage - 1;
+        let mut base_cycle = self.issue_group.get_earliest_cycle(inst);
+        // a wait on cycle 0 never stalls, so it stands for a missing one
+        let mut waits = [
+            (0, StallCause::Flush),
+            (
+                fetched_cycle + inst.fetch_stall_cycle_num,
+                StallCause::Fetch,
+            ),
+            (0, StallCause::Fpu),
+            (self.memory_free_cycle, StallCause::Memory),
+            (0, StallCause::Data),
+            (0, StallCause::Data),
+        ];
+        if inst.is_fpu {
+            waits[2].0 = self.fpu_free_cycle;
+        }
+        if self.is_redirected {
+            waits[0].0 = fetched_cycle;
+        } else {
+            base_cycle = base_cycle.max(fetched_cycle);
+        }
+        for (wait, source) in waits[4..].iter_mut().zip(inst.operands.sources) {
+            if let Some(source) = source {
+                let cause = if self.is_loaded[source] {
+                    StallCause::LoadUse
+                } else {
+                    StallCause::Data
+                };
+                *wait = (self.ready_cycles[source], cause);
+            }
+        }
+        waits.sort_by_key(|(cycle, _)| *cycle);
+        let mut execute_cycle = base_cycle;
+        for (cycle, cause) in waits {
+            if cause == StallCause::Memory && execute_cycle == self.memory_hold_cycle {
+                continue;
+            }
+            if cycle > execute_cycle {
+                *self.stall_cycles.get_mut(cause) += cycle - execute_cycle;
+                execute_cycle = cycle;
+            }
+        }
+
+        let fpu_stall = inst.fpu_stall as u128;
+        let computed_cycle = execute_cycle + fpu_stall + inst.memory_stall_cycle_num;
+        if let Some(dest) = inst.operands.dest {
+            let stage = if !config.forwarding {
+                config.stage_num
+            } else if inst.is_load {
+                config.memory_stage
+            } else {
+                config.execute_stage
+            };
+            self.ready_cycles[dest] = computed_cycle + stage as u128 - execute_stage + 1;
+            self.is_loaded[dest] = inst.is_load;
+        }
+        let retire_cycle = computed_cycle + config.stage_num as u128 - execute_stage;
+        let issue_width = self.issue_group.get_config().issue_width;
+        let retire_cycle = match self.retire_cycle {
+            Some(last_cycle) if retire_cycle <= last_cycle && self.retire_count < issue_width => {
+                last_cycle
+            }
+            Some(last_cycle) => retire_cycle.max(last_cycle + 1),
+            None => retire_cycle,
+        };
+        self.retire_count = match self.retire_cycle {
+            Some(last_cycle) if last_cycle == retire_cycle => self.retire_count + 1,
+            _ => 1,
+        };
+        self.retire_cycle = Some(retire_cycle);
+        self.issue_group.add(inst, execute_cycle);
+        if inst.is_fpu {
+            self.fpu_free_cycle = if self.fpu_config.pipelined {
+                execute_cycle + 1
+            } else {
+                execute_cycle + 1 + fpu_stall
+            };
+        }
+        if inst.memory_stall_cycle_num != 0 {
+            self.memory_free_cycle = execute_cycle + 1 + inst.memory_stall_cycle_num;
+            self.memory_hold_cycle = execute_cycle;
+        }
+        self.is_redirected = inst.flushes;
+        self.next_fetch_cycle = if inst.flushes {
+            execute_cycle + (config.branch_stage - config.execute_stage) as u128 + 1
+        } else {
+            // the fetch stage is one issue group ahead of the decode stage
+            execute_cycle + 1 + self.issue_group.is_full() as u128 - execute_stage
+        };
+        self.instruction_count += 1;
+    }
+
+    /// `name` labels the counts of this model, and `baseline_name` those of `baseline_cycle_num`,
+    /// the prediction of another model shown for comparison.
+    pub fn show(&self, name: &str, baseline_name: &str, baseline_cycle_num: u128) {
+        let config = &self.config;
+        let cycle_num = self.get_cycle_num();
+        println!(
+            "pipeline: {} stages, execute at {}, memory at {}, branch at {}, {}, {} fpu",
+            config.stage_num,
+            config.execute_stage,
+            config.memory_stage,
+            config.branch_stage,
+            if config.forwarding {
+                "forwarding"
+            } else {
+                "no forwarding"
+            },
+            if self.fpu_config.pipelined {
+                "pipelined"
+            } else {
+                "unpipelined"
+            }
+        );
+        let is_superscalar = self.issue_group.get_config() != SuperscalarConfig::scalar();
+        if is_superscalar {
+            self.issue_group.show();
+        }
+        println!("{} cycle count: {}", name, cycle_num);
+        println!("{} cycle count: {}", baseline_name, baseline_cycle_num);
+        // the ratios are left out of an empty run, where both counts are 0
+        if self.instruction_count != 0 {
+            println!(
+                "cycles per instruction: {:.5}",
+                cycle_num as f64 / self.instruction_count as f64
+            );
+        }
+        if is_superscalar && self.instruction_count != 0 {
+            println!(
+                "instructions per cycle: {:.5}",
+                self.instruction_count as f64 / cycle_num as f64
+            );
+            println!(
+                "speedup over {}: {:.5}",
+                baseline_name,
+                baseline_cycle_num as f64 / cycle_num as f64
+            );
+        }
+        let stalls = &self.stall_cycles;
+        println!(
+            "{} stall cycles: {} (flush: {}, fetch: {}, data: {}, load-use: {}, fpu: {}, memory: {})",
+            name,
+            stalls.get_total(),
+            stalls.flush,
+            stalls.fetch,
+            stalls.data,
+            stalls.load_use,
+            stalls.fpu,
+            stalls.memory
+        );
+    }
+
+    pub fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        for (ready_cycle, is_loaded) in self.ready_cycles.iter().zip(self.is_loaded.iter()) {
+            writer.put_u128(*ready_cycle);
+            writer.put_bool(*is_loaded);
+        }
+        self.issue_group.write_snapshot(writer);
+        for cycle in [
+            self.fpu_free_cycle,
+            self.memory_free_cycle,
+            self.memory_hold_cycle,
+            self.next_fetch_cycle,
+            self.instruction_count,
+        ] {
+            writer.put_u128(cycle);
+        }
+        writer.put_bool(self.is_redirected);
+        writer.put_bool(self.retire_cycle.is_some());
+        writer.put_u128(self.retire_cycle.unwrap_or(0));
+        writer.put_usize(self.retire_count);
+        let stalls = &self.stall_cycles;
+        for cycle in [
+            stalls.flush,
+            stalls.fetch,
+            stalls.data,
+            stalls.load_use,
+            stalls.fpu,
+            stalls.memory,
+        ] {
+            writer.put_u128(cycle);
+        }
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        for (ready_cycle, is_loaded) in self.ready_cycles.iter_mut().zip(self.is_loaded.iter_mut())
+        {
+            *ready_cycle = reader.get_u128()?;
+            *is_loaded = reader.get_bool()?;
+        }
+        self.issue_group.read_snapshot(reader)?;
+        for cycle in [
+            &mut self.fpu_free_cycle,
+            &mut self.memory_free_cycle,
+            &mut self.memory_hold_cycle,
+            &mut self.next_fetch_cycle,
+            &mut self.instruction_count,
+        ] {
+            *cycle = reader.get_u128()?;
+        }
+        self.is_redirected = reader.get_bool()?;
+        let has_retired = reader.get_bool()?;
+        let retire_cycle = reader.get_u128()?;
+        self.retire_cycle = has_retired.then_some(retire_cycle);
+        self.retire_count = reader.get_usize()?;
+        let stalls = &mut self.stall_cycles;
+        for cycle in [
+            &mut stalls.flush,
+            &mut stalls.fetch,
+            &mut stalls.data,
+            &mut stalls.load_use,
+            &mut stalls.fpu,
+            &mut stalls.memory,
+        ] {
+            *cycle = reader.get_u128()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An instruction with only operands, for the other fields to be set with struct update syntax.
+    pub(crate) fn inst(sources: [Option<usize>; 2], dest: Option<usize>) -> IssuedInstruction {
+        IssuedInstruction {
+            operands: Operands { sources, dest },
+            ..IssuedInstruction::default()
+        }
+    }
+
+    #[test]
+    fn test_pipeline_config_validate() {
+        assert!(PipelineConfig::default().validate().is_ok());
+        assert!(PipelineConfig {
+            branch_stage: 6,
+            ..PipelineConfig::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let lw = IssuedInstruction {
+            is_load: true,
+            ..inst([Some(2), None], Some(1))
+        };
+        let add = inst([Some(1), None], Some(3));
+        let beq = IssuedInstruction {
+            flushes: true,
+            ..inst([Some(3), None], None)
+        };
+        let fadd = IssuedInstruction {
+            is_fpu: true,
+            fpu_stall: 2,
+            ..inst([Some(34), Some(35)], Some(33))
+        };
+        let fsw = IssuedInstruction {
+            memory_stall_cycle_num: 5,
+            ..inst([Some(2), Some(33)], None)
+        };
+        let addi = inst([Some(4), None], Some(4));
+        let mut pipeline = Pipeline::new(
+            PipelineConfig::default(),
+            SuperscalarConfig::scalar(),
+            FpuConfig::default(),
+        );
+        for inst in [lw, add, beq, fadd, fsw, addi] {
+            pipeline.issue(&inst);
+        }
+        assert_eq!(
+            pipeline.stall_cycles,
+            PipelineStallCycles {
+                flush: 3,
+                load_use: 1,
+                data: 2,
+                memory: 5,
+                ..PipelineStallCycles::default()
+            }
+        );
+        // 6 instructions, 4 cycles to fill the pipeline and the stalls
+        assert_eq!(pipeline.get_cycle_num(), 6 + 4 + 11);
+    }
+}
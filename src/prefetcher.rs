@@ -0,0 +1,342 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fmt;
+
+use crate::snapshot::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrefetcherKind {
+    /// Fetches the lines following a missed line, and those following a prefetched line on its first use.
+    #[default]
+    NextLine,
+    /// Fetches ahead of loads whose addresses change by a constant stride, tracked per pc.
+    Stride,
+    /// Fetches ahead of sequential streams of lines, in either direction.
+    Stream,
+}
+
+impl fmt::Display for PrefetcherKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            PrefetcherKind::NextLine => "next-line",
+            PrefetcherKind::Stride => "stride",
+            PrefetcherKind::Stream => "stream",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrefetchConfig {
+    pub kind: PrefetcherKind,
+    /// Number of lines (or strides) fetched ahead on each trigger.
+    pub degree: usize,
+    /// Entries of the pc-indexed table of the stride prefetcher.
+    pub table_size: usize,
+    /// Number of streams the stream prefetcher follows at once.
+    pub stream_num: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        PrefetchConfig {
+            kind: PrefetcherKind::NextLine,
+            degree: 1,
+            table_size: 64,
+            stream_num: 4,
+        }
+    }
+}
+
+impl PrefetchConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("prefetch degree", self.degree),
+            ("prefetch table size", self.table_size),
+            ("prefetch stream num", self.stream_num),
+        ] {
+            if value == 0 {
+                return Err(format!("{} must be positive", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A load seen by the prefetcher.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadAccess {
+    pub pc: Address,
+    pub addr: Address,
+    pub is_hit: bool,
+    /// The first use of a line brought in by a prefetch.
+    pub is_prefetch_hit: bool,
+}
+
+/// Watches the loads of the core and returns the addresses to prefetch.
+pub trait Prefetcher {
+    fn observe(&mut self, access: &LoadAccess, line_size: usize) -> Vec<Address>;
+
+    fn write_snapshot(&self, _writer: &mut SnapshotWriter) {}
+
+    fn read_snapshot(&mut self, _reader: &mut SnapshotReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// The config is assumed to have passed `PrefetchConfig::validate`.
+pub fn create_prefetcher(config: &PrefetchConfig) -> Box<dyn Prefetcher> {
+    match config.kind {
+        PrefetcherKind::NextLine => Box::new(NextLinePrefetcher {
+            degree: config.degree,
+        }),
+        PrefetcherKind::Stride => Box::new(StridePrefetcher::new(config.degree, config.table_size)),
+        PrefetcherKind::Stream => Box::new(StreamPrefetcher::new(config.degree, config.stream_num)),
+    }
+}
+
+/// Returns `degree` addresses `step` bytes apart after `addr`, stopping at the ends of the address space.
+fn get_addrs_ahead(addr: Address, step: i64, degree: usize) -> Vec<Address> {
+    (1..=degree as i64)
+        .map_while(|k| Address::try_from(addr as i64 + step * k).ok())
+        .collect()
+}
+
+struct NextLinePrefetcher {
+    degree: usize,
+}
+
+impl Prefetcher for NextLinePrefetcher {
+    fn observe(&mut self, access: &LoadAccess, line_size: usize) -> Vec<Address> {
+        if access.is_hit && !access.is_prefetch_hit {
+            return vec![];
+        }
+        let line_addr = access.addr & !(line_size as Address - 1);
+        get_addrs_ahead(line_addr, line_size as i64, self.degree)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct StrideEntry {
+    pc: Address,
+    last_addr: Address,
+    stride: i64,
+    /// Whether the last two strides were equal.
+    is_steady: bool,
+}
+
+/// Keeps the last address and stride of each load in a direct-mapped table indexed by pc.
+struct StridePrefetcher {
+    table: Vec<Option<StrideEntry>>,
+    degree: usize,
+}
+
+impl StridePrefetcher {
+    fn new(degree: usize, table_size: usize) -> Self {
+        StridePrefetcher {
+            table: vec![None; table_size],
+            degree,
+        }
+    }
+}
+
+impl Prefetcher for StridePrefetcher {
+    fn observe(&mut self, access: &LoadAccess, _line_size: usize) -> Vec<Address> {
+        let index = (access.pc >> 2) as usize % self.table.len();
+        let entry = match self.table[index].as_mut() {
+            Some(entry) if entry.pc == access.pc => entry,
+            _ => {
+                self.table[index] = Some(StrideEntry {
+                    pc: access.pc,
+                    last_addr: access.addr,
+                    ..StrideEntry::default()
+                });
+                return vec![];
+            }
+        };
+        let stride = access.addr as i64 - entry.last_addr as i64;
+        entry.is_steady = stride != 0 && stride == entry.stride;
+        entry.stride = stride;
+        entry.last_addr = access.addr;
+        if !entry.is_steady {
+            return vec![];
+        }
+        get_addrs_ahead(access.addr, stride, self.degree)
+    }
+
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        for entry in self.table.iter() {
+            writer.put_bool(entry.is_some());
+            if let Some(entry) = entry {
+                writer.put_u32(entry.pc);
+                writer.put_u32(entry.last_addr);
+                writer.put_u64(entry.stride as u64);
+                writer.put_bool(entry.is_steady);
+            }
+        }
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        for entry in self.table.iter_mut() {
+            *entry = if reader.get_bool()? {
+                Some(StrideEntry {
+                    pc: reader.get_u32()?,
+                    last_addr: reader.get_u32()?,
+                    stride: reader.get_u64()? as i64,
+                    is_steady: reader.get_bool()?,
+                })
+            } else {
+                None
+            };
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Stream {
+    last_line_addr: Address,
+    /// 1 or -1 once two adjacent lines have been seen, and 0 before.
+    direction: i64,
+    last_used: u64,
+}
+
+/// Starts a stream on a miss and follows it when the next access lands on an adjacent line.
+/// The least recently used stream is replaced when all of them are taken.
+struct StreamPrefetcher {
+    streams: Vec<Stream>,
+    stream_num: usize,
+    degree: usize,
+    clock: u64,
+}
+
+impl StreamPrefetcher {
+    fn new(degree: usize, stream_num: usize) -> Self {
+        StreamPrefetcher {
+            streams: Vec::with_capacity(stream_num),
+            stream_num,
+            degree,
+            clock: 0,
+        }
+    }
+}
+
+impl Prefetcher for StreamPrefetcher {
+    fn observe(&mut self, access: &LoadAccess, line_size: usize) -> Vec<Address> {
+        self.clock += 1;
+        let line_addr = access.addr & !(line_size as Address - 1);
+        let step = line_size as i64;
+        for stream in self.streams.iter_mut() {
+            let distance = line_addr as i64 - stream.last_line_addr as i64;
+            if distance == 0 {
+                stream.last_used = self.clock;
+                return vec![];
+            }
+            let is_next = if stream.direction == 0 {
+                distance.abs() == step
+            } else {
+                distance == stream.direction * step
+            };
+            if is_next {
+                stream.direction = distance.signum();
+                stream.last_line_addr = line_addr;
+                stream.last_used = self.clock;
+                return get_addrs_ahead(line_addr, stream.direction * step, self.degree);
+            }
+        }
+        if !access.is_hit {
+            let stream = Stream {
+                last_line_addr: line_addr,
+                direction: 0,
+                last_used: self.clock,
+            };
+            if self.streams.len() < self.stream_num {
+                self.streams.push(stream);
+            } else {
+                let victim = self
+                    .streams
+                    .iter_mut()
+                    .min_by_key(|stream| stream.last_used)
+                    .unwrap();
+                *victim = stream;
+            }
+        }
+        vec![]
+    }
+
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.put_u64(self.clock);
+        writer.put_usize(self.streams.len());
+        for stream in self.streams.iter() {
+            writer.put_u32(stream.last_line_addr);
+            writer.put_u64(stream.direction as u64);
+            writer.put_u64(stream.last_used);
+        }
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        self.clock = reader.get_u64()?;
+        let stream_num = reader.get_usize()?;
+        if stream_num > self.stream_num {
+            return Err(format!(
+                "prefetch stream num mismatch (snapshot: {}, current: {})",
+                stream_num, self.stream_num
+            ));
+        }
+        self.streams.clear();
+        for _ in 0..stream_num {
+            self.streams.push(Stream {
+                last_line_addr: reader.get_u32()?,
+                direction: reader.get_u64()? as i64,
+                last_used: reader.get_u64()?,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe(prefetcher: &mut dyn Prefetcher, pc: Address, addr: Address) -> Vec<Address> {
+        let access = LoadAccess {
+            pc,
+            addr,
+            is_hit: false,
+            is_prefetch_hit: false,
+        };
+        prefetcher.observe(&access, 16)
+    }
+
+    #[test]
+    fn test_prefetchers() {
+        let config = PrefetchConfig {
+            degree: 2,
+            ..PrefetchConfig::default()
+        };
+        let mut next_line = create_prefetcher(&config);
+        assert_eq!(observe(next_line.as_mut(), 0, 0x104), [0x110, 0x120]);
+
+        let mut stride = create_prefetcher(&PrefetchConfig {
+            kind: PrefetcherKind::Stride,
+            ..config
+        });
+        assert!(observe(stride.as_mut(), 8, 0x100).is_empty());
+        assert!(observe(stride.as_mut(), 8, 0x140).is_empty());
+        assert_eq!(observe(stride.as_mut(), 8, 0x180), [0x1c0, 0x200]);
+        // another load in the same entry resets it
+        assert!(observe(stride.as_mut(), 8 + 64 * 4, 0x1c0).is_empty());
+
+        let mut stream = create_prefetcher(&PrefetchConfig {
+            kind: PrefetcherKind::Stream,
+            ..config
+        });
+        assert!(observe(stream.as_mut(), 0, 0x200).is_empty());
+        assert_eq!(observe(stream.as_mut(), 0, 0x1f0), [0x1e0, 0x1d0]);
+        assert!(observe(stream.as_mut(), 0, 0x200).is_empty());
+        assert_eq!(observe(stream.as_mut(), 0, 0x1e0), [0x1d0, 0x1c0]);
+    }
+}
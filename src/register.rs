@@ -35,3 +35,18 @@ impl FloatRegister {
         self.value
     }
 }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterId {
+    Int(usize),
+    Float(usize),
+}
+
+impl std::fmt::Display for RegisterId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterId::Int(index) => write!(f, "x{}", index),
+            RegisterId::Float(index) => write!(f, "f{}", index),
+        }
+    }
+}
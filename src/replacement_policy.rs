@@ -0,0 +1,305 @@
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::fmt;
+
+use crate::cache::CacheLine;
+use crate::snapshot::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplacementPolicyKind {
+    /// Least recently used.
+    #[default]
+    Lru,
+    /// First in, first out.
+    Fifo,
+    /// Uniformly random, reproducible with the seed.
+    Random,
+    /// Tree pseudo-LRU (the number of ways has to be a power of two).
+    Plru,
+    /// Not recently used, with the accessed bit of each line.
+    Nru,
+}
+
+impl fmt::Display for ReplacementPolicyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            ReplacementPolicyKind::Lru => "lru",
+            ReplacementPolicyKind::Fifo => "fifo",
+            ReplacementPolicyKind::Random => "random",
+            ReplacementPolicyKind::Plru => "plru",
+            ReplacementPolicyKind::Nru => "nru",
+        })
+    }
+}
+
+/// Decides which way of a full set is evicted.
+/// Invalid ways are always filled first, so `choose_victim` is only called on full sets.
+pub trait ReplacementPolicy {
+    fn on_hit(&mut self, index: CacheIndex, way: usize);
+
+    fn on_fill(&mut self, index: CacheIndex, way: usize);
+
+    fn choose_victim(&mut self, index: CacheIndex, lines: &mut [CacheLine]) -> usize;
+
+    fn write_snapshot(&self, _writer: &mut SnapshotWriter) {}
+
+    fn read_snapshot(&mut self, _reader: &mut SnapshotReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub fn create_replacement_policy(
+    kind: ReplacementPolicyKind,
+    line_num: usize,
+    way_num: usize,
+    seed: u64,
+) -> Box<dyn ReplacementPolicy> {
+    match kind {
+        ReplacementPolicyKind::Lru => Box::new(StampPolicy::new(line_num, way_num, true)),
+        ReplacementPolicyKind::Fifo => Box::new(StampPolicy::new(line_num, way_num, false)),
+        ReplacementPolicyKind::Random => Box::new(RandomPolicy::new(way_num, seed)),
+        ReplacementPolicyKind::Plru => Box::new(TreePlruPolicy::new(line_num, way_num)),
+        ReplacementPolicyKind::Nru => Box::new(NruPolicy),
+    }
+}
+
+/// Evicts the way with the oldest stamp; LRU stamps on every access and FIFO only on fills.
+struct StampPolicy {
+    stamps: Vec<u64>,
+    clock: u64,
+    way_num: usize,
+    stamp_on_hit: bool,
+}
+
+impl StampPolicy {
+    fn new(line_num: usize, way_num: usize, stamp_on_hit: bool) -> Self {
+        StampPolicy {
+            stamps: vec![0; line_num * way_num],
+            clock: 0,
+            way_num,
+            stamp_on_hit,
+        }
+    }
+
+    fn stamp(&mut self, index: CacheIndex, way: usize) {
+        self.clock += 1;
+        self.stamps[index * self.way_num + way] = self.clock;
+    }
+}
+
+impl ReplacementPolicy for StampPolicy {
+    fn on_hit(&mut self, index: CacheIndex, way: usize) {
+        if self.stamp_on_hit {
+            self.stamp(index, way);
+        }
+    }
+
+    fn on_fill(&mut self, index: CacheIndex, way: usize) {
+        self.stamp(index, way);
+    }
+
+    fn choose_victim(&mut self, index: CacheIndex, _lines: &mut [CacheLine]) -> usize {
+        let stamps = &self.stamps[index * self.way_num..(index + 1) * self.way_num];
+        (0..self.way_num).min_by_key(|&way| stamps[way]).unwrap()
+    }
+
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.put_u64(self.clock);
+        for stamp in self.stamps.iter() {
+            writer.put_u64(*stamp);
+        }
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        self.clock = reader.get_u64()?;
+        for stamp in self.stamps.iter_mut() {
+            *stamp = reader.get_u64()?;
+        }
+        Ok(())
+    }
+}
+
+/// The generator state cannot be saved, so a snapshot records the number of draws to replay instead.
+struct RandomPolicy {
+    rng: StdRng,
+    seed: u64,
+    draw_count: u64,
+    way_num: usize,
+}
+
+impl RandomPolicy {
+    fn new(way_num: usize, seed: u64) -> Self {
+        RandomPolicy {
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            draw_count: 0,
+            way_num,
+        }
+    }
+}
+
+impl ReplacementPolicy for RandomPolicy {
+    fn on_hit(&mut self, _index: CacheIndex, _way: usize) {}
+
+    fn on_fill(&mut self, _index: CacheIndex, _way: usize) {}
+
+    fn choose_victim(&mut self, _index: CacheIndex, _lines: &mut [CacheLine]) -> usize {
+        self.draw_count += 1;
+        self.rng.gen_range(0..self.way_num)
+    }
+
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.put_u64(self.seed);
+        writer.put_u64(self.draw_count);
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        let seed = reader.get_u64()?;
+        if seed != self.seed {
+            return Err(format!(
+                "random seed mismatch (snapshot: {}, current: {})",
+                seed, self.seed
+            ));
+        }
+        *self = Self::new(self.way_num, seed);
+        for _ in 0..reader.get_u64()? {
+            self.choose_victim(0, &mut []);
+        }
+        Ok(())
+    }
+}
+
+/// Keeps `way_num - 1` bits per set as a binary tree; each bit points to the half to evict next.
+struct TreePlruPolicy {
+    bits: Vec<bool>,
+    way_num: usize,
+}
+
+impl TreePlruPolicy {
+    fn new(line_num: usize, way_num: usize) -> Self {
+        TreePlruPolicy {
+            bits: vec![false; line_num * (way_num - 1)],
+            way_num,
+        }
+    }
+
+    fn get_tree(&mut self, index: CacheIndex) -> &mut [bool] {
+        let node_num = self.way_num - 1;
+        &mut self.bits[index * node_num..(index + 1) * node_num]
+    }
+
+    /// Points every node on the path to `way` away from it.
+    fn touch(&mut self, index: CacheIndex, way: usize) {
+        let way_num = self.way_num;
+        let tree = self.get_tree(index);
+        let mut node = 0;
+        let mut half = way_num / 2;
+        let mut first = 0;
+        while half > 0 {
+            let is_right = way >= first + half;
+            tree[node] = !is_right;
+            if is_right {
+                first += half;
+            }
+            node = 2 * node + 1 + is_right as usize;
+            half /= 2;
+        }
+    }
+}
+
+impl ReplacementPolicy for TreePlruPolicy {
+    fn on_hit(&mut self, index: CacheIndex, way: usize) {
+        self.touch(index, way);
+    }
+
+    fn on_fill(&mut self, index: CacheIndex, way: usize) {
+        self.touch(index, way);
+    }
+
+    fn choose_victim(&mut self, index: CacheIndex, _lines: &mut [CacheLine]) -> usize {
+        let way_num = self.way_num;
+        let tree = self.get_tree(index);
+        let mut node = 0;
+        let mut half = way_num / 2;
+        let mut first = 0;
+        while half > 0 {
+            let is_right = tree[node];
+            if is_right {
+                first += half;
+            }
+            node = 2 * node + 1 + is_right as usize;
+            half /= 2;
+        }
+        first
+    }
+
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        for bit in self.bits.iter() {
+            writer.put_bool(*bit);
+        }
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        for bit in self.bits.iter_mut() {
+            *bit = reader.get_bool()?;
+        }
+        Ok(())
+    }
+}
+
+/// Evicts the first line whose accessed bit is clear, clearing all of them when every line has been accessed.
+/// The bits live in the lines themselves, so there is no state of its own.
+struct NruPolicy;
+
+impl ReplacementPolicy for NruPolicy {
+    fn on_hit(&mut self, _index: CacheIndex, _way: usize) {}
+
+    fn on_fill(&mut self, _index: CacheIndex, _way: usize) {}
+
+    fn choose_victim(&mut self, _index: CacheIndex, lines: &mut [CacheLine]) -> usize {
+        if let Some(way) = lines.iter().position(|line| !line.accessed) {
+            return way;
+        }
+        for line in lines.iter_mut() {
+            line.accessed = false;
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn victims(kind: ReplacementPolicyKind, hits: &[usize]) -> Vec<usize> {
+        let mut policy = create_replacement_policy(kind, 1, 4, 0);
+        for way in 0..4 {
+            policy.on_fill(0, way);
+        }
+        for &way in hits {
+            policy.on_hit(0, way);
+        }
+        (0..3)
+            .map(|_| {
+                let way = policy.choose_victim(0, &mut []);
+                policy.on_fill(0, way);
+                way
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_replacement_policies() {
+        assert_eq!(victims(ReplacementPolicyKind::Lru, &[0, 2]), [1, 3, 0]);
+        assert_eq!(victims(ReplacementPolicyKind::Fifo, &[0, 2]), [0, 1, 2]);
+        assert_eq!(victims(ReplacementPolicyKind::Plru, &[0]), [2, 1, 3]);
+        assert_eq!(
+            victims(ReplacementPolicyKind::Random, &[]),
+            victims(ReplacementPolicyKind::Random, &[])
+        );
+    }
+}
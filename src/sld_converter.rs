@@ -0,0 +1,165 @@
+use std::fs;
+
+use crate::error::*;
+use crate::fpu_emulator::*;
+
+/// Walks the tokens of an sld file in the order min-rt reads them,
+/// so that every value is encoded as the word `in` or `fin` expects.
+struct SldConverter<'a> {
+    tokens: Vec<&'a str>,
+    position: usize,
+    words: Vec<u32>,
+}
+
+impl<'a> SldConverter<'a> {
+    fn new(text: &'a str) -> Self {
+        SldConverter {
+            tokens: text.split_whitespace().collect(),
+            position: 0,
+            words: vec![],
+        }
+    }
+
+    fn next_token(&mut self, expected: &str) -> Result<&'a str, String> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token)
+            }
+            None => Err(format!(
+                "unexpected end of file (expected {} as token {})",
+                expected, self.position
+            )),
+        }
+    }
+
+    fn int(&mut self) -> Result<i32, String> {
+        let token = self.next_token("an integer")?;
+        match token.parse::<i32>() {
+            Ok(value) => {
+                self.words.push(value as u32);
+                Ok(value)
+            }
+            Err(_) => Err(format!(
+                "token {} \"{}\" is not an integer",
+                self.position - 1,
+                token
+            )),
+        }
+    }
+
+    /// The host parses floats as doubles and rounds them to single precision,
+    /// which is not always the same as parsing them as `f32` directly.
+    fn float(&mut self) -> Result<(), String> {
+        let token = self.next_token("a float")?;
+        match token.parse::<f64>() {
+            Ok(value) => {
+                self.words
+                    .push(FloatingPoint::new_f32(value as f32).get_32_bits());
+                Ok(())
+            }
+            Err(_) => Err(format!(
+                "token {} \"{}\" is not a float",
+                self.position - 1,
+                token
+            )),
+        }
+    }
+
+    fn floats(&mut self, n: usize) -> Result<(), String> {
+        for _ in 0..n {
+            self.float()?;
+        }
+        Ok(())
+    }
+
+    /// Reads `-1`-terminated lists of integers until an empty one.
+    fn networks(&mut self) -> Result<(), String> {
+        loop {
+            if self.int()? == -1 {
+                return Ok(());
+            }
+            while self.int()? != -1 {}
+        }
+    }
+
+    fn convert(mut self) -> Result<Vec<u32>, String> {
+        // screen position and rotation
+        self.floats(5)?;
+        // number of lights, light direction and beam
+        self.int()?;
+        self.floats(3)?;
+        // objects
+        while self.int()? != -1 {
+            // form, reflection type, rotation flag
+            self.int()?;
+            self.int()?;
+            let is_rotated = self.int()? != 0;
+            // size, position, invert flag, reflection parameters, color
+            self.floats(12)?;
+            if is_rotated {
+                self.floats(3)?;
+            }
+        }
+        // and-networks, then or-networks
+        self.networks()?;
+        self.networks()?;
+        if self.position != self.tokens.len() {
+            return Err(format!(
+                "{} trailing tokens after or-networks",
+                self.tokens.len() - self.position
+            ));
+        }
+        Ok(self.words)
+    }
+}
+
+/// Converts the text of an sld file to the words sent to the board.
+pub fn convert_sld(text: &str) -> Result<Vec<u32>, String> {
+    SldConverter::new(text).convert()
+}
+
+/// Converts an sld file to the little-endian byte stream sent to the board over UART.
+pub fn convert_sld_file(file_path: &str) -> Result<Vec<u8>, SimulatorError> {
+    let error = |reason: String| SimulatorError::InvalidSldFile {
+        path: file_path.to_string(),
+        reason,
+    };
+    let text = fs::read_to_string(file_path).map_err(|e| error(e.to_string()))?;
+    let words = convert_sld(&text).map_err(error)?;
+    Ok(words.iter().flat_map(|word| word.to_le_bytes()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: u32 = 0x3f800000;
+
+    #[test]
+    fn test_convert_sld() {
+        let text = "0 0 0 0 30\n1 0 0\n255\n\
+                    0 1 2 1 40 10 40 0 -40 0 1 0.2 64 255 255 0 1 2 3\n\
+                    -1\n0 -1\n-1\n99 0 -1\n-1\n";
+        let words = convert_sld(text).unwrap();
+        assert_eq!(words.len(), 36);
+        assert_eq!(words[4], 0x41f00000);
+        assert_eq!(words[5], 1);
+        assert_eq!(words[9], 0);
+        assert_eq!(words[11], 2);
+        assert_eq!(words[19], ONE);
+        assert_eq!(words[20], 0x3e4ccccd);
+        assert_eq!(words[27], 0x40400000);
+        assert_eq!(
+            words[28..],
+            [u32::MAX, 0, u32::MAX, u32::MAX, 99, 0, u32::MAX, u32::MAX][..]
+        );
+    }
+
+    #[test]
+    fn test_convert_sld_error() {
+        assert!(convert_sld("0 0 0 0 30 1 0 0").is_err());
+        assert!(convert_sld("0 0 0 0 30 1.5 0 0 255 -1 -1 -1").is_err());
+        assert!(convert_sld("0 0 0 0 30 1 0 0 255 -1 -1 -1 0").is_err());
+    }
+}
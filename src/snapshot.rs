@@ -0,0 +1,170 @@
+use std::fs;
+
+use crate::error::*;
+
+const MAGIC: &[u8; 8] = b"CPUEXSNP";
+const VERSION: u32 = 12;
+
+/// Little-endian encoder for snapshot files.
+pub struct SnapshotWriter {
+    bytes: Vec<u8>,
+}
+
+/// Decoder for the data written by `SnapshotWriter`; every getter fails on truncated data.
+pub struct SnapshotReader {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        let mut writer = SnapshotWriter { bytes: vec![] };
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.put_u32(VERSION);
+        writer
+    }
+
+    pub fn put_bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_usize(&mut self, value: usize) {
+        self.put_u64(value as u64);
+    }
+
+    pub fn put_u128(&mut self, value: u128) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_option_usize(&mut self, value: Option<usize>) {
+        self.put_u64(value.map_or(u64::MAX, |value| value as u64));
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_usize(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn write_to_file(&self, file_path: &str) -> Result<(), SimulatorError> {
+        fs::write(file_path, &self.bytes).map_err(|e| SimulatorError::InvalidSnapshot {
+            path: file_path.to_string(),
+            reason: e.to_string(),
+        })
+    }
+}
+
+impl SnapshotReader {
+    pub fn new(bytes: Vec<u8>) -> Result<Self, String> {
+        let mut reader = SnapshotReader { bytes, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("not a snapshot file".to_string());
+        }
+        let version = reader.get_u32()?;
+        if version != VERSION {
+            return Err(format!("unsupported snapshot version {}", version));
+        }
+        Ok(reader)
+    }
+
+    pub fn from_file(file_path: &str) -> Result<Self, SimulatorError> {
+        let error = |reason: String| SimulatorError::InvalidSnapshot {
+            path: file_path.to_string(),
+            reason,
+        };
+        let bytes = fs::read(file_path).map_err(|e| error(e.to_string()))?;
+        Self::new(bytes).map_err(error)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        if self.bytes.len() - self.position < len {
+            return Err("unexpected end of snapshot".to_string());
+        }
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool, String> {
+        Ok(self.take(1)?[0] != 0)
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn get_usize(&mut self) -> Result<usize, String> {
+        Ok(self.get_u64()? as usize)
+    }
+
+    pub fn get_u128(&mut self) -> Result<u128, String> {
+        Ok(u128::from_le_bytes(self.take(16)?.try_into().unwrap()))
+    }
+
+    pub fn get_option_usize(&mut self) -> Result<Option<usize>, String> {
+        let value = self.get_u64()?;
+        Ok(if value == u64::MAX {
+            None
+        } else {
+            Some(value as usize)
+        })
+    }
+
+    pub fn get_bytes(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.get_usize()?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Checks a value recorded at save time against the current one, e.g. a configuration parameter.
+    pub fn expect_usize(&mut self, name: &str, expected: usize) -> Result<(), String> {
+        let value = self.get_usize()?;
+        if value != expected {
+            return Err(format!(
+                "{} mismatch (snapshot: {}, current: {})",
+                name, value, expected
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = SnapshotWriter::new();
+        writer.put_bool(true);
+        writer.put_u32(0xdeadbeef);
+        writer.put_u128(1 << 100);
+        writer.put_option_usize(None);
+        writer.put_option_usize(Some(33));
+        writer.put_bytes(b"P3");
+        let mut reader = SnapshotReader::new(writer.bytes.clone()).unwrap();
+        assert_eq!(reader.get_bool(), Ok(true));
+        assert_eq!(reader.get_u32(), Ok(0xdeadbeef));
+        assert_eq!(reader.get_u128(), Ok(1 << 100));
+        assert_eq!(reader.get_option_usize(), Ok(None));
+        assert_eq!(reader.get_option_usize(), Ok(Some(33)));
+        assert_eq!(reader.get_bytes(), Ok(b"P3".to_vec()));
+        assert!(reader.is_at_end());
+        assert!(reader.get_u32().is_err());
+        assert!(SnapshotReader::new(b"CPUEXSN".to_vec()).is_err());
+    }
+}
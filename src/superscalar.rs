@@ -0,0 +1,312 @@
+use serde::Deserialize;
+
+use crate::pipeline::*;
+use crate::snapshot::*;
+
+const UNIT_NUM: usize = 3;
+/// Operations the FPU and the load/store unit can start per cycle; only the ALUs are replicated.
+const FPU_NUM: usize = 1;
+const LSU_NUM: usize = 1;
+
+/// Width and functional units of an in-order superscalar core.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SuperscalarConfig {
+    /// Instructions fetched, issued and retired per cycle.
+    pub issue_width: usize,
+    /// Integer ALUs, which also resolve branches and jumps.
+    pub alu_num: usize,
+    /// Whether a branch or jump is the last instruction issued in its cycle.
+    pub branch_ends_group: bool,
+}
+
+impl Default for SuperscalarConfig {
+    fn default() -> Self {
+        SuperscalarConfig {
+            issue_width: 2,
+            alu_num: 2,
+            branch_ends_group: true,
+        }
+    }
+}
+
+impl SuperscalarConfig {
+    /// The single-issue core the pipeline model times.
+    pub fn scalar() -> Self {
+        SuperscalarConfig {
+            issue_width: 1,
+            alu_num: 1,
+            branch_ends_group: true,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.issue_width == 0 {
+            return Err("superscalar issue width must be at least 1".to_string());
+        }
+        if self.alu_num == 0 || self.alu_num > self.issue_width {
+            return Err(format!(
+                "superscalar alu number must be between 1 and the issue width ({})",
+                self.issue_width
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FunctionalUnit {
+    Alu,
+    Fpu,
+    Lsu,
+}
+
+fn get_unit(inst: &IssuedInstruction) -> FunctionalUnit {
+    if inst.is_fpu {
+        FunctionalUnit::Fpu
+    } else if inst.is_memory {
+        FunctionalUnit::Lsu
+    } else {
+        FunctionalUnit::Alu
+    }
+}
+
+/// Why an instruction cannot join the current issue group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueConflict {
+    /// Every slot of the group is taken.
+    Full,
+    /// The group ends with a branch or jump.
+    Branch,
+    /// Every unit of the kind the instruction needs is taken.
+    Unit(usize),
+}
+
+/// Instructions entering the execute stage in the same cycle.
+pub struct IssueGroup {
+    config: SuperscalarConfig,
+    cycle: Option<u128>,
+    size: usize,
+    unit_counts: [usize; UNIT_NUM],
+    is_closed: bool,
+    /// Number of groups of each size, the current one excluded.
+    size_counts: Vec<u128>,
+    /// Instructions issued a cycle late with slots left, by unit, then for following a branch.
+    split_counts: [u128; UNIT_NUM + 1],
+}
+
+impl IssueGroup {
+    /// The config is assumed to have passed `SuperscalarConfig::validate`.
+    pub fn new(config: SuperscalarConfig) -> Self {
+        IssueGroup {
+            config,
+            cycle: None,
+            size: 0,
+            unit_counts: [0; UNIT_NUM],
+            is_closed: false,
+            size_counts: vec![0; config.issue_width + 1],
+            split_counts: [0; UNIT_NUM + 1],
+        }
+    }
+
+    pub fn get_config(&self) -> SuperscalarConfig {
+        self.config
+    }
+
+    /// Whether the instruction after the last one has to issue in a later cycle.
+    pub fn is_full(&self) -> bool {
+        self.is_closed || self.size == self.config.issue_width
+    }
+
+    fn get_unit_num(&self, unit: FunctionalUnit) -> usize {
+        match unit {
+            FunctionalUnit::Alu => self.config.alu_num,
+            FunctionalUnit::Fpu => FPU_NUM,
+            FunctionalUnit::Lsu => LSU_NUM,
+        }
+    }
+
+    pub fn get_conflict(&self, inst: &IssuedInstruction) -> Option<IssueConflict> {
+        let unit = get_unit(inst);
+        if self.size == self.config.issue_width {
+            Some(IssueConflict::Full)
+        } else if self.is_closed {
+            Some(IssueConflict::Branch)
+        } else if self.unit_counts[unit as usize] == self.get_unit_num(unit) {
+            Some(IssueConflict::Unit(unit as usize))
+        } else {
+            None
+        }
+    }
+
+    /// Earliest cycle the instruction can issue at without passing the previous one.
+    pub fn get_earliest_cycle(&self, inst: &IssuedInstruction) -> u128 {
+        match self.cycle {
+            Some(cycle) if self.get_conflict(inst).is_some() => cycle + 1,
+            Some(cycle) => cycle,
+            None => 0,
+        }
+    }
+
+    /// Adds the instruction issued at `cycle`, which is not earlier than `get_earliest_cycle`.
+    pub fn add(&mut self, inst: &IssuedInstruction, cycle: u128) {
+        if self.cycle != Some(cycle) {
+            if let Some(last_cycle) = self.cycle {
+                match self.get_conflict(inst) {
+                    Some(IssueConflict::Branch) if cycle == last_cycle + 1 => {
+                        self.split_counts[UNIT_NUM] += 1;
+                    }
+                    Some(IssueConflict::Unit(unit)) if cycle == last_cycle + 1 => {
+                        self.split_counts[unit] += 1;
+                    }
+                    _ => {}
+                }
+                self.size_counts[self.size] += 1;
+            }
+            self.cycle = Some(cycle);
+            self.size = 0;
+            self.unit_counts = [0; UNIT_NUM];
+            self.is_closed = false;
+        }
+        self.size += 1;
+        self.unit_counts[get_unit(inst) as usize] += 1;
+        self.is_closed = self.config.branch_ends_group && inst.is_control_transfer;
+    }
+
+    pub fn show(&self) {
+        let config = &self.config;
+        println!(
+            "superscalar: {}-wide, {} alus, {} fpu, {} lsu, {}",
+            config.issue_width,
+            config.alu_num,
+            FPU_NUM,
+            LSU_NUM,
+            if config.branch_ends_group {
+                "branches end issue groups"
+            } else {
+                "issue after branches"
+            }
+        );
+        let mut size_counts = self.size_counts.clone();
+        size_counts[self.size] += self.cycle.is_some() as u128;
+        let group_count = size_counts.iter().sum::<u128>();
+        let sizes = size_counts
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(size, count)| {
+                let share = if group_count == 0 {
+                    0.0
+                } else {
+                    *count as f64 / group_count as f64 * 100.0
+                };
+                format!("{}: {} ({:.5}%)", size, count, share)
+            })
+            .collect::<Vec<_>>();
+        println!("issue group sizes: {}", sizes.join(", "));
+        let splits = &self.split_counts;
+        println!(
+            "issue groups split with free slots: {} (alu: {}, fpu: {}, lsu: {}, branch: {})",
+            splits.iter().sum::<u128>(),
+            splits[FunctionalUnit::Alu as usize],
+            splits[FunctionalUnit::Fpu as usize],
+            splits[FunctionalUnit::Lsu as usize],
+            splits[UNIT_NUM]
+        );
+    }
+
+    pub fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.put_usize(self.config.issue_width);
+        writer.put_bool(self.cycle.is_some());
+        writer.put_u128(self.cycle.unwrap_or(0));
+        writer.put_usize(self.size);
+        for count in self.unit_counts.iter() {
+            writer.put_usize(*count);
+        }
+        writer.put_bool(self.is_closed);
+        for count in self.size_counts.iter().chain(self.split_counts.iter()) {
+            writer.put_u128(*count);
+        }
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        reader.expect_usize("issue width", self.config.issue_width)?;
+        let has_issued = reader.get_bool()?;
+        let cycle = reader.get_u128()?;
+        self.cycle = has_issued.then_some(cycle);
+        self.size = reader.get_usize()?;
+        if self.size > self.config.issue_width {
+            return Err(format!("issue group size {} out of range", self.size));
+        }
+        for count in self.unit_counts.iter_mut() {
+            *count = reader.get_usize()?;
+        }
+        self.is_closed = reader.get_bool()?;
+        for count in self
+            .size_counts
+            .iter_mut()
+            .chain(self.split_counts.iter_mut())
+        {
+            *count = reader.get_u128()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fpu_scoreboard::*;
+    use crate::pipeline::tests::inst;
+
+    #[test]
+    fn test_superscalar_config_validate() {
+        assert!(SuperscalarConfig {
+            issue_width: 4,
+            ..SuperscalarConfig::default()
+        }
+        .validate()
+        .is_ok());
+        assert!(SuperscalarConfig {
+            alu_num: 3,
+            ..SuperscalarConfig::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_superscalar() {
+        let lw = IssuedInstruction {
+            is_load: true,
+            is_memory: true,
+            ..inst([Some(2), None], Some(1))
+        };
+        let sw = IssuedInstruction {
+            is_memory: true,
+            ..inst([Some(2), Some(4)], None)
+        };
+        let addi = inst([Some(5), None], Some(5));
+        let add = inst([Some(6), Some(7)], Some(8));
+        let bne = IssuedInstruction {
+            is_control_transfer: true,
+            ..inst([Some(6), Some(7)], None)
+        };
+        let mut pipeline = Pipeline::new(
+            PipelineConfig::default(),
+            SuperscalarConfig::default(),
+            FpuConfig::default(),
+        );
+        // {lw}, {sw, addi}: the second access waits for the lsu
+        // {bne}, {add}: a branch ends its group
+        for inst in [lw, sw, addi, bne, add] {
+            pipeline.issue(&inst);
+        }
+        let group = pipeline.get_issue_group();
+        assert_eq!(group.size_counts, vec![0, 2, 1]);
+        assert_eq!(group.split_counts, [0, 0, 1, 1]);
+        assert_eq!(group.size, 1);
+        // 4 issue groups and 4 cycles to fill the pipeline
+        assert_eq!(pipeline.get_cycle_num(), 4 + 4);
+    }
+}
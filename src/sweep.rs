@@ -0,0 +1,105 @@
+use crate::cache::*;
+use crate::cache_hierarchy::*;
+use crate::memory::*;
+use crate::types::*;
+
+/// Simulates many cache configurations at once on the access stream of a single run.
+pub struct CacheSweep {
+    models: Vec<CacheHierarchy>,
+    /// Backing memory shared by every model. The data is never read and only zeros are stored,
+    /// so the models cannot tell each other's writes apart.
+    memory: Memory,
+}
+
+impl CacheSweep {
+    /// Every config is assumed to have passed `CacheConfig::validate`, and the L2 config `L2Config::validate` with each of them.
+    pub fn new(
+        configs: &[CacheConfig],
+        l2_config: Option<L2Config>,
+        stall_config: StallConfig,
+    ) -> Self {
+        CacheSweep {
+            models: configs
+                .iter()
+                .map(|&config| CacheHierarchy::new(config, l2_config, stall_config))
+                .collect(),
+            memory: Memory::new(),
+        }
+    }
+
+    /// `base_cycle_num` is the cycle count of the core without the stalls of the data cache hierarchy,
+    /// which does not depend on the cache.
+    pub fn access(&mut self, addr: Address, is_store: bool, base_cycle_num: u128) {
+        for model in self.models.iter_mut() {
+            model.count_access();
+            if is_store {
+                model.store_word(&mut self.memory, addr, 0, base_cycle_num);
+            } else {
+                model.load_word(&mut self.memory, addr, base_cycle_num);
+            }
+        }
+    }
+
+    /// Predicted cycle count of every model, with `base_cycle_num` as in `access`.
+    pub fn get_cycle_nums(&self, base_cycle_num: u128) -> Vec<u128> {
+        self.models
+            .iter()
+            .map(|model| base_cycle_num + model.get_stall_cycle_num())
+            .collect()
+    }
+
+    pub fn show_results(&self, base_cycle_num: u128) {
+        println!("---------- cache sweep ----------");
+        println!(
+            "{:>8} {:>4} {:>4} {:>6} {:>10} {:>12} {:>12} {:>16}",
+            "size", "ways", "line", "policy", "hit rate", "load miss", "store miss", "cycle count"
+        );
+        let cycle_nums = self.get_cycle_nums(base_cycle_num);
+        for (model, cycle_num) in self.models.iter().zip(cycle_nums) {
+            let config = model.get_config();
+            let stats = model.get_l1_stats();
+            println!(
+                "{:>8} {:>4} {:>4} {:>6} {:>9.5}% {:>12} {:>12} {:>16}",
+                config.size,
+                config.way_num,
+                config.line_size,
+                config.policy,
+                stats.get_hit_rate(),
+                stats.load_cache_miss_count,
+                stats.store_cache_miss_count,
+                cycle_num
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_sweep() {
+        let small = CacheConfig {
+            size: 64,
+            way_num: 1,
+            line_size: 16,
+            ..CacheConfig::default()
+        };
+        let large = CacheConfig { size: 128, ..small };
+        let mut sweep = CacheSweep::new(&[small, large], None, StallConfig::default());
+        // 0x00 and 0x40 share a set only in the small cache
+        for _ in 0..2 {
+            sweep.access(0x00, false, 0);
+            sweep.access(0x44, true, 0);
+        }
+        let counts = sweep
+            .models
+            .iter()
+            .map(|model| {
+                let stats = model.get_l1_stats();
+                (stats.cache_hit_count, stats.load_cache_miss_count)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [(0, 2), (2, 1)]);
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+use crate::error::*;
+use crate::instruction::*;
+use crate::register::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceFormat {
+    /// One line of `key=value` fields per instruction.
+    Text,
+    /// One JSON object per line.
+    Jsonl,
+    /// Fixed-size little-endian records of 32 bytes.
+    Binary,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterWrite {
+    pub register: RegisterId,
+    pub value: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: Address,
+    pub value: u32,
+    pub is_store: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub count: InstructionCount,
+    pub pc: Address,
+    pub inst: InstructionValue,
+    pub inst_id: InstructionId,
+    pub reg_write: Option<RegisterWrite>,
+    pub mem_access: Option<MemoryAccess>,
+}
+
+const RECORD_SIZE: usize = 32;
+const FLAG_REG_WRITE: u8 = 1;
+const FLAG_FLOAT_REG: u8 = 1 << 1;
+const FLAG_MEM_ACCESS: u8 = 1 << 2;
+const FLAG_STORE: u8 = 1 << 3;
+
+impl TraceRecord {
+    pub fn to_text(self, inst_name: &str) -> String {
+        let mut text = format!(
+            "n={} pc={:>08x} inst={:>08x} op={}",
+            self.count, self.pc, self.inst, inst_name
+        );
+        if let Some(reg_write) = self.reg_write {
+            text += &format!(" rd={}:{:>08x}", reg_write.register, reg_write.value);
+        }
+        if let Some(mem_access) = self.mem_access {
+            text += &format!(
+                " {}={:>08x}:{:>08x}",
+                if mem_access.is_store {
+                    "mem_w"
+                } else {
+                    "mem_r"
+                },
+                mem_access.addr,
+                mem_access.value
+            );
+        }
+        text
+    }
+
+    pub fn to_json(self, inst_name: &str) -> String {
+        let mut json = format!(
+            "{{\"n\":{},\"pc\":{},\"inst\":{},\"op\":\"{}\"",
+            self.count, self.pc, self.inst, inst_name
+        );
+        if let Some(reg_write) = self.reg_write {
+            json += &format!(
+                ",\"rd\":\"{}\",\"rd_value\":{}",
+                reg_write.register, reg_write.value
+            );
+        }
+        if let Some(mem_access) = self.mem_access {
+            json += &format!(
+                ",\"mem_addr\":{},\"mem_value\":{},\"mem_store\":{}",
+                mem_access.addr, mem_access.value, mem_access.is_store
+            );
+        }
+        json + "}"
+    }
+
+    /// Layout: count (u64), pc (u32), inst (u32), inst_id (u8), flags (u8), rd (u8),
+    /// reserved (u8), rd_value (u32), mem_addr (u32), mem_value (u32), reserved (u32).
+    pub fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut flags = 0;
+        let mut rd = 0;
+        let mut rd_value = 0;
+        let mut mem_addr = 0;
+        let mut mem_value = 0;
+        if let Some(reg_write) = self.reg_write {
+            flags |= FLAG_REG_WRITE;
+            rd = match reg_write.register {
+                RegisterId::Int(index) => index as u8,
+                RegisterId::Float(index) => {
+                    flags |= FLAG_FLOAT_REG;
+                    index as u8
+                }
+            };
+            rd_value = reg_write.value;
+        }
+        if let Some(mem_access) = self.mem_access {
+            flags |= FLAG_MEM_ACCESS;
+            if mem_access.is_store {
+                flags |= FLAG_STORE;
+            }
+            mem_addr = mem_access.addr;
+            mem_value = mem_access.value;
+        }
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&(self.count as u64).to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.inst.to_le_bytes());
+        bytes[16] = self.inst_id as u8;
+        bytes[17] = flags;
+        bytes[18] = rd;
+        bytes[20..24].copy_from_slice(&rd_value.to_le_bytes());
+        bytes[24..28].copy_from_slice(&mem_addr.to_le_bytes());
+        bytes[28..32].copy_from_slice(&mem_value.to_le_bytes());
+        bytes
+    }
+
+    /// `n` and `op` are optional so that traces from other tools (e.g. the Verilog testbench)
+    /// only need to provide `pc`, `inst`, `rd` and `mem_r`/`mem_w`.
+    pub fn from_text(line: &str) -> Result<Self, String> {
+        let mut fields = HashMap::new();
+        for field in line.split_whitespace() {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or(format!("invalid field: {}", field))?;
+            fields.insert(key, value);
+        }
+        let hex = |s: &str| u32::from_str_radix(s, 16).map_err(|e| format!("{}: {}", s, e));
+        let pair = |s: &str| -> Result<(String, u32), String> {
+            let (left, right) = s.split_once(':').ok_or(format!("invalid pair: {}", s))?;
+            Ok((left.to_string(), hex(right)?))
+        };
+        let count = match fields.get("n") {
+            Some(n) => n.parse().map_err(|e| format!("{}: {}", n, e))?,
+            None => 0,
+        };
+        let pc = hex(fields.get("pc").ok_or("missing pc")?)?;
+        let inst = hex(fields.get("inst").ok_or("missing inst")?)?;
+        let reg_write = match fields.get("rd") {
+            Some(rd) => {
+                let (register, value) = pair(rd)?;
+                Some(RegisterWrite {
+                    register: parse_register_id(&register)?,
+                    value,
+                })
+            }
+            None => None,
+        };
+        let mem_access = match (fields.get("mem_r"), fields.get("mem_w")) {
+            (Some(mem), None) | (None, Some(mem)) => {
+                let (addr, value) = pair(mem)?;
+                Some(MemoryAccess {
+                    addr: hex(&addr)?,
+                    value,
+                    is_store: fields.contains_key("mem_w"),
+                })
+            }
+            (None, None) => None,
+            _ => return Err("both mem_r and mem_w are given".to_string()),
+        };
+        Ok(TraceRecord {
+            count,
+            pc,
+            inst,
+            inst_id: 0,
+            reg_write,
+            mem_access,
+        })
+    }
+
+    pub fn from_json(line: &str) -> Result<Self, String> {
+        let fields = parse_flat_json(line)?;
+        let number = |key: &str| -> Result<Option<u32>, String> {
+            match fields.get(key) {
+                Some(value) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|e| format!("{}: {}", key, e)),
+                None => Ok(None),
+            }
+        };
+        let count = match fields.get("n") {
+            Some(n) => n.parse().map_err(|e| format!("n: {}", e))?,
+            None => 0,
+        };
+        let pc = number("pc")?.ok_or("missing pc")?;
+        let inst = number("inst")?.ok_or("missing inst")?;
+        let reg_write = match (fields.get("rd"), number("rd_value")?) {
+            (Some(rd), Some(value)) => Some(RegisterWrite {
+                register: parse_register_id(rd)?,
+                value,
+            }),
+            (None, None) => None,
+            _ => return Err("rd and rd_value must be given together".to_string()),
+        };
+        let mem_access = match (number("mem_addr")?, number("mem_value")?) {
+            (Some(addr), Some(value)) => Some(MemoryAccess {
+                addr,
+                value,
+                is_store: fields.get("mem_store").map(String::as_str) == Some("true"),
+            }),
+            (None, None) => None,
+            _ => return Err("mem_addr and mem_value must be given together".to_string()),
+        };
+        Ok(TraceRecord {
+            count,
+            pc,
+            inst,
+            inst_id: 0,
+            reg_write,
+            mem_access,
+        })
+    }
+
+    pub fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Self {
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let flags = bytes[17];
+        let reg_write = if flags & FLAG_REG_WRITE != 0 {
+            let index = bytes[18] as usize;
+            Some(RegisterWrite {
+                register: if flags & FLAG_FLOAT_REG != 0 {
+                    RegisterId::Float(index)
+                } else {
+                    RegisterId::Int(index)
+                },
+                value: u32_at(20),
+            })
+        } else {
+            None
+        };
+        let mem_access = if flags & FLAG_MEM_ACCESS != 0 {
+            Some(MemoryAccess {
+                addr: u32_at(24),
+                value: u32_at(28),
+                is_store: flags & FLAG_STORE != 0,
+            })
+        } else {
+            None
+        };
+        TraceRecord {
+            count: u64::from_le_bytes(bytes[0..8].try_into().unwrap()) as InstructionCount,
+            pc: u32_at(8),
+            inst: u32_at(12),
+            inst_id: bytes[16] as InstructionId,
+            reg_write,
+            mem_access,
+        }
+    }
+}
+
+fn parse_register_id(s: &str) -> Result<RegisterId, String> {
+    let index = |s: &str| match s.parse::<usize>() {
+        Ok(index) if index < 32 => Ok(index),
+        _ => Err(format!("invalid register: {}", s)),
+    };
+    if let Some(rest) = s.strip_prefix('x') {
+        Ok(RegisterId::Int(index(rest)?))
+    } else if let Some(rest) = s.strip_prefix('f') {
+        Ok(RegisterId::Float(index(rest)?))
+    } else {
+        Err(format!("invalid register: {}", s))
+    }
+}
+
+/// Parses a JSON object whose values are numbers, booleans or strings without escapes,
+/// which is all the trace format needs.
+fn parse_flat_json(line: &str) -> Result<HashMap<String, String>, String> {
+    let body = line
+        .trim()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or(format!("invalid JSON object: {}", line))?;
+    let mut fields = HashMap::new();
+    for field in body.split(',').filter(|field| !field.trim().is_empty()) {
+        let (key, value) = field
+            .split_once(':')
+            .ok_or(format!("invalid JSON field: {}", field))?;
+        let key = key.trim().trim_matches('"').to_string();
+        let value = value.trim().trim_matches('"').to_string();
+        fields.insert(key, value);
+    }
+    Ok(fields)
+}
+
+pub struct Tracer {
+    file_path: String,
+    writer: BufWriter<File>,
+    format: TraceFormat,
+    from: InstructionCount,
+    to: Option<InstructionCount>,
+    inst_id_to_name_map: HashMap<InstructionId, String>,
+}
+
+impl Tracer {
+    pub fn new(
+        file_path: &str,
+        format: TraceFormat,
+        from: InstructionCount,
+        to: Option<InstructionCount>,
+    ) -> Result<Self, SimulatorError> {
+        let file = File::create(file_path).map_err(|e| SimulatorError::DeviceUnavailable {
+            path: file_path.to_string(),
+            reason: e.to_string(),
+        })?;
+        Ok(Tracer {
+            file_path: file_path.to_string(),
+            writer: BufWriter::new(file),
+            format,
+            from,
+            to,
+            inst_id_to_name_map: create_inst_id_to_name_map(),
+        })
+    }
+
+    fn map_error(&self, e: io::Error) -> SimulatorError {
+        SimulatorError::DeviceUnavailable {
+            path: self.file_path.clone(),
+            reason: e.to_string(),
+        }
+    }
+
+    pub fn is_in_window(&self, count: InstructionCount) -> bool {
+        count >= self.from && self.to.is_none_or(|to| count < to)
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> Result<(), SimulatorError> {
+        let inst_name = self.inst_id_to_name_map.get(&record.inst_id).unwrap();
+        let result = match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", record.to_text(inst_name)),
+            TraceFormat::Jsonl => writeln!(self.writer, "{}", record.to_json(inst_name)),
+            TraceFormat::Binary => self.writer.write_all(&record.to_bytes()),
+        };
+        result.map_err(|e| self.map_error(e))
+    }
+
+    pub fn flush(&mut self) -> Result<(), SimulatorError> {
+        self.writer.flush().map_err(|e| self.map_error(e))
+    }
+}
+
+pub struct TraceReader {
+    reader: BufReader<File>,
+    format: TraceFormat,
+    line_number: usize,
+    /// Bytes of a binary trace read so far.
+    position: usize,
+}
+
+impl TraceReader {
+    pub fn new(file_path: &str, format: TraceFormat) -> Result<Self, SimulatorError> {
+        let file = File::open(file_path).map_err(|e| SimulatorError::DeviceUnavailable {
+            path: file_path.to_string(),
+            reason: e.to_string(),
+        })?;
+        Ok(TraceReader {
+            reader: BufReader::new(file),
+            format,
+            line_number: 0,
+            position: 0,
+        })
+    }
+
+    /// Returns `None` at the end of the trace, which in a binary trace has to fall on a record boundary.
+    pub fn next_record(&mut self) -> Option<Result<TraceRecord, String>> {
+        if self.format == TraceFormat::Binary {
+            return self.next_binary_record();
+        }
+        loop {
+            let mut line = String::new();
+            self.line_number += 1;
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.to_string())),
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = match self.format {
+                TraceFormat::Text => TraceRecord::from_text(&line),
+                _ => TraceRecord::from_json(&line),
+            };
+            return Some(record.map_err(|e| format!("line {}: {}", self.line_number, e)));
+        }
+    }
+
+    fn next_binary_record(&mut self) -> Option<Result<TraceRecord, String>> {
+        let mut bytes = [0; RECORD_SIZE];
+        let mut len = 0;
+        while len < RECORD_SIZE {
+            match self.reader.read(&mut bytes[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e.to_string())),
+            }
+        }
+        let position = self.position;
+        self.position += len;
+        match len {
+            0 => None,
+            RECORD_SIZE => Some(Ok(TraceRecord::from_bytes(&bytes))),
+            _ => Some(Err(format!("truncated record at byte {}", position))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_records() -> Vec<TraceRecord> {
+        vec![
+            TraceRecord {
+                count: 7,
+                pc: 0x1c,
+                inst: 0xff812403,
+                inst_id: 0,
+                reg_write: Some(RegisterWrite {
+                    register: RegisterId::Int(8),
+                    value: 0xfffffffe,
+                }),
+                mem_access: Some(MemoryAccess {
+                    addr: 0x7fffff8,
+                    value: 0xfffffffe,
+                    is_store: false,
+                }),
+            },
+            TraceRecord {
+                count: 8,
+                pc: 0x50,
+                inst: 0xfe212827,
+                inst_id: 22,
+                reg_write: None,
+                mem_access: Some(MemoryAccess {
+                    addr: 0x7fffff0,
+                    value: 0x40400000,
+                    is_store: true,
+                }),
+            },
+            TraceRecord {
+                count: 9,
+                pc: 0x54,
+                inst: 0x002081d3,
+                inst_id: 9,
+                reg_write: Some(RegisterWrite {
+                    register: RegisterId::Float(3),
+                    value: 0x40400000,
+                }),
+                mem_access: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        for record in sample_records() {
+            let parsed = TraceRecord::from_text(&record.to_text("op")).unwrap();
+            assert_eq!(
+                parsed,
+                TraceRecord {
+                    inst_id: 0,
+                    ..record
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        for record in sample_records() {
+            let parsed = TraceRecord::from_json(&record.to_json("op")).unwrap();
+            assert_eq!(
+                parsed,
+                TraceRecord {
+                    inst_id: 0,
+                    ..record
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        for record in sample_records() {
+            assert_eq!(TraceRecord::from_bytes(&record.to_bytes()), record);
+        }
+    }
+
+    #[test]
+    fn test_truncated_binary_trace() {
+        let file_path = std::env::temp_dir().join("instruction-simulator-test.trace");
+        let file_path = file_path.to_str().unwrap();
+        let records = sample_records();
+        let mut bytes = records[0].to_bytes().to_vec();
+        bytes.extend_from_slice(&records[1].to_bytes()[..RECORD_SIZE - 1]);
+        std::fs::write(file_path, bytes).unwrap();
+        let mut reader = TraceReader::new(file_path, TraceFormat::Binary).unwrap();
+        std::fs::remove_file(file_path).unwrap();
+        assert_eq!(reader.next_record(), Some(Ok(records[0])));
+        assert_eq!(
+            reader.next_record(),
+            Some(Err("truncated record at byte 32".to_string()))
+        );
+        assert_eq!(reader.next_record(), None);
+    }
+
+    #[test]
+    fn test_text_without_optional_fields() {
+        let record = TraceRecord::from_text("pc=00000010 inst=0053dc63").unwrap();
+        assert_eq!(record.pc, 0x10);
+        assert_eq!(record.reg_write, None);
+        assert!(TraceRecord::from_text("pc=00000010").is_err());
+        assert!(TraceRecord::from_text("pc=00000010 inst=0053dc63 rd=x32:0").is_err());
+    }
+}
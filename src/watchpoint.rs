@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+
+use crate::fpu_emulator::*;
+use crate::instruction::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchTarget {
+    Memory(Address, WatchKind),
+    IntRegister(usize),
+    FloatRegister(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchCondition {
+    Always,
+    Changed,
+    Nan,
+    Inf,
+    Equal(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchAction {
+    Stop,
+    Log,
+}
+
+pub struct Watchpoint {
+    id: usize,
+    target: WatchTarget,
+    condition: WatchCondition,
+    action: WatchAction,
+}
+
+pub struct WatchHit {
+    id: usize,
+    pc: Address,
+    target: WatchTarget,
+    is_write: bool,
+    old_value: u32,
+    new_value: u32,
+    action: WatchAction,
+}
+
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    hits: Vec<WatchHit>,
+    next_id: usize,
+    inst_id_to_name_map: HashMap<InstructionId, String>,
+}
+
+impl WatchCondition {
+    /// Conditions on a write fire when the value starts to satisfy them,
+    /// so e.g. `nan` reports the instruction that produced the first NaN.
+    fn is_satisfied(&self, old_value: u32, new_value: u32, is_write: bool) -> bool {
+        let satisfied = |value: u32| match self {
+            WatchCondition::Always => true,
+            WatchCondition::Changed => old_value != new_value,
+            WatchCondition::Nan => FloatingPoint::new(value).is_nan(),
+            WatchCondition::Inf => FloatingPoint::new(value).is_infinite(),
+            WatchCondition::Equal(expected) => value == *expected,
+        };
+        match self {
+            WatchCondition::Always | WatchCondition::Changed => satisfied(new_value),
+            _ => satisfied(new_value) && !(is_write && satisfied(old_value)),
+        }
+    }
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            watchpoints: vec![],
+            hits: vec![],
+            next_id: 1,
+            inst_id_to_name_map: create_inst_id_to_name_map(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    pub fn add(
+        &mut self,
+        target: WatchTarget,
+        condition: WatchCondition,
+        action: WatchAction,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint {
+            id,
+            target,
+            condition,
+            action,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let before_len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        self.watchpoints.len() != before_len
+    }
+
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn show(&self) {
+        if self.watchpoints.is_empty() {
+            println!("No watchpoints.");
+        }
+        for watchpoint in &self.watchpoints {
+            println!(
+                "{: <4} {: <8} {} {}",
+                watchpoint.id,
+                match watchpoint.action {
+                    WatchAction::Stop => "stop",
+                    WatchAction::Log => "log",
+                },
+                describe_target(watchpoint.target),
+                describe_condition(watchpoint.condition)
+            );
+        }
+    }
+
+    fn check(
+        &mut self,
+        pc: Address,
+        matches: impl Fn(WatchTarget) -> bool,
+        is_write: bool,
+        old_value: u32,
+        new_value: u32,
+    ) {
+        for watchpoint in &self.watchpoints {
+            if matches(watchpoint.target)
+                && watchpoint
+                    .condition
+                    .is_satisfied(old_value, new_value, is_write)
+            {
+                self.hits.push(WatchHit {
+                    id: watchpoint.id,
+                    pc,
+                    target: watchpoint.target,
+                    is_write,
+                    old_value,
+                    new_value,
+                    action: watchpoint.action,
+                });
+            }
+        }
+    }
+
+    pub fn check_memory_read(&mut self, pc: Address, addr: Address, value: u32) {
+        let matches = |target| matches!(target, WatchTarget::Memory(a, kind) if a == addr && kind != WatchKind::Write);
+        self.check(pc, matches, false, value, value);
+    }
+
+    pub fn check_memory_write(&mut self, pc: Address, addr: Address, old_value: u32, value: u32) {
+        let matches = |target| matches!(target, WatchTarget::Memory(a, kind) if a == addr && kind != WatchKind::Read);
+        self.check(pc, matches, true, old_value, value);
+    }
+
+    pub fn check_int_register(&mut self, pc: Address, index: usize, old_value: u32, value: u32) {
+        let matches = |target| target == WatchTarget::IntRegister(index);
+        self.check(pc, matches, true, old_value, value);
+    }
+
+    pub fn check_float_register(&mut self, pc: Address, index: usize, old_value: u32, value: u32) {
+        let matches = |target| target == WatchTarget::FloatRegister(index);
+        self.check(pc, matches, true, old_value, value);
+    }
+
+    /// Prints the hits of the last executed instruction and returns whether any of them should stop the execution.
+    pub fn report_hits(&mut self, inst_id: InstructionId) -> bool {
+        let mut stop = false;
+        let inst_name = self.inst_id_to_name_map.get(&inst_id).unwrap();
+        for hit in &self.hits {
+            let values = if hit.is_write {
+                format!("0x{:>08x} -> 0x{:>08x}", hit.old_value, hit.new_value)
+            } else {
+                format!("0x{:>08x}", hit.new_value)
+            };
+            println!(
+                "Watchpoint {} ({} {}) at 0x{:>08x} ({}): {}",
+                hit.id,
+                if hit.is_write { "write" } else { "read" },
+                describe_target(hit.target),
+                hit.pc,
+                inst_name,
+                values
+            );
+            stop |= hit.action == WatchAction::Stop;
+        }
+        self.hits.clear();
+        stop
+    }
+
+    pub fn has_hits(&self) -> bool {
+        !self.hits.is_empty()
+    }
+}
+
+impl Default for Watchpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn describe_target(target: WatchTarget) -> String {
+    match target {
+        WatchTarget::Memory(addr, kind) => {
+            let kind = match kind {
+                WatchKind::Read => "r",
+                WatchKind::Write => "w",
+                WatchKind::Access => "a",
+            };
+            format!("{}:0x{:>08x}", kind, addr)
+        }
+        WatchTarget::IntRegister(index) => format!("x{}", index),
+        WatchTarget::FloatRegister(index) => format!("f{}", index),
+    }
+}
+
+fn describe_condition(condition: WatchCondition) -> String {
+    match condition {
+        WatchCondition::Always => "".to_string(),
+        WatchCondition::Changed => "if changed".to_string(),
+        WatchCondition::Nan => "if nan".to_string(),
+        WatchCondition::Inf => "if inf".to_string(),
+        WatchCondition::Equal(value) => format!("if == 0x{:>08x}", value),
+    }
+}
+
+fn parse_value(s: &str) -> Option<u32> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Ok(value) = s.parse::<i32>() {
+        Some(value as u32)
+    } else {
+        s.parse::<u32>().ok()
+    }
+}
+
+fn parse_register(s: &str, prefix: char) -> Option<usize> {
+    let index = s.strip_prefix(prefix)?.parse::<usize>().ok()?;
+    if index < 32 {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+/// Parses `<target> [if <condition>]`, where target is `x<n>`, `f<n>` or a memory address
+/// and condition is one of `changed`, `nan`, `inf` or `== <value>`.
+pub fn parse_watch_spec(
+    spec: &str,
+    kind: WatchKind,
+) -> Result<(WatchTarget, WatchCondition), String> {
+    let (target, condition) = match spec.split_once(" if ") {
+        Some((target, condition)) => (target.trim(), Some(condition.trim())),
+        None => (spec.trim(), None),
+    };
+    let target = if let Some(index) = parse_register(target, 'x') {
+        if kind != WatchKind::Write {
+            return Err("only write watchpoints are supported on registers".to_string());
+        }
+        WatchTarget::IntRegister(index)
+    } else if let Some(index) = parse_register(target, 'f') {
+        if kind != WatchKind::Write {
+            return Err("only write watchpoints are supported on registers".to_string());
+        }
+        WatchTarget::FloatRegister(index)
+    } else {
+        match parse_value(target) {
+            Some(addr) if addr % 4 == 0 => WatchTarget::Memory(addr, kind),
+            _ => return Err(format!("invalid watch target: {}", target)),
+        }
+    };
+    let condition = match condition {
+        None => WatchCondition::Always,
+        Some("changed") => WatchCondition::Changed,
+        Some("nan") => WatchCondition::Nan,
+        Some("inf") => WatchCondition::Inf,
+        Some(condition) => match condition.strip_prefix("==").map(|s| parse_value(s.trim())) {
+            Some(Some(value)) => WatchCondition::Equal(value),
+            _ => return Err(format!("invalid watch condition: {}", condition)),
+        },
+    };
+    Ok((target, condition))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAN: u32 = 0x7fc00000;
+    const ONE: u32 = 0x3f800000;
+
+    #[test]
+    fn test_parse_watch_spec() {
+        assert_eq!(
+            parse_watch_spec("f3 if nan", WatchKind::Write),
+            Ok((WatchTarget::FloatRegister(3), WatchCondition::Nan))
+        );
+        assert_eq!(
+            parse_watch_spec("0x100 if == -1", WatchKind::Read),
+            Ok((
+                WatchTarget::Memory(0x100, WatchKind::Read),
+                WatchCondition::Equal(0xffffffff)
+            ))
+        );
+        assert!(parse_watch_spec("x5", WatchKind::Read).is_err());
+        assert!(parse_watch_spec("0x102", WatchKind::Write).is_err());
+        assert!(parse_watch_spec("x32", WatchKind::Write).is_err());
+    }
+
+    #[test]
+    fn test_condition_fires_when_value_becomes_nan() {
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.add(
+            WatchTarget::FloatRegister(1),
+            WatchCondition::Nan,
+            WatchAction::Log,
+        );
+        watchpoints.check_float_register(0, 1, ONE, ONE);
+        assert!(!watchpoints.has_hits());
+        watchpoints.check_float_register(4, 1, ONE, NAN);
+        assert!(watchpoints.has_hits());
+        assert!(!watchpoints.report_hits(0));
+        watchpoints.check_float_register(8, 1, NAN, NAN);
+        assert!(!watchpoints.has_hits());
+        watchpoints.check_float_register(8, 2, ONE, NAN);
+        assert!(!watchpoints.has_hits());
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+
+use crate::snapshot::*;
+
+/// FIFO of pending memory writes, drained one at a time while the core keeps running.
+pub struct WriteBuffer {
+    capacity: usize,
+    /// Cycles taken by memory to complete a single word or line write.
+    write_stall: u128,
+    /// Cycle at which each pending write completes, oldest first.
+    completion_cycles: VecDeque<u128>,
+}
+
+impl WriteBuffer {
+    pub fn new(capacity: usize, write_stall: u128) -> Self {
+        WriteBuffer {
+            capacity,
+            write_stall,
+            completion_cycles: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Issues a memory write at cycle `now` and returns the number of cycles the core stalls for it,
+    /// which is `unbuffered_stall` when there is no buffer.
+    pub fn push(&mut self, now: u128, unbuffered_stall: u128) -> u128 {
+        if self.capacity == 0 {
+            return unbuffered_stall;
+        }
+        while self
+            .completion_cycles
+            .front()
+            .is_some_and(|&cycle| cycle <= now)
+        {
+            self.completion_cycles.pop_front();
+        }
+        let mut stall = 0;
+        if self.completion_cycles.len() == self.capacity {
+            stall = self.completion_cycles.pop_front().unwrap() - now;
+        }
+        let start = self
+            .completion_cycles
+            .back()
+            .map_or(now + stall, |&cycle| cycle.max(now + stall));
+        self.completion_cycles.push_back(start + self.write_stall);
+        stall
+    }
+
+    pub fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.put_usize(self.completion_cycles.len());
+        for cycle in self.completion_cycles.iter() {
+            writer.put_u128(*cycle);
+        }
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), String> {
+        self.completion_cycles.clear();
+        let len = reader.get_usize()?;
+        if len > self.capacity {
+            return Err(format!(
+                "{} pending writes do not fit in a write buffer of {}",
+                len, self.capacity
+            ));
+        }
+        for _ in 0..len {
+            self.completion_cycles.push_back(reader.get_u128()?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_buffer() {
+        assert_eq!(WriteBuffer::new(0, 100).push(0, 30), 30);
+        let mut buffer = WriteBuffer::new(2, 100);
+        assert_eq!(buffer.push(0, 100), 0);
+        assert_eq!(buffer.push(1, 100), 0);
+        // the first write completes at 100 and the second at 200
+        assert_eq!(buffer.push(10, 100), 90);
+        assert_eq!(buffer.push(250, 100), 0);
+        assert_eq!(buffer.completion_cycles, [300, 400]);
+    }
+}